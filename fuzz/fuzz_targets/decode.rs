@@ -0,0 +1,29 @@
+//! Untrusted bytes through every decoder. Run with
+//! `cargo fuzz run decode` from the crate root.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use cuckoo_filter::{decode_ops, inspect_serialized, Filter, FilterDelta};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(filter) = Filter::from_bytes(data) {
+        // Migrations add a few header bytes and a checksum at most, so a
+        // filter much larger than its input means the header was trusted
+        // over the buffer
+        let bytes = filter.to_bytes();
+        assert!(bytes.len() <= data.len() + 32);
+        assert_eq!(Filter::from_bytes(&bytes).unwrap().to_bytes(), bytes);
+        for key in 0u8 .. 16 {
+            filter.contains(&key);
+            let _ = filter.insert(&key);
+        }
+    }
+    let _ = inspect_serialized(data);
+    let _ = decode_ops(data);
+    let _ = FilterDelta::from_bytes(data);
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = Filter::from_dump(text);
+    }
+});
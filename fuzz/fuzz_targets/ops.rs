@@ -0,0 +1,89 @@
+//! Arbitrary operation sequences on small filters, checked against an
+//! exact multiset after every operation. Run with `cargo fuzz run ops`
+//! from the crate root.
+
+#![no_main]
+
+use std::collections::HashMap;
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+use cuckoo_filter::{Config, Filter};
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    num_buckets: u8,
+    num_entries: u8,
+    wide: bool,
+    occupancy_bitmap: bool,
+    two_hashes: bool,
+    num_hashes: u8,
+    ops: Vec<Op>,
+}
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Insert(u8),
+    Delete(u8),
+    Contains(u8),
+    Clear,
+    /// Merge a filter holding these keys
+    Merge(Vec<u8>),
+}
+
+fuzz_target!(|input: Input| {
+    let config = Config {
+        finger_bits: if input.wide { 16 } else { 8 },
+        num_buckets: input.num_buckets as u32 % 8 + 1,
+        num_entries: input.num_entries as u16 % 4 + 1,
+        hash_count: if input.two_hashes { 2 } else { 1 },
+        num_hashes: input.num_hashes % 3 + 2,
+        occupancy_bitmap: input.occupancy_bitmap,
+        seed: Some(0),
+        ..Config::default()
+    };
+    let filter = Filter::new(&config).unwrap();
+    let mut model: HashMap<u8, u64> = HashMap::new();
+    // A failed insert drops a fingerprint, after which only a clear
+    // makes the model exact again
+    let mut exact = true;
+    for op in input.ops {
+        match op {
+            Op::Insert(key) => match filter.insert(&key) {
+                Ok(_) => *model.entry(key).or_insert(0) += 1,
+                Err(_) => exact = false,
+            },
+            Op::Delete(key) => {
+                // Deleting keys that were never inserted is not allowed
+                if let Some(n) = model.get_mut(&key).filter(|n| **n > 0) {
+                    *n -= 1;
+                    let found = filter.delete(&key);
+                    assert!(found || !exact);
+                }
+            },
+            Op::Contains(key) => {
+                let found = filter.contains(&key);
+                assert!(found || !exact || model.get(&key).is_none_or(|&n| n == 0));
+            },
+            Op::Clear => {
+                filter.clear();
+                model.clear();
+                exact = true;
+            },
+            Op::Merge(keys) => {
+                let other = Filter::new(&config).unwrap();
+                if keys.iter().all(|key| other.insert(key).is_ok()) && filter.merge(&other).is_ok() {
+                    keys.iter().for_each(|&key| *model.entry(key).or_insert(0) += 1);
+                } else {
+                    exact = false;
+                }
+            },
+        }
+        if exact {
+            assert_eq!(filter.used(), model.values().sum::<u64>());
+            assert!(model.iter().all(|(key, &n)| n == 0 || filter.contains(key)));
+        }
+        assert!(filter.load_factor() <= 1.0);
+    }
+});
@@ -0,0 +1,204 @@
+//! Python bindings: a `cuckoo_filter.CuckooFilter` class wrapping `Filter`.
+//!
+//! Keys are `bytes` or `str` (as UTF-8) and are hashed as byte slices,
+//! like the command line `build`, so a filter built in Python answers the
+//! same as one built in Rust by inserting `key.as_bytes()`, and the two
+//! can exchange `to_bytes` output.
+//!
+//! Build a wheel with `maturin build --release` in this directory.
+
+// The code pyo3 generates for methods returning `PyResult` trips this lint
+#![allow(clippy::useless_conversion)]
+
+use pyo3::exceptions::{PyRuntimeError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyString};
+
+use cuckoo::{Config, Filter};
+
+/// Methods that release the GIL borrow the filter mutably, so that other
+/// threads get an error rather than share it while it runs.
+#[pyclass(module = "cuckoo_filter")]
+struct CuckooFilter {
+    filter: Filter,
+}
+
+#[pymethods]
+impl CuckooFilter {
+    /// Filter sized for `capacity` keys with false positive rate at most
+    /// `fp_rate` (see `Config::for_capacity`).
+    #[new]
+    fn new(capacity: u64, fp_rate: f64) -> PyResult<CuckooFilter> {
+        let config = Config::for_capacity(capacity, fp_rate)
+            .map_err(|_| PyValueError::new_err("capacity or fp_rate out of reach"))?;
+        let filter = Filter::new(&config).map_err(|_| PyValueError::new_err("invalid configuration"))?;
+        Ok(CuckooFilter { filter })
+    }
+
+    /// Adds `key` unless already present; returns whether it was added.
+    /// Raises `RuntimeError` if the filter is full.
+    fn add(&self, key: &Bound<'_, PyAny>) -> PyResult<bool> {
+        let added = self.filter.contains_or_insert(key_bytes(key)?).map_err(|_| full())?;
+        Ok(!added)
+    }
+
+    /// `add` for each of `keys`, with the GIL released; returns how many
+    /// were added. Keys added before the filter filled up are kept.
+    fn add_many(&mut self, py: Python<'_>, keys: Vec<Bound<'_, PyAny>>) -> PyResult<u64> {
+        let keys = owned_keys(&keys)?;
+        let filter = &mut self.filter;
+        py.allow_threads(move || {
+            let mut added = 0;
+            for key in &keys {
+                added += !filter.contains_or_insert(key.as_slice()).map_err(|_| full())? as u64;
+            }
+            Ok(added)
+        })
+    }
+
+    fn __contains__(&self, key: &Bound<'_, PyAny>) -> PyResult<bool> {
+        Ok(self.filter.contains(key_bytes(key)?))
+    }
+
+    /// Membership of each of `keys`, with the GIL released.
+    fn contains_many(&mut self, py: Python<'_>, keys: Vec<Bound<'_, PyAny>>) -> PyResult<Vec<bool>> {
+        let keys = owned_keys(&keys)?;
+        let filter = &mut self.filter;
+        Ok(py.allow_threads(move || keys.iter().map(|key| filter.contains(key.as_slice())).collect()))
+    }
+
+    /// Removes one copy of `key`; returns whether one was found. Only
+    /// remove keys that were added.
+    fn remove(&self, key: &Bound<'_, PyAny>) -> PyResult<bool> {
+        Ok(self.filter.delete(key_bytes(key)?))
+    }
+
+    fn __len__(&self) -> usize {
+        self.filter.used() as usize
+    }
+
+    fn to_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.filter.to_bytes())
+    }
+
+    /// Reads bytes written by `to_bytes`, here or by the Rust crate.
+    /// Raises `ValueError` if they do not decode.
+    #[staticmethod]
+    fn from_bytes(bytes: &[u8]) -> PyResult<CuckooFilter> {
+        let filter = Filter::from_bytes(bytes).map_err(|e| PyValueError::new_err(format!("{:?}", e)))?;
+        Ok(CuckooFilter { filter })
+    }
+
+    /// Adds every key in `other`, which must have been built with the
+    /// same capacity and fp_rate. Raises `ValueError` if it was not or if
+    /// this filter fills up.
+    fn merge(&self, other: &CuckooFilter) -> PyResult<()> {
+        self.filter.merge(&other.filter)
+            .map_err(|_| PyValueError::new_err("filters differ in configuration, or the merge does not fit"))
+    }
+}
+
+/// The bytes `key` is hashed as.
+fn key_bytes<'a>(key: &'a Bound<'_, PyAny>) -> PyResult<&'a [u8]> {
+    if let Ok(bytes) = key.downcast::<PyBytes>() {
+        Ok(bytes.as_bytes())
+    } else if let Ok(s) = key.downcast::<PyString>() {
+        Ok(s.to_str()?.as_bytes())
+    } else {
+        Err(PyTypeError::new_err("keys must be bytes or str"))
+    }
+}
+
+/// Copies of the bytes of `keys`, to use without the GIL.
+fn owned_keys(keys: &[Bound<'_, PyAny>]) -> PyResult<Vec<Vec<u8>>> {
+    keys.iter().map(|key| key_bytes(key).map(<[u8]>::to_vec)).collect()
+}
+
+fn full() -> PyErr {
+    PyRuntimeError::new_err("filter is full")
+}
+
+#[pymodule]
+fn cuckoo_filter(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<CuckooFilter>()
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::types::PyDict;
+
+    use super::*;
+
+    /// Runs `code` with the `CuckooFilter` class in scope, returning its
+    /// globals.
+    fn run<'py>(py: Python<'py>, code: &str) -> Bound<'py, PyDict> {
+        let module = PyModule::new_bound(py, "cuckoo_filter").unwrap();
+        cuckoo_filter(&module).unwrap();
+        let globals = PyDict::new_bound(py);
+        globals.set_item("CuckooFilter", module.getattr("CuckooFilter").unwrap()).unwrap();
+        if let Err(e) = py.run_bound(code, Some(&globals), None) {
+            e.print(py);
+            panic!("python code failed");
+        }
+        globals
+    }
+
+    #[test]
+    fn class_behaves_as_a_set() {
+        Python::with_gil(|py| {
+            run(py, r#"
+f = CuckooFilter(1000, 0.001)
+assert f.add("alpha") and f.add(b"beta")
+assert not f.add("alpha")
+assert "alpha" in f and b"alpha" in f and "beta" in f
+assert len(f) == 2
+assert f.remove("beta") and "beta" not in f and len(f) == 1
+assert f.add_many([str(i) for i in range(500)]) == 500
+assert all(x for x in f.contains_many([str(i) for i in range(500)]))
+try:
+    f.add(7)
+    raise AssertionError("int key accepted")
+except TypeError:
+    pass
+try:
+    CuckooFilter.from_bytes(b"junk")
+    raise AssertionError("junk decoded")
+except ValueError:
+    pass
+g = CuckooFilter.from_bytes(f.to_bytes())
+assert len(g) == len(f) and "alpha" in g
+h = CuckooFilter(1000, 0.001)
+h.add("gamma")
+h.merge(g)
+assert "alpha" in h and "gamma" in h and len(h) == len(f) + 1
+try:
+    h.merge(CuckooFilter(10, 0.1))
+    raise AssertionError("mismatched merge accepted")
+except ValueError:
+    pass
+"#);
+        });
+    }
+
+    #[test]
+    fn bytes_are_portable_to_rust() {
+        let bytes: Vec<u8> = Python::with_gil(|py| {
+            let globals = run(py, r#"
+f = CuckooFilter(1000, 0.001)
+f.add("alpha")
+f.add(b"beta")
+data = f.to_bytes()
+"#);
+            globals.get_item("data").unwrap().unwrap().extract().unwrap()
+        });
+        let filter = Filter::from_bytes(&bytes).unwrap();
+        assert!(filter.contains("alpha".as_bytes()) && filter.contains(&b"beta"[..]));
+        filter.insert("gamma".as_bytes()).unwrap();
+        Python::with_gil(|py| {
+            let globals = run(py, "");
+            globals.set_item("data", PyBytes::new_bound(py, &filter.to_bytes())).unwrap();
+            py.run_bound("f = CuckooFilter.from_bytes(data)\nassert 'gamma' in f and len(f) == 3", Some(&globals), None)
+                .unwrap();
+        });
+    }
+}
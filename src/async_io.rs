@@ -0,0 +1,150 @@
+//! Saving and loading from async tasks, behind the `tokio` feature.
+//!
+//! The bytes are those of `to_bytes`, moved in chunks of `CHUNK_BYTES`
+//! with a yield to the runtime after each, so writing or reading a large
+//! filter does not hold up other tasks for long. Writing encodes and
+//! checksums as it goes; reading collects the bytes and decodes them on
+//! the blocking thread pool.
+
+use std::cell::Ref;
+use std::io;
+use std::path::Path;
+
+use tokio::fs::{self, File};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::task;
+
+use crate::serialize::{fnv1a, fnv1a_update, HEADER_LEN};
+use crate::{Buckets, Filter};
+
+/// Bytes written or read between yields.
+const CHUNK_BYTES: usize = 1 << 16;
+
+impl Filter {
+    /// Writes `to_bytes` to `w`. Fails with `Other` if the filter is
+    /// modified before the future completes, having written part of a
+    /// stream that will not decode.
+    pub async fn write_to_async<W: AsyncWrite + Unpin>(&self, mut w: W) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(CHUNK_BYTES);
+        buf.extend_from_slice(&self.header_bytes());
+        buf.extend_from_slice(&self.used().to_le_bytes());
+        debug_assert_eq!(buf.len(), HEADER_LEN);
+        let mut h = fnv1a(&buf);
+        w.write_all(&buf).await?;
+
+        // The buckets are borrowed a chunk at a time, and their running
+        // checksum tells whether another task wrote to them in between
+        let (len, checksum) = {
+            let buckets = self.buckets.borrow();
+            (buckets.len(), buckets.checksum)
+        };
+        let unchanged = || -> io::Result<Ref<Buckets>> {
+            let buckets = self.buckets.borrow();
+            if buckets.checksum != checksum {
+                return Err(io::Error::other("filter modified while writing"));
+            }
+            Ok(buckets)
+        };
+        let per_chunk = CHUNK_BYTES / self.finger_bits.div_ceil(8) as usize;
+        for start in (0 .. len).step_by(per_chunk) {
+            buf.clear();
+            self.write_fingers(&*unchanged()?, start .. len.min(start + per_chunk), &mut buf);
+            h = fnv1a_update(h, &buf);
+            w.write_all(&buf).await?;
+            task::yield_now().await;
+        }
+        buf.clear();
+        self.write_bitmap(&*unchanged()?, &mut buf);
+        for chunk in buf.chunks(CHUNK_BYTES) {
+            h = fnv1a_update(h, chunk);
+            w.write_all(chunk).await?;
+            task::yield_now().await;
+        }
+        unchanged()?;
+        w.write_all(&h.to_le_bytes()).await?;
+        w.flush().await
+    }
+
+    /// Reads a filter written by `write_to_async` or `to_bytes` of this or
+    /// any older format version from `r`, to its end. Fails with
+    /// `InvalidData` if `from_bytes` rejects the bytes.
+    pub async fn read_from_async<R: AsyncRead + Unpin>(mut r: R) -> io::Result<Filter> {
+        let mut bytes = Vec::new();
+        let mut chunk = vec![0u8; CHUNK_BYTES];
+        loop {
+            let n = r.read(&mut chunk).await?;
+            if n == 0 {
+                break;
+            }
+            bytes.extend_from_slice(&chunk[.. n]);
+            task::yield_now().await;
+        }
+        task::spawn_blocking(move || Filter::from_bytes(&bytes))
+            .await
+            .map_err(io::Error::other)?
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))
+    }
+
+    /// `save` from an async task: writes to a temporary file beside
+    /// `path`, syncs it and renames it over `path`.
+    pub async fn save_async(&self, path: &Path) -> io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut file = BufWriter::new(File::create(&tmp).await?);
+        self.write_to_async(&mut file).await?;
+        file.into_inner().sync_all().await?;
+        fs::rename(&tmp, path).await
+    }
+
+    /// Reads a filter saved by `save` or `save_async` from an async task.
+    pub async fn load_async(path: &Path) -> io::Result<Filter> {
+        Filter::read_from_async(File::open(path).await?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::future::Future;
+
+    use tokio::runtime::Builder;
+
+    use crate::tests::config;
+    use crate::{Config, Filter};
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        Builder::new_current_thread().build().unwrap().block_on(future)
+    }
+
+    #[test]
+    fn async_bytes_match_sync() {
+        // Large enough for several chunks of fingerprints and of bitmap
+        for &(finger_bits, occupancy_bitmap) in &[(8, false), (12, true), (16, true), (32, false)] {
+            let filter = Filter::new(&Config { finger_bits, occupancy_bitmap, ..config(1 << 17) }).unwrap();
+            for i in 0 .. 100_000u64 {
+                filter.insert(&i).unwrap();
+            }
+            let mut bytes = Vec::new();
+            block_on(filter.write_to_async(&mut bytes)).unwrap();
+            assert_eq!(bytes, filter.to_bytes(), "{} bits", finger_bits);
+            let read = block_on(Filter::read_from_async(&bytes[..])).unwrap();
+            assert_eq!(read.to_bytes(), bytes);
+        }
+        assert!(block_on(Filter::read_from_async(&[1u8, 2, 3][..])).is_err());
+    }
+
+    #[test]
+    fn save_and_load_interoperate() {
+        let filter = Filter::new(&config(1024)).unwrap();
+        for i in 0 .. 2000u64 {
+            filter.insert(&i).unwrap();
+        }
+        let path = std::env::temp_dir().join(format!("cuckoo-async-{}", std::process::id()));
+        block_on(filter.save_async(&path)).unwrap();
+        assert_eq!(Filter::from_bytes(&fs::read(&path).unwrap()).unwrap().to_bytes(), filter.to_bytes());
+        filter.delete(&0u64);
+        filter.save(&path).unwrap();
+        assert_eq!(block_on(Filter::load_async(&path)).unwrap().to_bytes(), filter.to_bytes());
+        fs::remove_file(&path).unwrap();
+    }
+}
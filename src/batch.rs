@@ -0,0 +1,216 @@
+//! All-or-nothing batch inserts, and batch removals.
+//!
+//! While a batch runs, `Buckets` journals the previous contents of every
+//! slot it writes, including slots rewritten by kicks and the victim a
+//! failed insert drops. Rolling back replays the journal newest first,
+//! which costs time proportional to the batch rather than the filter.
+
+use std::hash::Hash;
+
+use crate::{BucketType, Buckets, Filter, FilterOp};
+
+/// Why `Filter::insert_all_or_rollback` rolled back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatchError {
+    /// The key at this position in the batch did not fit
+    Full { index: usize },
+}
+
+/// Outcome of `Filter::remove_many`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RemoveReport {
+    /// Keys whose fingerprint was found and removed
+    pub removed: u64,
+
+    /// Keys with no matching fingerprint
+    pub not_found: u64,
+}
+
+impl Filter {
+    /// Inserts every key, or none: if one does not fit, the slots and
+    /// `used` are restored to their state before the call and the failing
+    /// key's position is returned. The op log only records the batch if it
+    /// succeeds; the observer sees every attempt.
+    pub fn insert_all_or_rollback<'a, T: ?Sized + Hash + 'a>(
+        &mut self,
+        keys: impl IntoIterator<Item = &'a T>,
+    ) -> Result<(), BatchError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("insert_all_or_rollback", load_factor = self.load_factor()).entered();
+        let op_log = self.op_log.take();
+        let mut hashes = Vec::new();
+        let result = self.or_roll_back(|| {
+            for (index, x) in keys.into_iter().enumerate() {
+                let h = self.hash_key(x);
+                self.insert_slot(h, 0).map_err(|_| BatchError::Full { index })?;
+                hashes.push(h);
+            }
+            Ok(())
+        });
+        self.op_log = op_log;
+        result?;
+        for h in hashes {
+            self.record_op(FilterOp::InsertHash(h));
+        }
+        Ok(())
+    }
+
+    /// Deletes one copy of each key, as `delete` would one at a time, but
+    /// hashing every key first and then holding the buckets for the whole
+    /// batch. As with `delete`, only remove keys that were inserted. Each
+    /// removal is recorded in the op log and reported to the observer.
+    pub fn remove_many<'a, T: ?Sized + Hash + 'a>(&mut self, keys: impl IntoIterator<Item = &'a T>) -> RemoveReport {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("remove_many", load_factor = self.load_factor()).entered();
+        let hashes: Vec<u64> = keys.into_iter().map(|x| self.hash_key(x)).collect();
+        let mut report = RemoveReport::default();
+        let mut buckets = self.buckets.borrow_mut();
+        for h in hashes {
+            let found = match self.bucket_type {
+                BucketType::U8 => self.find_word_in::<u8>(&buckets, h),
+                BucketType::U16 => self.find_word_in::<u16>(&buckets, h),
+                BucketType::U32 => self.find_word_in::<u32>(&buckets, h),
+            };
+            match found {
+                Some(i) => {
+                    buckets.remove(i);
+                    report.removed += 1;
+                },
+                None => report.not_found += 1,
+            }
+            self.record_remove(FilterOp::RemoveHash(h), found.is_some());
+        }
+        report
+    }
+
+    /// Runs `f` with slot writes journaled and, if it fails, undoes them
+    /// and restores `used`.
+    pub(crate) fn or_roll_back<R, E>(&self, f: impl FnOnce() -> Result<R, E>) -> Result<R, E> {
+        let used = self.used();
+        // An open checkpoint is already journaling; these writes follow
+        // its entries
+        let (checkpointed, mark) = {
+            let mut buckets = self.buckets.borrow_mut();
+            let checkpointed = buckets.journal.is_some();
+            (checkpointed, buckets.journal.get_or_insert_with(Vec::new).len())
+        };
+        let result = f();
+
+        let mut buckets = self.buckets.borrow_mut();
+        if result.is_err() {
+            buckets.roll_back_to(mark);
+            self.used.replace(used);
+        }
+        if !checkpointed {
+            buckets.journal = None;
+        }
+        result
+    }
+}
+
+impl Buckets {
+    /// Notes the contents of slot `i` before a write, if journaling.
+    pub(crate) fn log_write(&mut self, i: usize) {
+        if self.journal.is_some() {
+            let old = self.entry(i);
+            self.note_write(i, old);
+        }
+    }
+
+    /// Notes that slot `i` held `old` before a write, if journaling.
+    pub(crate) fn note_write(&mut self, i: usize, old: Option<u32>) {
+        if let Some(ref mut journal) = self.journal {
+            journal.push((i, old));
+        }
+    }
+
+    /// Undoes the journaled writes after the first `mark`, newest first,
+    /// and drops them from the journal.
+    pub(crate) fn roll_back_to(&mut self, mark: usize) {
+        let mut journal = match self.journal.take() {
+            Some(journal) => journal,
+            None => return,
+        };
+        for (i, old) in journal.drain(mark ..).rev() {
+            match old {
+                Some(finger) => self.put(i, finger),
+                None => self.remove(i),
+            }
+        }
+        self.journal = Some(journal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BatchError;
+    use crate::tests::config;
+    use crate::{Config, Filter};
+
+    #[test]
+    fn failed_batch_leaves_identical_state() {
+        for &occupancy_bitmap in &[false, true] {
+            let mut filter = Filter::new(&Config { occupancy_bitmap, ..config(64) }).unwrap();
+            for i in 0 .. 200u64 {
+                filter.insert(&i).unwrap();
+            }
+            filter.record_ops(16);
+            let before = filter.to_bytes();
+            let checksum = filter.checksum();
+            // Fills the filter with kicks until one key fails partway
+            let batch: Vec<u64> = (1000 .. 1100).collect();
+            match filter.insert_all_or_rollback(&batch) {
+                Err(BatchError::Full { index }) => assert!(index > 0 && index < 100),
+                other => panic!("{:?}", other),
+            }
+            assert_eq!(filter.to_bytes(), before);
+            assert_eq!(filter.checksum(), checksum);
+            assert_eq!(filter.used(), 200);
+            assert!(filter.take_ops().is_empty());
+            assert!((0 .. 200u64).all(|i| filter.contains(&i)));
+        }
+    }
+
+    #[test]
+    fn successful_batch_inserts_every_key() {
+        let mut filter = Filter::new(&config(1024)).unwrap();
+        filter.record_ops(16);
+        let batch: Vec<u64> = (0 .. 10).collect();
+        filter.insert_all_or_rollback(&batch).unwrap();
+        assert_eq!(filter.used(), 10);
+        assert!(batch.iter().all(|i| filter.contains(i)));
+        assert_eq!(filter.take_ops().len(), 10);
+    }
+
+    #[test]
+    fn remove_many_matches_single_deletes() {
+        let fill = || {
+            let filter = Filter::new(&config(1024)).unwrap();
+            for i in 0 .. 2000u64 {
+                filter.insert(&i).unwrap();
+            }
+            filter
+        };
+        let mut filter = fill();
+        filter.record_ops(2000);
+        let keys: Vec<u64> = (0 .. 1000).collect();
+        let report = filter.remove_many(&keys);
+        assert_eq!((report.removed, report.not_found), (1000, 0));
+        assert_eq!(filter.used(), 1000);
+        assert_eq!(filter.take_ops().len(), 1000);
+        assert!((1000 .. 2000u64).all(|i| filter.contains(&i)));
+
+        let single = fill();
+        for key in &keys {
+            single.delete(key);
+        }
+        assert_eq!(filter.to_bytes(), single.to_bytes());
+        assert_eq!(filter.checksum(), single.checksum());
+
+        let mut empty = Filter::new(&config(1024)).unwrap();
+        let report = empty.remove_many(&keys);
+        assert_eq!((report.removed, report.not_found), (0, 1000));
+        assert_eq!(empty.used(), 0);
+        assert_eq!(filter.remove_many(&[] as &[u64]), Default::default());
+    }
+}
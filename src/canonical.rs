@@ -0,0 +1,71 @@
+//! Putting each bucket's fingerprints in a fixed order, so filters that
+//! differ only in the order their buckets were filled serialize to the
+//! same bytes.
+
+use crate::Filter;
+
+impl Filter {
+    /// Sorts the fingerprints of every bucket in ascending order, empty
+    /// entries last. Lookups are unaffected, but afterwards two filters
+    /// holding the same fingerprints in the same buckets have equal
+    /// `to_bytes` and `to_dump` output, whatever order they were inserted
+    /// in. Filters that placed a fingerprint in different candidate
+    /// buckets still differ, as happens once buckets fill up and which
+    /// value finds room in its primary bucket depends on the order. The
+    /// rearrangement is not recorded in the op log.
+    pub fn canonicalize(&self) {
+        let mut buckets = self.buckets.borrow_mut();
+        let entries = self.num_entries as usize;
+        let mut fingers = Vec::with_capacity(entries);
+        for start in (0 .. buckets.len()).step_by(entries) {
+            fingers.clear();
+            fingers.extend((start .. start + entries).filter_map(|i| buckets.entry(i)));
+            fingers.sort_unstable();
+            for (i, want) in (start .. start + entries).zip(fingers.iter().map(Some).chain(std::iter::repeat(None))) {
+                match (buckets.entry(i), want) {
+                    (have, Some(&finger)) if have != Some(finger) => buckets.put(i, finger),
+                    (Some(_), None) => buckets.remove(i),
+                    _ => {},
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    use crate::tests::config;
+    use crate::{Config, Filter};
+
+    #[test]
+    fn canonical_bytes_ignore_order_and_seed() {
+        let keys: Vec<u64> = (0 .. 500).collect();
+        let mut reference = None;
+        for seed in 0 .. 4 {
+            let mut order = keys.clone();
+            order.shuffle(&mut StdRng::seed_from_u64(seed));
+            let filter = Filter::new(&Config { seed: Some(seed), ..config(1024) }).unwrap();
+            for key in &order {
+                filter.insert(key).unwrap();
+            }
+            let before = filter.to_bytes();
+            filter.canonicalize();
+            let bytes = filter.to_bytes();
+            assert!(keys.iter().all(|key| filter.contains(key)));
+            assert_eq!(filter.used(), 500);
+            filter.canonicalize();
+            assert_eq!(filter.to_bytes(), bytes);
+            match reference {
+                None => reference = Some((before, bytes)),
+                Some((ref first, ref canonical)) => {
+                    assert_ne!(&before, first);
+                    assert_eq!(&bytes, canonical, "seed {}", seed);
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Checkpoints for speculative changes.
+//!
+//! A checkpoint turns on the bucket journal (see `batch`), so its cost
+//! grows with the writes made while it is open rather than with the size
+//! of the filter. Checkpoints do not nest.
+
+use crate::Filter;
+
+/// An open checkpoint, returned by `Filter::checkpoint` and consumed by
+/// `restore` or `discard`.
+#[derive(Debug)]
+#[must_use]
+pub struct Checkpoint {
+    used: u64,
+}
+
+impl Filter {
+    /// Starts recording writes so that `restore` can bring the filter back
+    /// to its current contents. Fails if a checkpoint is already open.
+    pub fn checkpoint(&self) -> Result<Checkpoint, ()> {
+        let mut buckets = self.buckets.borrow_mut();
+        if buckets.journal.is_some() {
+            return Err(());
+        }
+        buckets.journal = Some(Vec::new());
+        Ok(Checkpoint { used: self.used() })
+    }
+
+    /// Rolls back every write made since `checkpoint` and closes it. The
+    /// rollback is not recorded in the op log, so replicas need a fresh
+    /// snapshot. Fails if no checkpoint is open.
+    pub fn restore(&self, checkpoint: Checkpoint) -> Result<(), ()> {
+        let mut buckets = self.buckets.borrow_mut();
+        if buckets.journal.is_none() {
+            return Err(());
+        }
+        buckets.roll_back_to(0);
+        buckets.journal = None;
+        self.used.replace(checkpoint.used);
+        Ok(())
+    }
+
+    /// Closes `checkpoint`, keeping the changes made since. Fails if no
+    /// checkpoint is open.
+    pub fn discard(&self, checkpoint: Checkpoint) -> Result<(), ()> {
+        let Checkpoint { .. } = checkpoint;
+        self.buckets.borrow_mut().journal.take().map(|_| ()).ok_or(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::tests::config;
+    use crate::Filter;
+
+    #[test]
+    fn restore_undoes_thousands_of_ops() {
+        let filter = Filter::new(&config(1024)).unwrap();
+        for i in 0 .. 2000u64 {
+            filter.insert(&i).unwrap();
+        }
+        let snapshot = filter.to_bytes();
+        let checkpoint = filter.checkpoint().unwrap();
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0 .. 5000 {
+            let key = rng.gen_range(0, 4000u64);
+            if rng.gen() {
+                let _ = filter.insert(&key);
+            } else {
+                filter.delete(&key);
+            }
+        }
+        assert_ne!(filter.to_bytes(), snapshot);
+        filter.restore(checkpoint).unwrap();
+        assert_eq!(filter.to_bytes(), snapshot);
+        assert_eq!(filter.used(), 2000);
+        assert_eq!(filter.checksum(), filter.recompute_checksum());
+        assert!((0 .. 2000u64).all(|i| filter.contains(&i)));
+    }
+
+    #[test]
+    fn nested_checkpoints_error() {
+        let filter = Filter::new(&config(64)).unwrap();
+        let checkpoint = filter.checkpoint().unwrap();
+        assert!(filter.checkpoint().is_err());
+        filter.insert(&1).unwrap();
+        filter.discard(checkpoint).unwrap();
+        assert!(filter.contains(&1));
+        let checkpoint = filter.checkpoint().unwrap();
+        filter.restore(checkpoint).unwrap();
+        assert_eq!(filter.used(), 1);
+    }
+}
@@ -0,0 +1,100 @@
+//! Running checksum of the slots.
+//!
+//! `Buckets` keeps the XOR of `slot_checksum` over its occupied slots and
+//! updates it on every write, so reading it is O(1). Slots are identified
+//! by their logical position, so the checksum does not depend on the
+//! layout or on whether the slots are still sparse.
+
+use crate::{remix, Buckets, Filter};
+
+impl Filter {
+    /// Checksum of the occupied slots and their fingerprints, maintained
+    /// incrementally. Filters holding the same fingerprints in the same
+    /// slots have the same checksum.
+    pub fn checksum(&self) -> u64 {
+        self.buckets.borrow().checksum
+    }
+
+    /// The checksum computed from scratch over every slot. A difference
+    /// from `checksum` means the slots changed behind the filter's back.
+    pub fn recompute_checksum(&self) -> u64 {
+        self.buckets.borrow().full_checksum()
+    }
+}
+
+impl Buckets {
+    pub(crate) fn full_checksum(&self) -> u64 {
+        (0 .. self.len())
+            .filter_map(|i| self.entry(i).map(|finger| slot_checksum(i, finger)))
+            .fold(0, |h, x| h ^ x)
+    }
+
+    /// Adds slot `i` to the checksum if occupied, or takes it out if it
+    /// was added before and has not changed since.
+    pub(crate) fn toggle_checksum(&mut self, i: usize) {
+        if let Some(finger) = self.entry(i) {
+            self.checksum ^= slot_checksum(i, finger);
+        }
+    }
+}
+
+/// Contribution of slot `i` holding `finger`. The slot is mixed before
+/// the fingerprint goes in, so fingerprints of any width cannot cancel out
+/// a change of slot.
+pub(crate) fn slot_checksum(i: usize, finger: u32) -> u64 {
+    remix(remix(i as u64) ^ finger as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::tests::config;
+    use crate::{Config, Filter, Layout};
+
+    #[test]
+    fn incremental_equals_recomputed() {
+        let configs = [
+            config(256),
+            Config { finger_bits: 8, ..config(256) },
+            Config { finger_bits: 12, layout: Layout::ColumnMajor, ..config(256) },
+            Config { finger_bits: 32, occupancy_bitmap: true, ..config(256) },
+            Config { num_entries: 2, num_hashes: 3, ..config(256) },
+        ];
+        let mut rng = StdRng::seed_from_u64(3);
+        for c in &configs {
+            let filter = Filter::new(c).unwrap();
+            assert_eq!(filter.checksum(), 0);
+            let mut live = Vec::new();
+            for op in 0 .. 3000u64 {
+                // Mostly inserts, so that later ones kick
+                match rng.gen_range(0, 10) {
+                    0 ..= 5 => {
+                        if filter.insert(&op).is_ok() {
+                            live.push(op);
+                        }
+                    },
+                    6 ..= 8 if !live.is_empty() => {
+                        let x = live.swap_remove(rng.gen_range(0, live.len()));
+                        filter.delete(&x);
+                    },
+                    _ if op % 1000 == 999 => {
+                        filter.clear();
+                        live.clear();
+                    },
+                    _ => {
+                        let _ = filter.insert(&rng.gen::<u64>());
+                    },
+                }
+                assert_eq!(filter.checksum(), filter.recompute_checksum(), "{:?} after {} ops", c, op);
+            }
+            let other = Filter::new(c).unwrap();
+            for i in 0 .. 100u64 {
+                other.insert(&!i).unwrap();
+            }
+            let _ = filter.merge(&other);
+            assert_eq!(filter.checksum(), filter.recompute_checksum());
+        }
+    }
+}
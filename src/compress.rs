@@ -0,0 +1,80 @@
+//! Compressed archives of a filter, behind the `flate2` feature.
+//!
+//! The archive is a gzip stream of the `to_bytes` encoding, so `gunzip`
+//! turns it back into a file `from_bytes` reads. Empty slots are zeros,
+//! so lightly loaded filters shrink the most; a full one with 16-bit
+//! fingerprints barely shrinks at all.
+
+use std::io::{self, Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::Filter;
+
+impl Filter {
+    /// Writes the filter to `w` as a gzip stream of its `to_bytes`
+    /// encoding, without building that encoding in memory first.
+    pub fn write_compressed(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut encoder = GzEncoder::new(w, Compression::default());
+        for slice in self.as_transmit_frame().io_slices() {
+            encoder.write_all(&slice)?;
+        }
+        encoder.finish()?;
+        Ok(())
+    }
+
+    /// Reads a filter written by `write_compressed`. Fails with the
+    /// decompressor's error if the stream is not valid gzip, or with
+    /// `InvalidData` if it does not decompress to bytes `from_bytes`
+    /// accepts.
+    pub fn read_compressed(r: impl Read) -> io::Result<Filter> {
+        let mut bytes = Vec::new();
+        GzDecoder::new(r).read_to_end(&mut bytes)?;
+        Filter::from_bytes(&bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use crate::tests::config;
+    use crate::Filter;
+
+    #[test]
+    fn lightly_loaded_filter_shrinks() {
+        let filter = Filter::new(&config(1 << 16)).unwrap();
+        for i in 0 .. 1000u64 {
+            filter.insert(&i).unwrap();
+        }
+        let mut archive = Vec::new();
+        filter.write_compressed(&mut archive).unwrap();
+        let raw = filter.to_bytes().len();
+        assert!(archive.len() * 20 < raw, "{} of {} bytes", archive.len(), raw);
+        let copy = Filter::read_compressed(&archive[..]).unwrap();
+        assert_eq!(copy.to_bytes(), filter.to_bytes());
+        assert!((0 .. 1000u64).all(|i| copy.contains(&i)));
+    }
+
+    #[test]
+    fn rejects_damaged_archives() {
+        let filter = Filter::new(&config(64)).unwrap();
+        let mut archive = Vec::new();
+        filter.write_compressed(&mut archive).unwrap();
+        let n = archive.len();
+        assert!(Filter::read_compressed(&archive[.. n / 2]).is_err());
+        assert!(Filter::read_compressed(&b"not gzip"[..]).is_err());
+        let mut truncated = Vec::new();
+        let mut encoder = GzEncoder::new(&mut truncated, Compression::default());
+        encoder.write_all(&filter.to_bytes()[.. 10]).unwrap();
+        encoder.finish().unwrap();
+        let e = Filter::read_compressed(&truncated[..]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
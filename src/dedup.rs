@@ -0,0 +1,89 @@
+//! Approximate deduplication of a stream of records.
+
+use std::io::{self, Write};
+
+use crate::Filter;
+
+/// Wraps a writer, forwarding each record only the first time it is seen.
+///
+/// Seen records are remembered in a `Filter`, so a small fraction of new
+/// records (the filter's false positive rate) are mistaken for duplicates
+/// and dropped. Once the filter is full, new records are still written but
+/// no longer remembered, so their later duplicates get through.
+#[derive(Debug)]
+pub struct DedupWriter<W: Write> {
+    inner: W,
+    filter: Filter,
+    suppressed: u64,
+}
+
+impl<W: Write> DedupWriter<W> {
+    pub fn new(inner: W, filter: Filter) -> DedupWriter<W> {
+        DedupWriter { inner, filter, suppressed: 0 }
+    }
+
+    /// Writes `record` unless it was seen before, returning whether it was
+    /// written. Records are written as given; add any separator yourself.
+    pub fn write_record(&mut self, record: &[u8]) -> io::Result<bool> {
+        if let Ok(true) = self.filter.contains_or_insert(record) {
+            self.suppressed += 1;
+            return Ok(false);
+        }
+        self.inner.write_all(record)?;
+        Ok(true)
+    }
+
+    /// Number of records dropped as duplicates.
+    pub fn suppressed(&self) -> u64 {
+        self.suppressed
+    }
+
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Returns the wrapped writer and the filter of seen records.
+    pub fn into_parts(self) -> (W, Filter) {
+        (self.inner, self.filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::tests::config;
+
+    #[test]
+    fn duplicates_are_suppressed() {
+        let mut writer = DedupWriter::new(Vec::new(), Filter::new(&config(1024)).unwrap());
+        let records: Vec<String> = (0 .. 3000).map(|i| format!("record {}\n", i % 1000)).collect();
+        let mut written = 0;
+        for record in &records {
+            written += writer.write_record(record.as_bytes()).unwrap() as u64;
+        }
+        assert_eq!(written + writer.suppressed(), 3000);
+        // Only false positives drop a first occurrence
+        assert!((990 ..= 1000).contains(&written), "{} written", written);
+
+        let (out, filter) = writer.into_parts();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len() as u64, written);
+        assert_eq!(lines.iter().collect::<HashSet<_>>().len(), lines.len());
+        assert_eq!(filter.used(), written);
+    }
+}
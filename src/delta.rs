@@ -0,0 +1,268 @@
+//! Slot-level differences between two filters of the same geometry, for
+//! shipping incremental updates to replicas.
+//!
+//! Encoded deltas are little-endian:
+//!
+//! ```text
+//! finger_bits  u8
+//! num_entries  u16
+//! num_buckets  u32
+//! count        u32
+//! writes       count times: slot gap (LEB128), presence u8, old fingerprint,
+//!              new fingerprint
+//! ```
+//!
+//! Bit 0 of the presence byte is set if the slot was occupied before the
+//! write and bit 1 if it is occupied after; the old and new fingerprints are
+//! only stored when the matching bit is set.
+//!
+//! Writes are sorted by slot and each slot is stored as the gap from the
+//! previous one, so the encoding grows with the number of changed slots,
+//! not with the table size.
+
+use crate::Filter;
+
+/// A single slot change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlotWrite {
+    pub bucket: u32,
+    pub entry: u16,
+    /// Fingerprint before the write (None for empty)
+    pub old: Option<u32>,
+    /// Fingerprint after the write (None for empty)
+    pub new: Option<u32>,
+}
+
+/// The slot writes that turn one filter state into another.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FilterDelta {
+    finger_bits: u8,
+    num_entries: u16,
+    num_buckets: u32,
+    writes: Vec<SlotWrite>,
+}
+
+/// Reasons a delta cannot be computed, decoded or applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffError {
+    /// The filters (or filter and delta) differ in fingerprint width,
+    /// bucket count or bucket size.
+    GeometryMismatch,
+    /// A write's `old` value does not match the target slot, so the target
+    /// is not the state the delta was computed against.
+    Conflict { bucket: u32, entry: u16 },
+    /// The encoded delta is malformed.
+    Malformed,
+}
+
+impl FilterDelta {
+    /// Slot writes sorted by bucket and entry.
+    pub fn writes(&self) -> &[SlotWrite] {
+        &self.writes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let width = self.finger_bits.div_ceil(8) as usize;
+        let mut out = Vec::with_capacity(11 + self.writes.len() * (3 + 2 * width));
+        out.push(self.finger_bits);
+        out.extend_from_slice(&self.num_entries.to_le_bytes());
+        out.extend_from_slice(&self.num_buckets.to_le_bytes());
+        out.extend_from_slice(&(self.writes.len() as u32).to_le_bytes());
+        let mut prev = 0u64;
+        for w in &self.writes {
+            let slot = w.bucket as u64 * self.num_entries as u64 + w.entry as u64;
+            write_varint(&mut out, slot - prev);
+            prev = slot;
+            out.push(w.old.is_some() as u8 | (w.new.is_some() as u8) << 1);
+            for finger in w.old.iter().chain(w.new.iter()) {
+                out.extend_from_slice(&finger.to_le_bytes()[.. width]);
+            }
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<FilterDelta, DiffError> {
+        if bytes.len() < 11 {
+            return Err(DiffError::Malformed);
+        }
+        let finger_bits = bytes[0];
+        let num_entries = u16::from_le_bytes([bytes[1], bytes[2]]);
+        let num_buckets = u32::from_le_bytes([bytes[3], bytes[4], bytes[5], bytes[6]]);
+        let count = u32::from_le_bytes([bytes[7], bytes[8], bytes[9], bytes[10]]) as usize;
+        let width = finger_bits.div_ceil(8) as usize;
+        if !(1 ..= 32).contains(&finger_bits) || num_entries == 0 {
+            return Err(DiffError::Malformed);
+        }
+        let capacity = num_buckets as u64 * num_entries as u64;
+        let mut rest = &bytes[11 ..];
+        let mut writes = Vec::with_capacity(count.min(rest.len()));
+        let mut slot = 0u64;
+        for i in 0 .. count {
+            let gap = read_varint(&mut rest).ok_or(DiffError::Malformed)?;
+            if (i > 0 && gap == 0) || rest.is_empty() || rest[0] > 3 {
+                return Err(DiffError::Malformed);
+            }
+            slot = slot.checked_add(gap).filter(|&s| s < capacity).ok_or(DiffError::Malformed)?;
+            let presence = rest[0];
+            rest = &rest[1 ..];
+            let old = read_finger(&mut rest, width, presence & 1 != 0)?;
+            let new = read_finger(&mut rest, width, presence & 2 != 0)?;
+            writes.push(SlotWrite {
+                bucket: (slot / num_entries as u64) as u32,
+                entry: (slot % num_entries as u64) as u16,
+                old,
+                new,
+            });
+        }
+        if !rest.is_empty() {
+            return Err(DiffError::Malformed);
+        }
+        Ok(FilterDelta { finger_bits, num_entries, num_buckets, writes })
+    }
+}
+
+impl Filter {
+    /// Computes the slot writes that transform `older` into `self`. Both
+    /// filters must share the same geometry.
+    pub fn diff(&self, older: &Filter) -> Result<FilterDelta, DiffError> {
+        if !self.same_geometry(older) {
+            return Err(DiffError::GeometryMismatch);
+        }
+        let new = self.buckets.borrow();
+        let old = older.buckets.borrow();
+        let writes = (0 .. new.len())
+            .filter(|&i| new.entry(i) != old.entry(i))
+            .map(|i| {
+                let (bucket, entry) = self.bucket_entry(i);
+                SlotWrite { bucket, entry, old: old.entry(i), new: new.entry(i) }
+            })
+            .collect();
+        Ok(FilterDelta {
+            finger_bits: self.finger_bits,
+            num_entries: self.num_entries,
+            num_buckets: self.num_buckets,
+            writes,
+        })
+    }
+
+    /// Applies a delta produced by `diff`. The filter must be in the state
+    /// the delta was computed against; on a conflict nothing is written.
+    pub fn apply_delta(&self, delta: &FilterDelta) -> Result<(), DiffError> {
+        if delta.finger_bits != self.finger_bits
+            || delta.num_entries != self.num_entries
+            || delta.num_buckets != self.num_buckets {
+            return Err(DiffError::GeometryMismatch);
+        }
+        let max = (1u64 << self.finger_bits) - 1;
+        let mut buckets = self.buckets.borrow_mut();
+        for w in &delta.writes {
+            let min = if self.occupancy_bitmap { 0 } else { 1 };
+            let valid = w.new.is_none_or(|x| x >= min && x as u64 <= max);
+            if w.bucket >= self.num_buckets || w.entry >= self.num_entries || !valid {
+                return Err(DiffError::Malformed);
+            }
+            if buckets.entry(self.slot(w.bucket, w.entry)) != w.old {
+                return Err(DiffError::Conflict { bucket: w.bucket, entry: w.entry });
+            }
+        }
+        let mut used = self.used.borrow_mut();
+        for w in &delta.writes {
+            let i = self.slot(w.bucket, w.entry);
+            match (w.old, w.new) {
+                (None, Some(finger)) => {
+                    buckets.put(i, finger);
+                    *used = used.saturating_add(1);
+                },
+                (Some(_), Some(finger)) => buckets.put(i, finger),
+                (Some(_), None) => {
+                    buckets.remove(i);
+                    *used = used.saturating_sub(1);
+                },
+                (None, None) => {},
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads a `width`-byte fingerprint off the front of `bytes` if `present`.
+fn read_finger(bytes: &mut &[u8], width: usize, present: bool) -> Result<Option<u32>, DiffError> {
+    if !present {
+        return Ok(None);
+    }
+    if bytes.len() < width {
+        return Err(DiffError::Malformed);
+    }
+    let mut b = [0u8; 4];
+    b[.. width].copy_from_slice(&bytes[.. width]);
+    *bytes = &bytes[width ..];
+    Ok(Some(u32::from_le_bytes(b)))
+}
+
+fn write_varint(out: &mut Vec<u8>, mut x: u64) {
+    while x >= 0x80 {
+        out.push(x as u8 | 0x80);
+        x >>= 7;
+    }
+    out.push(x as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut x = 0u64;
+    for (i, &b) in bytes.iter().enumerate().take(10) {
+        x |= ((b & 0x7f) as u64) << (7 * i);
+        if b & 0x80 == 0 {
+            *bytes = &bytes[i + 1 ..];
+            return Some(x);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::config;
+
+    /// A copy of `filter` through its serialized form.
+    fn copy(filter: &Filter) -> Filter {
+        Filter::from_bytes(&filter.to_bytes()).unwrap()
+    }
+
+    #[test]
+    fn delta_turns_a_copy_into_the_mutated_filter() {
+        let base = Filter::new(&config(256)).unwrap();
+        for i in 0 .. 500 {
+            base.insert(&i).unwrap();
+        }
+        let (mutated, replica) = (copy(&base), copy(&base));
+        for i in 500 .. 700 {
+            mutated.insert(&i).unwrap();
+        }
+        for i in (0 .. 500).step_by(3) {
+            assert!(mutated.delete(&i));
+        }
+
+        let delta = FilterDelta::from_bytes(&mutated.diff(&base).unwrap().to_bytes()).unwrap();
+        assert!(!delta.is_empty());
+        replica.apply_delta(&delta).unwrap();
+        assert_eq!(replica.to_bytes(), mutated.to_bytes());
+        assert!(replica.diff(&mutated).unwrap().is_empty());
+
+        // The replica is no longer in the state the delta starts from
+        let w = delta.writes()[0];
+        assert_eq!(replica.apply_delta(&delta), Err(DiffError::Conflict { bucket: w.bucket, entry: w.entry }));
+    }
+
+    #[test]
+    fn diff_rejects_other_geometry() {
+        let a = Filter::new(&config(256)).unwrap();
+        let b = Filter::new(&config(128)).unwrap();
+        assert_eq!(a.diff(&b), Err(DiffError::GeometryMismatch));
+        assert_eq!(FilterDelta::from_bytes(&[16, 4, 0]), Err(DiffError::Malformed));
+    }
+}
@@ -0,0 +1,125 @@
+//! Estimating the number of distinct values stored, as opposed to `used`,
+//! which counts every stored copy.
+//!
+//! Copies of one value share its fingerprint and primary bucket, so the
+//! stored fingerprints, less any generation tag, are grouped by
+//! (fingerprint, primary bucket) and the groups counted. Distinct values
+//! can also share a group by accident; the number of values behind `g`
+//! groups is recovered as for linear counting, assuming uniform hashing
+//! over `num_buckets * fingerprint values` cells:
+//!
+//! ```text
+//! n = ln(1 - g / cells) / ln(1 - 1 / cells)
+//! ```
+//!
+//! Fingerprints sitting in their alternate bucket do not reveal their
+//! primary bucket. The lower bound skips fingerprint values that also have
+//! a primary group (they may be overflow copies) and the upper bound counts
+//! every copy; both are then widened by three standard errors of the linear
+//! counting estimate. The point estimate is halfway between skipping those
+//! values and counting each of them once.
+
+use std::collections::HashSet;
+
+use crate::Filter;
+
+/// Result of `Filter::estimate_distinct`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DistinctEstimate {
+    /// Point estimate of the number of distinct values
+    pub estimate: f64,
+
+    /// Lower bound
+    pub lower: f64,
+
+    /// Upper bound
+    pub upper: f64,
+}
+
+impl Filter {
+    /// Estimates how many distinct values are stored, discounting repeated
+    /// inserts of the same value and correcting for distinct values that
+    /// share a fingerprint and bucket. Assumes uniform hashing.
+    pub fn estimate_distinct(&self) -> DistinctEstimate {
+        let mut primary = HashSet::new();
+        let mut alternate = HashSet::new();
+        let mut alternate_copies = 0u64;
+        let mask = self.key_mask();
+        self.for_each_placement(|finger, bucket| match (finger & mask, bucket) {
+            (finger, Some(bucket)) => {
+                primary.insert((finger, bucket));
+            },
+            (finger, None) => {
+                alternate.insert(finger);
+                alternate_copies += 1;
+            },
+        });
+        // Alternate placements may be overflow copies of a value already
+        // counted in its primary bucket
+        let primary_fingers: HashSet<u32> = primary.iter().map(|&(finger, _)| finger).collect();
+        let orphans = alternate.iter().filter(|finger| !primary_fingers.contains(finger)).count();
+
+        let cells = self.num_buckets as f64 * self.finger_values() as f64;
+        let groups = primary.len() as f64;
+        let lower = invert(groups + orphans as f64, cells);
+        let estimate = (lower + invert(groups + alternate.len() as f64, cells)) / 2.0;
+        let upper = invert(groups + alternate_copies as f64, cells);
+        DistinctEstimate {
+            estimate,
+            lower: (lower - 3.0 * std_error(lower, cells)).max(0.0),
+            upper: upper + 3.0 * std_error(upper, cells),
+        }
+    }
+}
+
+/// Number of values expected to occupy `groups` of `cells` cells.
+fn invert(groups: f64, cells: f64) -> f64 {
+    if groups >= cells {
+        return f64::INFINITY;
+    }
+    (1.0 - groups / cells).ln() / (1.0 - 1.0 / cells).ln()
+}
+
+/// Standard error of the linear counting estimate `n` over `cells` cells.
+fn std_error(n: f64, cells: f64) -> f64 {
+    let t = n / cells;
+    (cells * (t.exp() - t - 1.0)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::tests::config;
+    use crate::Filter;
+
+    #[test]
+    fn bounds_hold_100k_keys_inserted_1_to_5_times() {
+        let filter = Filter::new(&config(1 << 18)).unwrap();
+        let mut rng = StdRng::seed_from_u64(5);
+        for key in 0 .. 100_000u64 {
+            for _ in 0 .. rng.gen_range(1, 6) {
+                // Up to 5 copies in two buckets of 4 entries make some
+                // inserts fail; those drop a fingerprint
+                let _ = filter.insert(&key);
+            }
+        }
+        assert!(filter.used() > 250_000);
+        let d = filter.estimate_distinct();
+        assert!(d.lower <= 100_000.0 && 100_000.0 <= d.upper, "{:?}", d);
+        assert!(d.lower <= d.estimate && d.estimate <= d.upper);
+        assert!((d.estimate - 100_000.0).abs() < 10_000.0, "{:?}", d);
+    }
+
+    #[test]
+    fn distinct_keys_once_each() {
+        let filter = Filter::new(&config(1024)).unwrap();
+        for key in 0 .. 2000 {
+            filter.insert(&key).unwrap();
+        }
+        let d = filter.estimate_distinct();
+        assert!(d.lower <= 2000.0 && 2000.0 <= d.upper, "{:?}", d);
+        assert_eq!(Filter::new(&config(1024)).unwrap().estimate_distinct().estimate, 0.0);
+    }
+}
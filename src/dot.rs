@@ -0,0 +1,111 @@
+//! Graphviz export of the moves kicks can make between buckets, for seeing
+//! why part of the table is congested.
+//!
+//! ```text
+//! digraph eviction {
+//!   b17 [label="17\n2/4"];
+//!   b17 -> b903;
+//!   b903 [label="903", style=dashed];
+//! }
+//! ```
+//!
+//! Each bucket in the range is a node labeled with its index and occupied
+//! entries. Each fingerprint stored there adds an edge to every alternate
+//! bucket a kick could move it to (a loop when that is its own bucket), so
+//! a bucket's out-degree is the number of fingerprints times
+//! `num_hashes - 1`. Buckets outside the range that edges lead to are drawn
+//! dashed, without fill counts. If `Config::trace_kicks` recorded a kick
+//! path, its moves between buckets in the range are added in red.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::ops::Range;
+
+use crate::Filter;
+
+impl Filter {
+    /// Writes the eviction graph of `buckets`, clamped to the table, as a
+    /// Graphviz digraph (see the module docs).
+    pub fn eviction_graph_dot(&self, buckets: Range<u64>) -> String {
+        let range = buckets.start.min(self.num_buckets as u64) as u32 .. buckets.end.min(self.num_buckets as u64) as u32;
+        let entries = self.num_entries as usize;
+        let mut outside = BTreeSet::new();
+        let mut out = String::from("digraph eviction {\n");
+        let slots = self.buckets.borrow();
+        for bucket in range.clone() {
+            let start = bucket as usize * entries;
+            let fingers: Vec<u32> = (start .. start + entries).filter_map(|i| slots.entry(i)).collect();
+            writeln!(out, "  b{} [label=\"{}\\n{}/{}\"];", bucket, bucket, fingers.len(), entries).unwrap();
+            for finger in fingers {
+                for &alt in self.alternates(finger).iter() {
+                    writeln!(out, "  b{} -> b{};", bucket, alt).unwrap();
+                    if !range.contains(&alt) {
+                        outside.insert(alt);
+                    }
+                }
+            }
+        }
+        drop(slots);
+        for bucket in outside {
+            writeln!(out, "  b{} [label=\"{}\", style=dashed];", bucket, bucket).unwrap();
+        }
+        if let Some(path) = self.last_kick_path() {
+            for step in path.windows(2) {
+                let (from, to) = (step[0].bucket, step[1].bucket);
+                if range.contains(&from) && range.contains(&to) {
+                    writeln!(out, "  b{} -> b{} [color=red, penwidth=2];", from, to).unwrap();
+                }
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use crate::tests::config;
+    use crate::{Config, Filter};
+
+    #[test]
+    fn graph_has_a_node_per_bucket_and_an_edge_per_move() {
+        let filter = Filter::new(&Config { num_hashes: 3, trace_kicks: true, ..config(64) }).unwrap();
+        let mut i = 0u64;
+        while filter.last_kick_path().unwrap().len() < 2 {
+            filter.insert(&i).unwrap();
+            i += 1;
+        }
+        let dot = filter.eviction_graph_dot(0 .. 16);
+        let lines: Vec<&str> = dot.lines().collect();
+        assert_eq!((lines[0], *lines.last().unwrap()), ("digraph eviction {", "}"));
+
+        let mut edges = 0;
+        let mut outside = BTreeSet::new();
+        let slots = filter.buckets.borrow();
+        for i in 0 .. 16 * 4 {
+            if let Some(finger) = slots.entry(i) {
+                for &alt in filter.alternates(finger).iter() {
+                    edges += 1;
+                    if alt >= 16 {
+                        outside.insert(alt);
+                    }
+                }
+            }
+        }
+        let count = |f: &dyn Fn(&str) -> bool| lines.iter().filter(|l| f(l)).count();
+        assert_eq!(count(&|l| l.contains("[label=") && !l.contains("dashed")), 16);
+        assert_eq!(count(&|l| l.contains("dashed")), outside.len());
+        assert_eq!(count(&|l| l.contains("->") && !l.contains("color=red")), edges);
+        let path = filter.last_kick_path().unwrap();
+        let red = path.windows(2).filter(|s| s[0].bucket < 16 && s[1].bucket < 16).count();
+        assert_eq!(count(&|l| l.contains("color=red")), red);
+        let whole = filter.eviction_graph_dot(0 .. 64);
+        assert_eq!(whole.lines().filter(|l| l.contains("color=red")).count(), path.len() - 1);
+
+        // The range is clamped to the table
+        let tail = filter.eviction_graph_dot(60 .. 1000);
+        assert_eq!(tail.lines().filter(|l| l.contains("/4\"]")).count(), 4);
+    }
+}
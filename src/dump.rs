@@ -0,0 +1,188 @@
+//! Stable text dump of a filter, for debugging and golden files.
+//!
+//! One item per line: a version line, the configuration that determines
+//! placement, then one line per occupied slot in slot order.
+//!
+//! ```text
+//! cuckoo-filter dump 1
+//! finger_bits 16
+//! num_buckets 1024
+//! num_entries 4
+//! max_swaps 100
+//! hash_count 1
+//! occupancy_bitmap false
+//! generation_bits 0
+//! slot 17 0 40311
+//! slot 17 1 2078
+//! ```
+//!
+//! A `domain` line follows `generation_bits` when the filter has one, and
+//! a `num_hashes` line follows when it is not 2. Slot
+//! lines give bucket, entry and fingerprint in decimal. `used` is the
+//! number of slot lines.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::{Config, Filter};
+
+const DUMP_HEADER: &str = "cuckoo-filter dump 1";
+
+impl Filter {
+    /// Writes the filter in the text dump format (see `from_dump`).
+    pub fn to_dump(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "{}", DUMP_HEADER).unwrap();
+        writeln!(out, "finger_bits {}", self.finger_bits).unwrap();
+        writeln!(out, "num_buckets {}", self.num_buckets).unwrap();
+        writeln!(out, "num_entries {}", self.num_entries).unwrap();
+        writeln!(out, "max_swaps {}", self.max_swaps).unwrap();
+        writeln!(out, "hash_count {}", self.hash_count).unwrap();
+        writeln!(out, "occupancy_bitmap {}", self.occupancy_bitmap).unwrap();
+        writeln!(out, "generation_bits {}", self.generation_bits).unwrap();
+        if let Some(domain) = self.domain {
+            writeln!(out, "domain {}", domain).unwrap();
+        }
+        if self.num_hashes != 2 {
+            writeln!(out, "num_hashes {}", self.num_hashes).unwrap();
+        }
+        let buckets = self.buckets.borrow();
+        for i in 0 .. buckets.len() {
+            if let Some(finger) = buckets.entry(i) {
+                let (bucket, entry) = self.bucket_entry(i);
+                writeln!(out, "slot {} {} {}", bucket, entry, finger).unwrap();
+            }
+        }
+        out
+    }
+
+    /// Parses a dump written by `to_dump`. Every config line is required
+    /// once; slots must be in range, hold valid fingerprints and appear
+    /// once.
+    pub fn from_dump(s: &str) -> Result<Filter, ()> {
+        let mut lines = s.lines();
+        if lines.next() != Some(DUMP_HEADER) {
+            return Err(());
+        }
+        let mut fields = HashMap::new();
+        let mut slots = Vec::new();
+        for line in lines.filter(|line| !line.is_empty()) {
+            let words: Vec<&str> = line.split(' ').collect();
+            match words[..] {
+                ["slot", bucket, entry, finger] => {
+                    slots.push((parse::<u32>(bucket)?, parse::<u16>(entry)?, parse::<u32>(finger)?))
+                },
+                [name, value] => {
+                    if fields.insert(name, value).is_some() {
+                        return Err(());
+                    }
+                },
+                _ => return Err(()),
+            }
+        }
+        let field = |name: &str| fields.get(name).ok_or(());
+        let config = Config {
+            finger_bits: parse(field("finger_bits")?)?,
+            num_buckets: parse(field("num_buckets")?)?,
+            num_entries: parse(field("num_entries")?)?,
+            max_swaps: parse(field("max_swaps")?)?,
+            hash_count: parse(field("hash_count")?)?,
+            occupancy_bitmap: parse(field("occupancy_bitmap")?)?,
+            generation_bits: parse(field("generation_bits")?)?,
+            domain: fields.get("domain").map(|d| parse(d)).transpose()?,
+            num_hashes: fields.get("num_hashes").map(|d| parse(d)).transpose()?.unwrap_or(2),
+            ..Config::default()
+        };
+        if fields.len() != 7 + config.domain.is_some() as usize + fields.contains_key("num_hashes") as usize {
+            // Unknown field
+            return Err(());
+        }
+
+        let filter = Filter::new(&config)?;
+        let lowest = if config.occupancy_bitmap { 0 } else { 1 };
+        let highest = (1u64 << config.finger_bits) - 1;
+        {
+            let mut buckets = filter.buckets.borrow_mut();
+            for &(bucket, entry, finger) in &slots {
+                if bucket >= config.num_buckets || entry >= config.num_entries
+                    || finger < lowest || finger as u64 > highest {
+                    return Err(());
+                }
+                let i = filter.slot(bucket, entry);
+                if buckets.is_occupied(i) {
+                    return Err(());
+                }
+                buckets.put(i, finger);
+            }
+        }
+        filter.used.replace(slots.len() as u64);
+        Ok(filter)
+    }
+}
+
+fn parse<T: FromStr>(s: &str) -> Result<T, ()> {
+    s.parse().map_err(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::config;
+    use crate::{Config, Filter};
+
+    #[test]
+    fn dump_round_trips() {
+        let configs = [
+            config(256),
+            Config { finger_bits: 12, occupancy_bitmap: true, ..config(256) },
+            Config { generation_bits: 4, domain: Some(7), ..config(256) },
+            Config { finger_bits: 32, num_hashes: 3, num_entries: 2, ..config(512) },
+        ];
+        for c in &configs {
+            let filter = Filter::new(c).unwrap();
+            for i in 0 .. 600u64 {
+                filter.insert(&i).unwrap();
+            }
+            let dump = filter.to_dump();
+            let parsed = Filter::from_dump(&dump).unwrap();
+            assert_eq!(parsed.to_dump(), dump);
+            assert_eq!(parsed.to_bytes(), filter.to_bytes());
+            assert_eq!(parsed.used(), 600);
+            assert_eq!(dump.lines().filter(|line| line.starts_with("slot ")).count(), 600);
+            assert!((0 .. 600u64).all(|i| parsed.contains(&i)));
+        }
+    }
+
+    #[test]
+    fn dump_format_is_stable() {
+        let filter = Filter::new(&Config { finger_bits: 8, num_entries: 2, ..config(4) }).unwrap();
+        filter.insert_fingerprint(1, 200).unwrap();
+        filter.insert_fingerprint(3, 9).unwrap();
+        let expected = "cuckoo-filter dump 1\n\
+                        finger_bits 8\n\
+                        num_buckets 4\n\
+                        num_entries 2\n\
+                        max_swaps 100\n\
+                        hash_count 1\n\
+                        occupancy_bitmap false\n\
+                        generation_bits 0\n\
+                        slot 1 0 200\n\
+                        slot 3 0 9\n";
+        assert_eq!(filter.to_dump(), expected);
+    }
+
+    #[test]
+    fn rejects_malformed_dumps() {
+        let dump = Filter::new(&config(4)).unwrap().to_dump();
+        assert!(Filter::from_dump(&dump).is_ok());
+        assert!(Filter::from_dump("").is_err());
+        assert!(Filter::from_dump(&dump.replace("dump 1", "dump 2")).is_err());
+        assert!(Filter::from_dump(&dump.replace("max_swaps 100\n", "")).is_err());
+        assert!(Filter::from_dump(&format!("{}color blue\n", dump)).is_err());
+        assert!(Filter::from_dump(&format!("{}max_swaps 3\n", dump)).is_err());
+        assert!(Filter::from_dump(&format!("{}slot 4 0 1\n", dump)).is_err());
+        assert!(Filter::from_dump(&format!("{}slot 0 0 0\n", dump)).is_err());
+        assert!(Filter::from_dump(&format!("{}slot 0 0 65536\n", dump)).is_err());
+        assert!(Filter::from_dump(&format!("{}slot 0 0 1\nslot 0 0 2\n", dump)).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Entry API for lookup-then-modify sequences that hash the key once.
+
+use std::hash::Hash;
+
+use crate::{Candidates, Filter, FilterOp, InsertError};
+
+/// A key's place in a filter, as returned by `Filter::entry`.
+#[derive(Debug)]
+pub enum Entry<'a> {
+    /// A fingerprint matching the key is stored
+    Occupied(OccupiedEntry<'a>),
+    /// No fingerprint matching the key is stored
+    Vacant(VacantEntry<'a>),
+}
+
+/// A stored fingerprint matching the key.
+#[derive(Debug)]
+pub struct OccupiedEntry<'a> {
+    filter: &'a Filter,
+    h: u64,
+    finger: u32,
+    slot: usize,
+}
+
+/// A key with no matching fingerprint, and the free slot found (if any) in
+/// its candidate buckets.
+#[derive(Debug)]
+pub struct VacantEntry<'a> {
+    filter: &'a Filter,
+    h: u64,
+    finger: u32,
+    candidates: Candidates,
+    empty: Option<usize>,
+}
+
+impl Filter {
+    /// Looks up `x`, computing its fingerprint and candidate buckets once
+    /// for the lookup and any following insert or remove.
+    pub fn entry<T: ?Sized + Hash>(&self, x: &T) -> Entry<'_> {
+        let h = self.hash_key(x);
+        let (finger, candidates) = self.candidates(h);
+        match self.probe(&candidates, finger) {
+            Ok(slot) => Entry::Occupied(OccupiedEntry { filter: self, h, finger, slot }),
+            Err(empty) => Entry::Vacant(VacantEntry { filter: self, h, finger, candidates, empty }),
+        }
+    }
+}
+
+impl<'a> Entry<'a> {
+    pub fn is_occupied(&self) -> bool {
+        matches!(*self, Entry::Occupied(_))
+    }
+
+    /// Inserts the key if vacant; returns whether it was already present.
+    pub fn or_insert(self) -> Result<bool, InsertError> {
+        match self {
+            Entry::Occupied(_) => Ok(true),
+            Entry::Vacant(entry) => entry.insert().map(|_| false),
+        }
+    }
+}
+
+impl<'a> OccupiedEntry<'a> {
+    /// `(bucket, entry)` of the matching fingerprint.
+    pub fn location(&self) -> (u32, u16) {
+        self.filter.bucket_entry(self.slot)
+    }
+
+    /// Removes the matching fingerprint, as `Filter::delete` would.
+    pub fn remove(self) {
+        let filter = self.filter;
+        let stored = filter.buckets.borrow().entry(self.slot);
+        let slot = if stored.map(|x| x & filter.key_mask()) == Some(self.finger) {
+            // The filter may have changed since the lookup
+            Some(self.slot)
+        } else {
+            filter.find(self.h)
+        };
+        if let Some(i) = slot {
+            filter.clear_slot(i);
+        }
+        filter.record_remove(FilterOp::RemoveHash(self.h), slot.is_some());
+    }
+}
+
+impl<'a> VacantEntry<'a> {
+    /// Inserts the key into the free slot found by the lookup, kicking if
+    /// there was none. Returns the number of swaps, as `Filter::insert`.
+    pub fn insert(self) -> Result<u8, InsertError> {
+        let filter = self.filter;
+        let empty = match self.empty {
+            // The filter may have changed since the lookup
+            Some(i) if filter.buckets.borrow().is_occupied(i) => {
+                filter.probe(&self.candidates, self.finger).err().flatten()
+            },
+            empty => empty,
+        };
+        let result = filter.place(self.finger, &self.candidates, empty);
+        filter.record_insert(FilterOp::InsertHash(self.h), result).map(|(swaps, _)| swaps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::hash::Hasher;
+
+    use super::*;
+    use crate::tests::config;
+
+    /// Key counting how often it is hashed.
+    struct Counted<'a>(u64, &'a Cell<u32>);
+
+    impl Hash for Counted<'_> {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.1.set(self.1.get() + 1);
+            self.0.hash(state);
+        }
+    }
+
+    #[test]
+    fn entry_matches_two_call_sequences() {
+        let (by_entry, by_calls) = (Filter::new(&config(128)).unwrap(), Filter::new(&config(128)).unwrap());
+        for i in 0 .. 400u64 {
+            by_entry.entry(&i).or_insert().unwrap();
+            if !by_calls.contains(&i) {
+                by_calls.insert(&i).unwrap();
+            }
+        }
+        for i in (0 .. 400u64).step_by(3) {
+            if let Entry::Occupied(entry) = by_entry.entry(&i) {
+                entry.remove();
+            }
+            if by_calls.contains(&i) {
+                by_calls.delete(&i);
+            }
+        }
+        assert_eq!(by_entry.to_bytes(), by_calls.to_bytes());
+    }
+
+    #[test]
+    fn entry_hashes_once() {
+        let filter = Filter::new(&config(64)).unwrap();
+        let calls = Cell::new(0);
+        let key = Counted(1, &calls);
+        match filter.entry(&key) {
+            Entry::Vacant(entry) => entry.insert().unwrap(),
+            Entry::Occupied(_) => unreachable!(),
+        };
+        match filter.entry(&key) {
+            Entry::Occupied(entry) => {
+                assert_eq!(Some(entry.location()), filter.locate(&1u64));
+                entry.remove();
+            },
+            Entry::Vacant(_) => unreachable!(),
+        }
+        // One hash per entry, against two per lookup-then-modify pair
+        assert_eq!(calls.get(), 2);
+        assert_eq!(filter.used(), 0);
+    }
+}
@@ -0,0 +1,1000 @@
+//! Capacity-planning experiments: insert a stream of keys into a fresh
+//! filter and summarize how the inserts fared.
+
+use std::collections::HashSet;
+use std::fmt::{self, Debug};
+use std::hash::Hash;
+use std::mem;
+use std::time::Instant;
+
+use rand::distributions::Alphanumeric;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+#[cfg(feature = "testkit")]
+use crate::testkit::{self, DeleteAuditReport, DeleteViolation, Op};
+use crate::{Config, Filter, Layout, Rounding};
+
+/// What to run: the filter to build and how many random words to insert.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExperimentConfig {
+    pub filter: Config,
+    pub keys: usize,
+}
+
+impl Default for ExperimentConfig {
+    fn default() -> ExperimentConfig {
+        ExperimentConfig {
+            filter: Config {
+                finger_bits: 16,    //    16      8      8     8     8
+                num_buckets: 10000, // 20000  20000  10000  5000  4000
+                num_entries: 100,   //    50     50    100   200   250
+                max_swaps: 99,
+                ..Config::default()
+            },
+            keys: 990000,
+        }
+    }
+}
+
+/// Outcome of an experiment.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExperimentReport {
+    /// Configuration of the filter, including the seed used
+    pub config: Config,
+    pub load_factor: f64,
+    pub bits: u64,
+    pub bits_per_item: f64,
+    pub summary: Summary,
+    /// Number of buckets by occupied entries
+    pub occupancy_histogram: Vec<u64>,
+}
+
+/// Insert outcomes, counted as they happen.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Summary {
+    pub success: u64,
+    pub failure: u64,
+    /// Total swaps, counting `max_swaps` for each failure
+    pub swaps: u64,
+    /// Index of the first failed insert (0 if none failed)
+    pub first_failure: usize,
+    /// Number of inserts by swap count
+    pub swap_histogram: Vec<usize>,
+}
+
+/// Inserts `config.keys` random words (see `random_words`) into a new
+/// filter built from `config.filter`. The words and the filter's kicks
+/// are drawn from `config.filter.seed`, or from a random seed recorded in
+/// the report, so any run can be repeated.
+pub fn run(config: &ExperimentConfig) -> Result<ExperimentReport, ()> {
+    benchmark_with(&config.filter, &mut Words, config.keys)
+}
+
+/// Inserts `n` keys from `keygen` into a new filter built from `config`,
+/// seeding both as `run` does.
+pub fn benchmark_with<K: KeyGen>(config: &Config, keygen: &mut K, n: usize) -> Result<ExperimentReport, ()> {
+    let seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let mut rng = StdRng::seed_from_u64(seed);
+    let filter = Config { seed: Some(seed), ..config.clone() };
+    run_with_keys(&filter, keygen.keys(&mut rng, n))
+}
+
+/// Generates the keys of an experiment.
+pub trait KeyGen {
+    type Key: Hash;
+
+    /// `n` keys, drawing any randomness from `rng`.
+    fn keys<R: Rng>(&mut self, rng: &mut R, n: usize) -> Vec<Self::Key>;
+}
+
+/// Distinct words as made by `random_words`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Words;
+
+impl KeyGen for Words {
+    type Key = String;
+
+    fn keys<R: Rng>(&mut self, rng: &mut R, n: usize) -> Vec<String> {
+        random_words(rng, n)
+    }
+}
+
+/// Consecutive integers, continuing where the previous call stopped, as
+/// with auto-incremented ids.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sequential {
+    pub next: u64,
+}
+
+impl KeyGen for Sequential {
+    type Key = u64;
+
+    fn keys<R: Rng>(&mut self, _rng: &mut R, n: usize) -> Vec<u64> {
+        let start = self.next;
+        self.next += n as u64;
+        (start .. self.next).collect()
+    }
+}
+
+/// Random 128-bit ids in the hyphenated hex form of UUIDs, e.g.
+/// `3f2504e0-4f89-11d3-9a0c-0305e82c3301`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UuidLike;
+
+impl KeyGen for UuidLike {
+    type Key = String;
+
+    fn keys<R: Rng>(&mut self, rng: &mut R, n: usize) -> Vec<String> {
+        (0 .. n)
+            .map(|_| {
+                let x: u128 = rng.gen();
+                let hex = format!("{:032x}", x);
+                format!("{}-{}-{}-{}-{}", &hex[.. 8], &hex[8 .. 12], &hex[12 .. 16], &hex[16 .. 20], &hex[20 ..])
+            })
+            .collect()
+    }
+}
+
+/// Keys picked with knowledge of the hash so that every candidate bucket
+/// of each lies among the first `buckets` buckets of the filter they
+/// target, as an attacker able to predict the hashes would. Once those
+/// buckets fill, inserts kick within them until they fail, long before
+/// the rest of the filter is used.
+///
+/// Found by hashing random integers and keeping those that qualify, which
+/// takes about `(num_buckets / buckets) ^ num_hashes` tries per key. A
+/// filter whose `Config::domain` differs from the target's (a secret
+/// domain acting as a hash key) places them like any other keys.
+#[derive(Debug)]
+pub struct Adversarial {
+    /// Built from the targeted configuration, for its hashing
+    target: Filter,
+    buckets: u32,
+}
+
+impl Adversarial {
+    /// Generator of keys crowding into the first `buckets` buckets of a
+    /// filter built from `target`. Fails if `target` is invalid or
+    /// `buckets` is 0 or more than the filter has.
+    pub fn new(target: &Config, buckets: u32) -> Result<Adversarial, ()> {
+        // Sparse, as only the hashing is used
+        let target = Filter::new(&Config { sparse_until: Some(1.0), seed: Some(0), ..target.clone() })?;
+        if buckets == 0 || buckets > target.num_buckets {
+            return Err(());
+        }
+        Ok(Adversarial { target, buckets })
+    }
+}
+
+impl KeyGen for Adversarial {
+    type Key = u64;
+
+    fn keys<R: Rng>(&mut self, rng: &mut R, n: usize) -> Vec<u64> {
+        let mut keys = Vec::with_capacity(n);
+        while keys.len() < n {
+            let x: u64 = rng.gen();
+            let (_, candidates) = self.target.candidates(self.target.hash_key(&x));
+            if candidates.iter().all(|&b| b < self.buckets) {
+                keys.push(x);
+            }
+        }
+        keys
+    }
+}
+
+/// Inserts `keys` in order into a new filter built from `config`.
+pub fn run_with_keys<I>(config: &Config, keys: I) -> Result<ExperimentReport, ()>
+    where I: IntoIterator, I::Item: Hash {
+    let f = Filter::new(config)?;
+    let mut summary = Summary::new(config.max_swaps as usize + 1);
+    f.insert_many_monitored(keys, |i, result| match result {
+        Ok(swaps) => summary.update(i, true, swaps as u64),
+        Err(_) => summary.update(i, false, config.max_swaps as u64),
+    });
+    let mut occupancy_histogram = vec![0; config.num_entries as usize + 1];
+    for (_, used) in f.hottest_buckets(usize::MAX) {
+        occupancy_histogram[used as usize] += 1;
+    }
+    Ok(ExperimentReport {
+        config: f.config(),
+        load_factor: f.load_factor(),
+        bits: f.bits(),
+        bits_per_item: f.bits_per_item(),
+        summary,
+        occupancy_histogram,
+    })
+}
+
+/// `n` distinct words made of 4 random alphanumerics and a sequence number,
+/// in random order.
+pub fn random_words<R>(rng: &mut R, n: usize) -> Vec<String> where R: Rng {
+    let mut vec = Vec::with_capacity(n);
+    for i in 0 .. n {
+        let s = rand_string(rng, 4);
+        vec.push(format!("{}_{}", s, i));
+    }
+    vec.shuffle(rng);
+    vec
+}
+
+impl Summary {
+    pub fn new(bins: usize) -> Summary {
+        Summary {
+            success: 0,
+            failure: 0,
+            swaps: 0,
+            first_failure: 0,
+            swap_histogram: vec![0; bins],
+        }
+    }
+
+    /// Records the outcome of the `i`th insert.
+    pub fn update(&mut self, i: usize, status: bool, swaps: u64) {
+        self.swaps += swaps;
+        self.swap_histogram[swaps as usize] += 1;
+        if status {
+            self.success += 1;
+        } else {
+            if self.failure == 0 {
+                self.first_failure = i;
+            }
+            self.failure += 1;
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "first_failure : {}", self.first_failure)?;
+        writeln!(f, "success       : {:8}", self.success)?;
+        writeln!(f, "failure       : {:8}", self.failure)?;
+        writeln!(f, "swaps         : {:8}", self.swaps)?;
+        for (i, x) in self.swap_histogram.iter().enumerate() {
+            writeln!(f, "{:2} {:8}", i, *x)?;
+        }
+        Ok(())
+    }
+}
+
+impl ExperimentReport {
+    /// The report as one JSON document, with the crate version. Field
+    /// names are kept stable for tools that track results over time.
+    pub fn to_json(&self) -> String {
+        let s = &self.summary;
+        let results = json_object(&[
+            ("load_factor", json_f64(self.load_factor)),
+            ("bits", self.bits.to_string()),
+            ("bits_per_item", json_f64(self.bits_per_item)),
+            ("success", s.success.to_string()),
+            ("failure", s.failure.to_string()),
+            ("swaps", s.swaps.to_string()),
+            ("first_failure", s.first_failure.to_string()),
+        ]);
+        json_object(&[
+            ("version", format!("\"{}\"", env!("CARGO_PKG_VERSION"))),
+            ("config", config_json(&self.config)),
+            ("results", results),
+            ("swap_histogram", format!("{:?}", s.swap_histogram)),
+            ("occupancy_histogram", format!("{:?}", self.occupancy_histogram)),
+        ])
+    }
+}
+
+/// The fields of `c` as a JSON object.
+fn config_json(c: &Config) -> String {
+    let layout = match c.layout {
+        Layout::RowMajor => "\"row_major\"",
+        Layout::ColumnMajor => "\"column_major\"",
+    };
+    let bucket_rounding = match c.bucket_rounding {
+        Rounding::Exact => "\"exact\"",
+        Rounding::NextPowerOfTwo => "\"next_power_of_two\"",
+        Rounding::NextPrime => "\"next_prime\"",
+    };
+    json_object(&[
+        ("finger_bits", c.finger_bits.to_string()),
+        ("num_buckets", c.num_buckets.to_string()),
+        ("bucket_rounding", bucket_rounding.to_string()),
+        ("num_entries", c.num_entries.to_string()),
+        ("max_swaps", c.max_swaps.to_string()),
+        ("track_queries", c.track_queries.to_string()),
+        ("hash_count", c.hash_count.to_string()),
+        ("num_hashes", c.num_hashes.to_string()),
+        ("occupancy_bitmap", c.occupancy_bitmap.to_string()),
+        ("generation_bits", c.generation_bits.to_string()),
+        ("domain", c.domain.map_or("null".to_string(), |domain| domain.to_string())),
+        ("sparse_until", c.sparse_until.map_or("null".to_string(), json_f64)),
+        ("trace_kicks", c.trace_kicks.to_string()),
+        ("layout", layout.to_string()),
+        ("huge_pages", c.huge_pages.to_string()),
+        ("lazy_pages", c.lazy_pages.to_string()),
+        ("seed", c.seed.map_or("null".to_string(), |seed| seed.to_string())),
+    ])
+}
+
+/// JSON object with the given members, whose values are already encoded.
+fn json_object(members: &[(&str, String)]) -> String {
+    let members: Vec<String> = members.iter().map(|(name, value)| format!("\"{}\":{}", name, value)).collect();
+    format!("{{{}}}", members.join(","))
+}
+
+/// `x` as a JSON number; null if not finite.
+fn json_f64(x: f64) -> String {
+    if x.is_finite() {
+        x.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+impl fmt::Display for ExperimentReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(seed) = self.config.seed {
+            writeln!(f, "seed          : {}", seed)?;
+        }
+        writeln!(f, "load_factor   : {}", self.load_factor)?;
+        writeln!(f, "bits          : {}", self.bits)?;
+        writeln!(f, "bits_per_key  : {}", self.bits_per_item)?;
+        write!(f, "{}", self.summary)
+    }
+}
+
+/// Operations timed by `measure_steady_state`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SteadyOps {
+    /// Inserts of new keys, each after an untimed delete of the oldest
+    /// key so the load stays put
+    Inserts,
+    /// Lookups, alternating between stored and absent keys
+    Lookups,
+    /// Inserts and lookups, alternating
+    Mixed,
+}
+
+/// What `measure_steady_state` runs: fill `filter` to `warm_load` without
+/// timing, then time `ops` operations of the kind `mix` says.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SteadyStateConfig {
+    pub filter: Config,
+    pub warm_load: f64,
+    pub ops: usize,
+    pub mix: SteadyOps,
+}
+
+/// Outcome of `measure_steady_state`. Only the steady phase is timed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SteadyStateReport {
+    /// Configuration of the filter, including the seed used
+    pub config: Config,
+    pub mix: SteadyOps,
+    /// Inserts made during warmup
+    pub warmup_inserts: u64,
+    /// Load factor at the end of warmup, where timing starts; below
+    /// `warm_load` if an insert failed first
+    pub warm_load_factor: f64,
+    pub steady_inserts: u64,
+    pub steady_lookups: u64,
+    /// Failed inserts in the steady phase
+    pub steady_failures: u64,
+    /// Swaps per steady-phase insert, counting `max_swaps` for failures
+    pub mean_swaps: f64,
+    pub ops_per_sec: f64,
+    /// Latency of steady-phase operations in nanoseconds: 50th, 90th and
+    /// 99th percentile and maximum
+    pub latency_ns: [u64; 4],
+}
+
+/// Fills a new filter built from `config.filter` with random keys up to
+/// `config.warm_load` (or the first failed insert), then times
+/// `config.ops` operations one by one. Keys are drawn from
+/// `config.filter.seed`, or from a random seed recorded in the report.
+/// Fails if the filter cannot be built or `warm_load` is not within
+/// [0, 1].
+pub fn measure_steady_state(config: &SteadyStateConfig) -> Result<SteadyStateReport, ()> {
+    if !(0.0 ..= 1.0).contains(&config.warm_load) {
+        return Err(());
+    }
+    let seed = config.filter.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let mut rng = StdRng::seed_from_u64(seed);
+    let filter = Config { seed: Some(seed), ..config.filter.clone() };
+    let f = Filter::new(&filter)?;
+    let max_swaps = filter.max_swaps;
+
+    // Keys stored, oldest first from `oldest`
+    let mut stored = Vec::new();
+    let target = (config.warm_load * f.capacity() as f64) as u64;
+    while f.used() < target {
+        let key: u64 = rng.gen();
+        if f.insert(&key).is_err() {
+            break;
+        }
+        stored.push(key);
+    }
+    let warmup_inserts = stored.len() as u64;
+    let warm_load_factor = f.load_factor();
+
+    let mut oldest = 0;
+    let (mut inserts, mut lookups, mut failures, mut swaps) = (0u64, 0u64, 0u64, 0u64);
+    let mut latencies = Vec::with_capacity(config.ops);
+    let started = Instant::now();
+    for op in 0 .. config.ops {
+        let insert_op = match config.mix {
+            SteadyOps::Inserts => true,
+            SteadyOps::Lookups => false,
+            SteadyOps::Mixed => op % 2 == 0,
+        };
+        if insert_op {
+            if oldest < stored.len() {
+                f.delete(&stored[oldest]);
+                oldest += 1;
+            }
+            let key: u64 = rng.gen();
+            let start = Instant::now();
+            let (status, n) = insert(&f, &key, max_swaps);
+            latencies.push(start.elapsed());
+            inserts += 1;
+            swaps += n;
+            if status {
+                stored.push(key);
+            } else {
+                failures += 1;
+            }
+        } else {
+            let key = match stored.len() - oldest {
+                live if live > 0 && lookups % 2 == 0 => stored[oldest + rng.gen_range(0, live)],
+                _ => rng.gen(),
+            };
+            let start = Instant::now();
+            f.contains(&key);
+            latencies.push(start.elapsed());
+            lookups += 1;
+        }
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+
+    latencies.sort_unstable();
+    let percentile = |p: f64| {
+        latencies.get(((latencies.len() as f64 * p) as usize).min(latencies.len().saturating_sub(1)))
+            .map_or(0, |d| d.as_nanos() as u64)
+    };
+    Ok(SteadyStateReport {
+        config: f.config(),
+        mix: config.mix,
+        warmup_inserts,
+        warm_load_factor,
+        steady_inserts: inserts,
+        steady_lookups: lookups,
+        steady_failures: failures,
+        mean_swaps: if inserts > 0 { swaps as f64 / inserts as f64 } else { 0.0 },
+        ops_per_sec: config.ops as f64 / elapsed,
+        latency_ns: [percentile(0.5), percentile(0.9), percentile(0.99), percentile(1.0)],
+    })
+}
+
+impl SteadyStateReport {
+    /// The report as one JSON document, with the crate version, the
+    /// warmup and steady phases as separate objects.
+    pub fn to_json(&self) -> String {
+        let mix = match self.mix {
+            SteadyOps::Inserts => "\"inserts\"",
+            SteadyOps::Lookups => "\"lookups\"",
+            SteadyOps::Mixed => "\"mixed\"",
+        };
+        let warmup = json_object(&[
+            ("inserts", self.warmup_inserts.to_string()),
+            ("load_factor", json_f64(self.warm_load_factor)),
+        ]);
+        let [p50, p90, p99, max] = self.latency_ns;
+        let steady = json_object(&[
+            ("mix", mix.to_string()),
+            ("inserts", self.steady_inserts.to_string()),
+            ("lookups", self.steady_lookups.to_string()),
+            ("failures", self.steady_failures.to_string()),
+            ("mean_swaps", json_f64(self.mean_swaps)),
+            ("ops_per_sec", json_f64(self.ops_per_sec)),
+            ("latency_ns", json_object(&[
+                ("p50", p50.to_string()),
+                ("p90", p90.to_string()),
+                ("p99", p99.to_string()),
+                ("max", max.to_string()),
+            ])),
+        ]);
+        json_object(&[
+            ("version", format!("\"{}\"", env!("CARGO_PKG_VERSION"))),
+            ("config", config_json(&self.config)),
+            ("warmup", warmup),
+            ("steady", steady),
+        ])
+    }
+}
+
+impl fmt::Display for SteadyStateReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(seed) = self.config.seed {
+            writeln!(f, "seed          : {}", seed)?;
+        }
+        writeln!(f, "warmup        : {} inserts (untimed)", self.warmup_inserts)?;
+        writeln!(f, "warm_load     : {}", self.warm_load_factor)?;
+        writeln!(f, "steady        : {:?}, {} inserts, {} lookups", self.mix, self.steady_inserts, self.steady_lookups)?;
+        writeln!(f, "failures      : {}", self.steady_failures)?;
+        writeln!(f, "mean_swaps    : {}", self.mean_swaps)?;
+        writeln!(f, "ops_per_sec   : {:.0}", self.ops_per_sec)?;
+        let [p50, p90, p99, max] = self.latency_ns;
+        writeln!(f, "latency_ns    : p50 {} p90 {} p99 {} max {}", p50, p90, p99, max)
+    }
+}
+
+/// One structure measured by `compare_hashset`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BaselineRow {
+    pub name: &'static str,
+    /// Bytes held, estimated for the sets from their capacity, entry size
+    /// and the strings' heap allocations
+    pub memory_bytes: usize,
+    pub build_secs: f64,
+    /// Lookups per second over present and absent keys alike
+    pub lookups_per_sec: f64,
+    /// Share of absent keys reported present
+    pub false_positive_rate: f64,
+}
+
+/// Outcome of `compare_hashset`: the filter first, then the sets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BaselineReport {
+    /// Configuration of the filter, including the seed used
+    pub config: Config,
+    pub keys: usize,
+    pub rows: Vec<BaselineRow>,
+}
+
+/// Runs the workload of `run` against the filter, a `HashSet<String>` of
+/// the words and a `HashSet<u64>` of their hashes: builds each from
+/// `config.keys` random words, then looks up those words and as many
+/// absent ones. Failed filter inserts are ignored, so their keys may be
+/// missed.
+pub fn compare_hashset(config: &ExperimentConfig) -> Result<BaselineReport, ()> {
+    let seed = config.filter.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let mut rng = StdRng::seed_from_u64(seed);
+    let filter = Filter::new(&Config { seed: Some(seed), ..config.filter.clone() })?;
+    let present = random_words(&mut rng, config.keys);
+    // The suffix keeps these distinct from `present`
+    let absent: Vec<String> = random_words(&mut rng, config.keys).into_iter()
+        .map(|word| format!("{}_absent", word))
+        .collect();
+    let lookups = (2 * config.keys) as f64;
+    let per_sec = |secs: f64| if secs > 0.0 { lookups / secs } else { f64::INFINITY };
+    let rate = |hits: usize| if config.keys > 0 { hits as f64 / config.keys as f64 } else { 0.0 };
+
+    let started = Instant::now();
+    for word in &present {
+        let _ = filter.insert(word);
+    }
+    let build_secs = started.elapsed().as_secs_f64();
+    let started = Instant::now();
+    present.iter().for_each(|word| { filter.contains(word); });
+    let hits = absent.iter().filter(|word| filter.contains(word)).count();
+    let filter_row = BaselineRow {
+        name: "cuckoo filter",
+        memory_bytes: filter.memory_usage(),
+        build_secs,
+        lookups_per_sec: per_sec(started.elapsed().as_secs_f64()),
+        false_positive_rate: rate(hits),
+    };
+
+    let started = Instant::now();
+    let strings: HashSet<String> = present.iter().cloned().collect();
+    let build_secs = started.elapsed().as_secs_f64();
+    let started = Instant::now();
+    present.iter().for_each(|word| { strings.contains(word); });
+    let hits = absent.iter().filter(|word| strings.contains(*word)).count();
+    let strings_row = BaselineRow {
+        name: "HashSet<String>",
+        memory_bytes: set_bytes(&strings) + strings.iter().map(String::capacity).sum::<usize>(),
+        build_secs,
+        lookups_per_sec: per_sec(started.elapsed().as_secs_f64()),
+        false_positive_rate: rate(hits),
+    };
+
+    let started = Instant::now();
+    let hashes: HashSet<u64> = present.iter().map(|word| filter.hash_key(word)).collect();
+    let build_secs = started.elapsed().as_secs_f64();
+    let started = Instant::now();
+    present.iter().for_each(|word| { hashes.contains(&filter.hash_key(word)); });
+    let hits = absent.iter().filter(|word| hashes.contains(&filter.hash_key(word))).count();
+    let hashes_row = BaselineRow {
+        name: "HashSet<u64>",
+        memory_bytes: set_bytes(&hashes),
+        build_secs,
+        lookups_per_sec: per_sec(started.elapsed().as_secs_f64()),
+        false_positive_rate: rate(hits),
+    };
+
+    Ok(BaselineReport { config: filter.config(), keys: config.keys, rows: vec![filter_row, strings_row, hashes_row] })
+}
+
+/// Bytes of a set's table: a slot and a control byte per unit of
+/// capacity, plus the set itself.
+fn set_bytes<T>(set: &HashSet<T>) -> usize {
+    mem::size_of::<HashSet<T>>() + set.capacity() * (mem::size_of::<T>() + 1)
+}
+
+impl BaselineReport {
+    /// The report as one JSON document, with the crate version.
+    pub fn to_json(&self) -> String {
+        let rows: Vec<String> = self.rows.iter()
+            .map(|row| json_object(&[
+                ("name", format!("\"{}\"", row.name)),
+                ("memory_bytes", row.memory_bytes.to_string()),
+                ("build_secs", json_f64(row.build_secs)),
+                ("lookups_per_sec", json_f64(row.lookups_per_sec)),
+                ("false_positive_rate", json_f64(row.false_positive_rate)),
+            ]))
+            .collect();
+        json_object(&[
+            ("version", format!("\"{}\"", env!("CARGO_PKG_VERSION"))),
+            ("config", config_json(&self.config)),
+            ("keys", self.keys.to_string()),
+            ("baseline", format!("[{}]", rows.join(","))),
+        ])
+    }
+}
+
+impl fmt::Display for BaselineReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(seed) = self.config.seed {
+            writeln!(f, "seed          : {}", seed)?;
+        }
+        writeln!(f, "keys          : {}", self.keys)?;
+        writeln!(f, "{:<16} {:>14} {:>12} {:>16} {:>10}", "structure", "memory_bytes", "build_secs", "lookups_per_sec", "fp_rate")?;
+        for row in &self.rows {
+            writeln!(f, "{:<16} {:>14} {:>12.4} {:>16.0} {:>10.6}",
+                row.name, row.memory_bytes, row.build_secs, row.lookups_per_sec, row.false_positive_rate)?;
+        }
+        Ok(())
+    }
+}
+
+/// Steps of `sweep_fp` from empty to full, 5% load each.
+const SWEEP_STEPS: u64 = 20;
+
+/// False positive rate measured by `sweep_fp` at one load.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FpSweepRow {
+    pub load_factor: f64,
+    /// Share of the absent probe words reported present
+    pub observed_fp: f64,
+    /// `Filter::estimated_fpp` at this load
+    pub predicted_fp: f64,
+}
+
+/// Outcome of `sweep_fp`, one row per step, by increasing load.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FpSweepReport {
+    /// Configuration of the filter, including the seed used
+    pub config: Config,
+    /// Number of absent words probed at each step
+    pub probes: usize,
+    pub rows: Vec<FpSweepRow>,
+}
+
+/// Fills one filter built from `config.filter` with random words in steps
+/// of 5% load and after each step looks up the same `config.keys` absent
+/// words, comparing the share found with `Filter::estimated_fpp`. Stops
+/// at the first failed insert, with a last row at the load reached.
+pub fn sweep_fp(config: &ExperimentConfig) -> Result<FpSweepReport, ()> {
+    let seed = config.filter.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let mut rng = StdRng::seed_from_u64(seed);
+    let filter = Filter::new(&Config { seed: Some(seed), ..config.filter.clone() })?;
+    let present = random_words(&mut rng, filter.capacity() as usize);
+    // The suffix keeps these distinct from `present`
+    let absent: Vec<String> = random_words(&mut rng, config.keys).into_iter()
+        .map(|word| format!("{}_absent", word))
+        .collect();
+    let mut present = present.iter();
+    let mut rows = Vec::new();
+    let mut failed = false;
+    for step in 1 ..= SWEEP_STEPS {
+        let goal = filter.capacity() * step / SWEEP_STEPS;
+        while filter.used() < goal {
+            match present.next() {
+                Some(word) if filter.insert(word).is_ok() => {},
+                _ => {
+                    failed = true;
+                    break;
+                },
+            }
+        }
+        let hits = absent.iter().filter(|word| filter.contains(word)).count();
+        rows.push(FpSweepRow {
+            load_factor: filter.load_factor(),
+            observed_fp: if absent.is_empty() { 0.0 } else { hits as f64 / absent.len() as f64 },
+            predicted_fp: filter.estimated_fpp(),
+        });
+        if failed {
+            break;
+        }
+    }
+    Ok(FpSweepReport { config: filter.config(), probes: config.keys, rows })
+}
+
+impl FpSweepReport {
+    /// The report as one JSON document, with the crate version.
+    pub fn to_json(&self) -> String {
+        let rows: Vec<String> = self.rows.iter()
+            .map(|row| json_object(&[
+                ("load_factor", json_f64(row.load_factor)),
+                ("observed_fp", json_f64(row.observed_fp)),
+                ("predicted_fp", json_f64(row.predicted_fp)),
+            ]))
+            .collect();
+        json_object(&[
+            ("version", format!("\"{}\"", env!("CARGO_PKG_VERSION"))),
+            ("config", config_json(&self.config)),
+            ("probes", self.probes.to_string()),
+            ("sweep", format!("[{}]", rows.join(","))),
+        ])
+    }
+}
+
+impl fmt::Display for FpSweepReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(seed) = self.config.seed {
+            writeln!(f, "seed          : {}", seed)?;
+        }
+        writeln!(f, "probes        : {}", self.probes)?;
+        writeln!(f, "{:>11} {:>12} {:>12}", "load_factor", "observed_fp", "predicted_fp")?;
+        for row in &self.rows {
+            writeln!(f, "{:>11.4} {:>12.6} {:>12.6}", row.load_factor, row.observed_fp, row.predicted_fp)?;
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of `audit_deletes`.
+#[cfg(feature = "testkit")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeleteAuditRun {
+    /// Configuration of the filter, including the seed used
+    pub config: Config,
+    pub ops: usize,
+    pub result: Result<DeleteAuditReport, DeleteViolation>,
+}
+
+/// Runs `testkit::audit_deletes` for `ops` ops on a filter built from
+/// `config`, with its seed or a random one recorded in the run. Fails if
+/// the filter cannot be built.
+#[cfg(feature = "testkit")]
+pub fn audit_deletes(config: &Config, ops: usize) -> Result<DeleteAuditRun, ()> {
+    let seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let config = Filter::new(&Config { seed: Some(seed), ..config.clone() })?.config();
+    let result = testkit::audit_deletes(seed, &config, ops);
+    Ok(DeleteAuditRun { config, ops, result })
+}
+
+#[cfg(feature = "testkit")]
+impl DeleteAuditRun {
+    /// The run as one JSON document, with the crate version. A violation
+    /// lists the ops replaying it as `["insert", key]` or `["delete", key]`.
+    pub fn to_json(&self) -> String {
+        let outcome = match self.result {
+            Ok(ref r) => json_object(&[
+                ("inserts", r.inserts.to_string()),
+                ("deletes", r.deletes.to_string()),
+                ("checked", r.checked.to_string()),
+                ("absent_deletes", r.absent_deletes.to_string()),
+                ("unsafe_absent_deletes", r.unsafe_absent_deletes.to_string()),
+                ("failed_inserts", r.failed_inserts.to_string()),
+            ]),
+            Err(ref v) => {
+                let ops: Vec<String> = v.ops.iter()
+                    .map(|op| match *op {
+                        Op::Insert(key) => format!("[\"insert\",{}]", key),
+                        Op::Delete(key) => format!("[\"delete\",{}]", key),
+                        Op::Contains(key) => format!("[\"contains\",{}]", key),
+                    })
+                    .collect();
+                json_object(&[
+                    ("op", v.op.to_string()),
+                    ("deleted", v.deleted.to_string()),
+                    ("missing", v.missing.to_string()),
+                    ("ops", format!("[{}]", ops.join(","))),
+                ])
+            },
+        };
+        json_object(&[
+            ("version", format!("\"{}\"", env!("CARGO_PKG_VERSION"))),
+            ("config", config_json(&self.config)),
+            ("ops", self.ops.to_string()),
+            (if self.result.is_ok() { "report" } else { "violation" }, outcome),
+        ])
+    }
+}
+
+#[cfg(feature = "testkit")]
+impl fmt::Display for DeleteAuditRun {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(seed) = self.config.seed {
+            writeln!(f, "seed          : {}", seed)?;
+        }
+        writeln!(f, "ops           : {}", self.ops)?;
+        match self.result {
+            Ok(ref r) => {
+                writeln!(f, "inserts       : {} ({} failed, rolled back)", r.inserts, r.failed_inserts)?;
+                writeln!(f, "deletes       : {}", r.deletes)?;
+                writeln!(f, "checked       : {}", r.checked)?;
+                writeln!(f, "absent        : {} deletes skipped, {} of them unsafe", r.absent_deletes, r.unsafe_absent_deletes)?;
+                writeln!(f, "violations    : none")
+            },
+            Err(ref v) => {
+                writeln!(f, "violation     : delete of {} at op {} lost {}", v.deleted, v.op, v.missing)?;
+                writeln!(f, "replay        :")?;
+                for (i, op) in v.ops.iter().enumerate() {
+                    writeln!(f, "{:>13} : {:?}", i, op)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+fn insert<T: ?Sized + Hash>(f: &Filter, x: &T, max_swaps: u8) -> (bool, u64) {
+    match f.insert(x) {
+        Ok(swaps) => (true, swaps as u64),
+        Err(_) => (false, max_swaps as u64),
+    }
+}
+
+fn rand_string<R>(rng: &mut R, k: usize) -> String where R: Rng {
+    rng.sample_iter(&Alphanumeric).take(k).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A run small enough for unit tests: 5000 words into 1024 buckets of
+    /// 4 entries.
+    fn tiny() -> ExperimentConfig {
+        ExperimentConfig {
+            filter: Config { num_buckets: 1024, num_entries: 4, seed: Some(1), ..Config::default() },
+            keys: 5000,
+        }
+    }
+
+    #[test]
+    fn tiny_run_reports_outcomes() {
+        let report = run(&tiny()).unwrap();
+        let s = &report.summary;
+        assert_eq!(s.success + s.failure, 5000);
+        assert_eq!(s.success, (report.load_factor * 4096.0).round() as u64);
+        assert!(s.failure > 0 && s.first_failure > 3000, "{}", s);
+        assert_eq!(s.swap_histogram.iter().sum::<usize>(), 5000);
+        assert_eq!(report.occupancy_histogram.len(), 5);
+        assert_eq!(report.occupancy_histogram.iter().sum::<u64>(), 1024);
+        assert_eq!(report.config.seed, Some(1));
+        assert_eq!(run(&tiny()).unwrap(), report);
+    }
+
+    #[test]
+    fn unseeded_run_records_its_seed() {
+        let unseeded = ExperimentConfig { filter: Config { seed: None, ..tiny().filter }, ..tiny() };
+        let report = run(&unseeded).unwrap();
+        let seeded = ExperimentConfig { filter: report.config.clone(), ..tiny() };
+        assert_eq!(run(&seeded).unwrap(), report);
+    }
+
+    #[test]
+    fn wide_buckets_fill_nearly_full() {
+        let config = Config { num_buckets: 16, num_entries: 1024, seed: Some(1), ..Config::default() };
+        let report = run(&ExperimentConfig { filter: config, keys: 16_000 }).unwrap();
+        let s = &report.summary;
+        assert_eq!((s.success, s.failure), (16_000, 0), "{}", s);
+        let h = &report.occupancy_histogram;
+        assert_eq!(h.len(), 1025);
+        assert_eq!(h.iter().sum::<u64>(), 16);
+        assert_eq!(h.iter().enumerate().map(|(used, &n)| used as u64 * n).sum::<u64>(), 16_000);
+    }
+
+    #[test]
+    fn benchmark_with_integer_keys() {
+        let mut keys = Sequential::default();
+        let report = benchmark_with(&tiny().filter, &mut keys, 2000).unwrap();
+        let s = &report.summary;
+        assert_eq!((s.success, s.failure), (2000, 0));
+        assert_eq!(report.load_factor, 2000.0 / 4096.0);
+        assert_eq!(report.occupancy_histogram.iter().sum::<u64>(), 1024);
+        assert_eq!(keys.next, 2000);
+        // The next run continues with new keys
+        let report = benchmark_with(&tiny().filter, &mut keys, 10).unwrap();
+        assert_eq!(report.summary.success, 10);
+        assert_eq!(keys.next, 2010);
+    }
+
+    #[test]
+    fn uuid_like_keys() {
+        let keys = UuidLike.keys(&mut StdRng::seed_from_u64(1), 100);
+        assert!(keys.iter().all(|k| k.len() == 36 && k.split('-').map(str::len).eq(vec![8, 4, 4, 4, 12])));
+        let report = benchmark_with(&tiny().filter, &mut UuidLike, 2000).unwrap();
+        assert_eq!(report.summary.success, 2000);
+    }
+
+    #[test]
+    fn adversarial_keys_fail_early_unless_keyed() {
+        let target = tiny().filter;
+        let keys = Adversarial::new(&target, 64).unwrap().keys(&mut StdRng::seed_from_u64(1), 1000);
+        let attacked = run_with_keys(&target, &keys).unwrap().summary;
+        // 64 buckets of 4 entries hold at most 256 keys
+        assert!(attacked.failure > 0 && attacked.first_failure <= 256, "{}", attacked);
+        let uniform = run_with_keys(&target, Sequential::default().keys(&mut StdRng::seed_from_u64(1), 1000)).unwrap();
+        assert_eq!(uniform.summary.failure, 0);
+        let keyed = Config { domain: Some(0x5eed), ..target.clone() };
+        assert_eq!(run_with_keys(&keyed, &keys).unwrap().summary.failure, 0);
+        assert!(Adversarial::new(&target, 0).is_err() && Adversarial::new(&target, 1025).is_err());
+    }
+
+    #[test]
+    fn fp_sweep_rises_with_load() {
+        let config = ExperimentConfig {
+            filter: Config { finger_bits: 8, num_buckets: 256, num_entries: 4, seed: Some(1), ..Config::default() },
+            keys: 20_000,
+        };
+        let report = sweep_fp(&config).unwrap();
+        let rows = &report.rows;
+        assert!(rows.len() >= 10, "{}", rows.len());
+        assert_eq!(report.probes, 20_000);
+        assert!(rows.windows(2).all(|w| w[0].load_factor < w[1].load_factor));
+        assert!(rows.windows(2).all(|w| w[0].predicted_fp <= w[1].predicted_fp));
+        assert!(rows.windows(2).all(|w| w[0].observed_fp <= w[1].observed_fp), "{:?}", rows);
+        assert!(rows.last().unwrap().observed_fp > 0.0);
+    }
+
+    /// Fields of `ExperimentReport::to_json` that tools rely on.
+    #[derive(serde::Deserialize)]
+    struct ReportJson {
+        version: String,
+        config: ConfigJson,
+        results: ResultsJson,
+        swap_histogram: Vec<usize>,
+        occupancy_histogram: Vec<u64>,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct ConfigJson {
+        finger_bits: u8,
+        num_buckets: u32,
+        num_entries: u16,
+        max_swaps: u8,
+        layout: String,
+        domain: Option<u64>,
+        seed: Option<u64>,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct ResultsJson {
+        load_factor: f64,
+        bits: u64,
+        bits_per_item: f64,
+        success: u64,
+        failure: u64,
+        swaps: u64,
+        first_failure: usize,
+    }
+
+    #[test]
+    fn json_report_has_required_fields() {
+        let report = run(&tiny()).unwrap();
+        let json: ReportJson = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json.version, env!("CARGO_PKG_VERSION"));
+        let c = &json.config;
+        assert_eq!((c.finger_bits, c.num_buckets, c.num_entries, c.max_swaps), (16, 1024, 4, 100));
+        assert_eq!((c.layout.as_str(), c.domain, c.seed), ("row_major", None, Some(1)));
+        let r = &json.results;
+        let s = &report.summary;
+        assert_eq!((r.success, r.failure, r.swaps, r.first_failure), (s.success, s.failure, s.swaps, s.first_failure));
+        assert_eq!((r.load_factor, r.bits, r.bits_per_item), (report.load_factor, report.bits, report.bits_per_item));
+        assert_eq!(json.swap_histogram, s.swap_histogram);
+        assert_eq!(json.occupancy_histogram, report.occupancy_histogram);
+    }
+}
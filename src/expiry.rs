@@ -0,0 +1,125 @@
+//! Expiry through generation tags, for cache-like use.
+//!
+//! With `Config::generation_bits` set, the top bits of each stored
+//! fingerprint hold the generation at which it expires, modulo
+//! `2^generation_bits`. Lookups, deletes and alternate buckets only look at
+//! the bits below the tag, so a value is found whatever its generation.
+//!
+//! Generations wrap, so tags are compared as serial numbers: a tag has
+//! expired once the current generation is at or less than half the tag
+//! space past it. TTLs must therefore stay below `2^(generation_bits - 1)`,
+//! and `sweep` must run at least once per that many generations or expired
+//! fingerprints start looking fresh again.
+
+use std::hash::Hash;
+
+use crate::{Filter, FilterOp, InsertError};
+
+impl Filter {
+    /// Inserts `x` to expire `ttl` generations after `current_gen`: the
+    /// first `sweep` at or past generation `current_gen + ttl` removes it.
+    /// Plain inserts are tagged with generation 0 and expire like any
+    /// other, so a filter with generation bits should only be filled
+    /// through this method.
+    ///
+    /// Panics unless `Config::generation_bits` is set and `ttl` is below
+    /// `2^(generation_bits - 1)`.
+    pub fn insert_with_ttl<T: ?Sized + Hash>(&self, x: &T, current_gen: u32, ttl: u32)
+        -> Result<u8, InsertError> {
+        self.insert_hash_with_ttl(self.hash_key(x), current_gen, ttl)
+    }
+
+    /// `insert_with_ttl` for a value given by its hash (see `Filter::hash_key`).
+    pub fn insert_hash_with_ttl(&self, h: u64, current_gen: u32, ttl: u32) -> Result<u8, InsertError> {
+        assert!(self.generation_bits > 0, "filter has no generation bits");
+        assert!(ttl < self.generation_window(), "ttl too long for generation_bits");
+        let tag = current_gen.wrapping_add(ttl) & self.generation_mask();
+        self.insert_slot(h, tag).map(|(swaps, _)| swaps)
+    }
+
+    /// Removes every fingerprint that has expired by `current_gen` and
+    /// returns how many were removed. Does nothing without generation
+    /// bits. The observer is not notified.
+    pub fn sweep(&self, current_gen: u32) -> u64 {
+        if self.generation_bits == 0 {
+            return 0;
+        }
+        let key_bits = self.finger_bits - self.generation_bits;
+        let mut removed = 0;
+        {
+            let mut buckets = self.buckets.borrow_mut();
+            for i in 0 .. buckets.len() {
+                match buckets.entry(i) {
+                    Some(x) if self.expired(x >> key_bits, current_gen) => {
+                        buckets.remove(i);
+                        removed += 1;
+                    },
+                    _ => {},
+                }
+            }
+        }
+        self.used.replace_with(|&mut x| x.saturating_sub(removed));
+        self.record_op(FilterOp::Sweep(current_gen));
+        removed
+    }
+
+    /// True if generation `tag` is at or before `current_gen`.
+    fn expired(&self, tag: u32, current_gen: u32) -> bool {
+        current_gen.wrapping_sub(tag) & self.generation_mask() < self.generation_window()
+    }
+
+    fn generation_mask(&self) -> u32 {
+        (1 << self.generation_bits) - 1
+    }
+
+    /// Number of generations a tag stays fresh for at most.
+    fn generation_window(&self) -> u32 {
+        (1 << self.generation_bits) >> 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::config;
+    use crate::{Config, Filter};
+
+    #[test]
+    fn sweep_removes_only_expired_generations() {
+        let filter = Filter::new(&Config { generation_bits: 4, ..config(1024) }).unwrap();
+        // Key i expires at generation 10 + i % 5
+        for i in 0 .. 2000u64 {
+            filter.insert_with_ttl(&i, 10, (i % 5) as u32).unwrap();
+        }
+        assert_eq!(filter.sweep(9), 0);
+        for gen in 10 .. 15u32 {
+            assert_eq!(filter.sweep(gen), 400, "generation {}", gen);
+            assert_eq!(filter.used(), 2000 - 400 * u64::from(gen - 9));
+            for i in 0 .. 2000u64 {
+                // Later keys may still be reported through a colliding
+                // fingerprint, never missed
+                if 10 + i % 5 > u64::from(gen) {
+                    assert!(filter.contains(&i), "{} missing at generation {}", i, gen);
+                }
+            }
+        }
+        assert_eq!(filter.checksum(), 0);
+    }
+
+    #[test]
+    fn generations_wrap() {
+        let filter = Filter::new(&Config { generation_bits: 4, ..config(64) }).unwrap();
+        filter.insert_with_ttl(&1, 14, 3).unwrap();
+        assert_eq!(filter.sweep(15), 0);
+        assert_eq!(filter.sweep(0), 0);
+        assert_eq!(filter.sweep(1), 1);
+        assert!(!filter.contains(&1));
+    }
+
+    #[test]
+    fn sweep_without_generation_bits_is_noop() {
+        let filter = Filter::new(&config(64)).unwrap();
+        filter.insert(&1).unwrap();
+        assert_eq!(filter.sweep(100), 0);
+        assert!(filter.contains(&1));
+    }
+}
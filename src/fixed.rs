@@ -0,0 +1,179 @@
+//! Filters of fixed geometry that hold no heap memory, for code that must
+//! not allocate once running.
+//!
+//! `StaticFilter` keeps its slots in an inline array sized by const
+//! generics, and takes the random number generator for kicks from the
+//! caller. Fingerprints and candidate buckets come from the same `Scheme`
+//! as `Filter`'s, so a value lands in the same buckets as in a `Filter`
+//! with 8-bit fingerprints, `BUCKETS` buckets and `ENTRIES` entries and
+//! otherwise default settings.
+//!
+//! This is not `no_std` support: the crate needs `std`, and keys are hashed
+//! with the standard library's `DefaultHasher` as `Filter`'s are, so
+//! `StaticFilter` only builds where `std` does.
+
+use std::hash::Hash;
+use std::mem;
+
+use rand::Rng;
+
+use crate::kick::{self, KickSlots, Walk};
+use crate::{domain_hash, Candidates, InsertError, Scheme};
+
+/// Cuckoo filter of `BUCKETS` buckets of `ENTRIES` 8-bit fingerprints,
+/// stored inline.
+#[derive(Clone, Debug)]
+pub struct StaticFilter<const BUCKETS: usize, const ENTRIES: usize> {
+    slots: [[u8; ENTRIES]; BUCKETS],
+    scheme: Scheme,
+    max_swaps: u8,
+    used: usize,
+}
+
+impl<const BUCKETS: usize, const ENTRIES: usize> StaticFilter<BUCKETS, ENTRIES> {
+    /// Rejects geometries `Filter` could not have, at compile time.
+    const VALID: () = assert!(
+        BUCKETS > 0 && BUCKETS <= u32::MAX as usize && ENTRIES > 0 && ENTRIES <= u16::MAX as usize,
+        "StaticFilter needs 1 to u32::MAX buckets of 1 to u16::MAX entries"
+    );
+
+    /// An empty filter giving up on an insert after `max_swaps` kicks.
+    pub const fn new(max_swaps: u8) -> Self {
+        let () = Self::VALID;
+        let scheme = Scheme {
+            num_buckets: BUCKETS as u32,
+            hash_count: 1,
+            num_hashes: 2,
+            occupancy_bitmap: false,
+            finger_bits: 8,
+            tag_bits: 0,
+        };
+        StaticFilter { slots: [[0; ENTRIES]; BUCKETS], scheme, max_swaps, used: 0 }
+    }
+
+    /// Inserts `x`, kicking with `rng` when both candidate buckets are
+    /// full, and returns the swaps made. Kicks follow the same walk as
+    /// `Filter::insert`'s, and fail the same way: `Full` means the
+    /// fingerprint relocated last was dropped.
+    pub fn insert<T: ?Sized + Hash, R: Rng + ?Sized>(&mut self, x: &T, rng: &mut R) -> Result<u8, InsertError> {
+        let (finger, index) = self.finger_index(x);
+        let candidates = self.scheme.candidates(index, finger);
+        let swaps = if candidates.iter().any(|&b| self.try_insert(b, finger).is_some()) {
+            0
+        } else if BUCKETS == 1 {
+            return Err(InsertError::Degenerate);
+        } else {
+            match kick::walk(self, rng, finger, &candidates, self.max_swaps) {
+                Walk::Placed(swaps, _) => swaps,
+                Walk::Cycle(..) => return Err(InsertError::Cycle),
+                Walk::Full(_) => return Err(InsertError::Full),
+            }
+        };
+        self.used += 1;
+        Ok(swaps)
+    }
+
+    /// True if `x` may have been inserted.
+    pub fn contains<T: ?Sized + Hash>(&self, x: &T) -> bool {
+        self.find(x).is_some()
+    }
+
+    /// Removes one copy of `x`, returning whether one was found. As with
+    /// `Filter::delete`, `x` must have been inserted.
+    pub fn remove<T: ?Sized + Hash>(&mut self, x: &T) -> bool {
+        match self.find(x) {
+            Some((bucket, entry)) => {
+                self.slots[bucket as usize][entry] = 0;
+                self.used -= 1;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Number of fingerprints stored.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn capacity(&self) -> usize {
+        BUCKETS * ENTRIES
+    }
+
+    fn finger_index<T: ?Sized + Hash>(&self, x: &T) -> (u8, u32) {
+        self.scheme.finger_index::<u8>(domain_hash(None, x))
+    }
+
+    /// Bucket and entry of the first fingerprint matching `x`, primary
+    /// bucket first.
+    fn find<T: ?Sized + Hash>(&self, x: &T) -> Option<(u32, usize)> {
+        let (finger, index) = self.finger_index(x);
+        self.scheme.candidates(index, finger).iter().find_map(|&b| {
+            self.slots[b as usize].iter().position(|&f| f == finger).map(|entry| (b, entry))
+        })
+    }
+}
+
+impl<const BUCKETS: usize, const ENTRIES: usize> KickSlots<u8> for StaticFilter<BUCKETS, ENTRIES> {
+    fn num_entries(&self) -> u16 {
+        ENTRIES as u16
+    }
+
+    fn alternates(&self, finger: u8) -> Candidates {
+        self.scheme.alternates(finger)
+    }
+
+    fn get(&self, bucket: u32, entry: u16) -> Option<u8> {
+        Some(self.slots[bucket as usize][entry as usize]).filter(|&f| f != 0)
+    }
+
+    fn try_insert(&mut self, bucket: u32, finger: u8) -> Option<usize> {
+        let entry = self.slots[bucket as usize].iter().position(|&f| f == 0)?;
+        self.slots[bucket as usize][entry] = finger;
+        Some(bucket as usize * ENTRIES + entry)
+    }
+
+    fn swap(&mut self, bucket: u32, entry: u16, finger: u8) -> u8 {
+        mem::replace(&mut self.slots[bucket as usize][entry as usize], finger)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::StaticFilter;
+    use crate::InsertError;
+
+    #[test]
+    fn stack_filter_membership() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut filter = StaticFilter::<256, 4>::new(100);
+        let n = 500u64;
+        for i in 0 .. n {
+            filter.insert(&i, &mut rng).unwrap();
+        }
+        assert_eq!(filter.used(), n as usize);
+        assert!((0 .. n).all(|i| filter.contains(&i)));
+        for i in (0 .. n).step_by(2) {
+            assert!(filter.remove(&i));
+        }
+        assert_eq!(filter.used(), n as usize / 2);
+        assert!((1 .. n).step_by(2).all(|i| filter.contains(&i)));
+        for i in (1 .. n).step_by(2) {
+            assert!(filter.remove(&i));
+        }
+        assert_eq!(filter.used(), 0);
+    }
+
+    #[test]
+    fn full_tiny_filter_detects_cycle() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut filter = StaticFilter::<2, 2>::new(100);
+        let failed = (0 .. 16u64).map(|i| filter.insert(&i, &mut rng)).find_map(Result::err);
+        assert_eq!(failed, Some(InsertError::Cycle));
+        assert_eq!(filter.used(), 4);
+        assert_eq!(StaticFilter::<1, 4>::new(100).insert(&0, &mut rng).ok(), Some(0));
+    }
+}
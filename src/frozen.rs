@@ -0,0 +1,197 @@
+//! Read-only filters served straight from a memory-mapped file written by
+//! `Filter::to_bytes`, behind the `memmap2` feature. Opening costs one
+//! header read regardless of size; pages are loaded as lookups touch them.
+
+use std::fs::File;
+use std::hash::Hash;
+use std::io;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::serialize::{fnv1a, invalid_data, read_u16, read_u64, CHECKSUM_LEN, HEADER_LEN, MAX_GENERATION_BITS};
+use crate::word::FingerprintWord;
+use crate::{domain_hash, Config, Scheme, CONFIG_HEADER_LEN, FORMAT_VERSION, MAX_HASHES};
+
+/// A filter that answers `contains` from a mapped file and cannot be
+/// modified.
+#[derive(Debug)]
+pub struct FrozenFilter {
+    map: Mmap,
+    config: Config,
+    scheme: Scheme,
+    used: u64,
+}
+
+impl FrozenFilter {
+    /// Maps a file holding `Filter::to_bytes` output of the current
+    /// `FORMAT_VERSION`. The header and length are validated; the checksum
+    /// is not, as that would read the whole file (see `verify_checksum`).
+    /// The file must not be modified while mapped.
+    pub fn open_mmap(path: &Path) -> io::Result<FrozenFilter> {
+        let file = File::open(path)?;
+        // SAFETY: the mapping is read-only and every access is bounds
+        // checked against the length validated below. Concurrent writes to
+        // the file are the caller's responsibility, as documented.
+        let map = unsafe { Mmap::map(&file)? };
+        let config = Config::from_header(&map).map_err(|_| invalid_data("bad header"))?;
+        if read_u16(&map, 4) != FORMAT_VERSION {
+            return Err(invalid_data("old format version; convert with Filter::from_bytes"));
+        }
+        let valid = (1 ..= 32).contains(&config.finger_bits)
+            && config.num_buckets > 0
+            && config.num_entries > 0
+            && config.slot_count().is_some()
+            && (1 ..= 2).contains(&config.hash_count)
+            && (2 ..= MAX_HASHES).contains(&(config.num_hashes as usize))
+            && config.generation_bits != 1
+            && config.generation_bits <= config.finger_bits / 2
+            && config.generation_bits <= MAX_GENERATION_BITS;
+        if !valid || map.len() < HEADER_LEN {
+            return Err(invalid_data("unsupported config"));
+        }
+        let capacity = config.num_buckets as u64 * config.num_entries as u64;
+        let frozen = FrozenFilter {
+            used: read_u64(&map, CONFIG_HEADER_LEN),
+            scheme: Scheme {
+                num_buckets: config.num_buckets,
+                hash_count: config.hash_count,
+                num_hashes: config.num_hashes,
+                occupancy_bitmap: config.occupancy_bitmap,
+                finger_bits: config.finger_bits,
+                tag_bits: config.generation_bits,
+            },
+            config,
+            map,
+        };
+        if frozen.map.len() != frozen.bitmap_start() + frozen.bitmap_len() + CHECKSUM_LEN
+            || frozen.used > capacity {
+            return Err(invalid_data("inconsistent payload"));
+        }
+        Ok(frozen)
+    }
+
+    /// Reads the whole file and checks its trailing checksum.
+    pub fn verify_checksum(&self) -> bool {
+        let split = self.map.len() - CHECKSUM_LEN;
+        fnv1a(&self.map[.. split]) == read_u64(&self.map, split)
+    }
+
+    pub fn contains<T: ?Sized + Hash>(&self, x: &T) -> bool {
+        self.contains_hash(domain_hash(self.config.domain, x))
+    }
+
+    /// `contains` for a value given by its hash (see `Filter::hash_key`).
+    pub fn contains_hash(&self, h: u64) -> bool {
+        match self.config.finger_bits {
+            1 ..= 8 => self.contains_word::<u8>(h),
+            9 ..= 16 => self.contains_word::<u16>(h),
+            _ => self.contains_word::<u32>(h),
+        }
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.config.num_buckets as u64 * self.config.num_entries as u64
+    }
+
+    /// Configuration stored in the file header.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn contains_word<W: FingerprintWord>(&self, h: u64) -> bool {
+        let (finger, idx_1) = self.scheme.finger_index::<W>(h);
+        self.scheme.candidates(idx_1, finger).iter().any(|&b| self.bucket_holds::<W>(b, finger))
+    }
+
+    /// True if `bucket` holds `finger` with any generation tag.
+    fn bucket_holds<W: FingerprintWord>(&self, bucket: u32, finger: W) -> bool {
+        let entries = self.config.num_entries as usize;
+        let start = bucket as usize * entries;
+        (start .. start + entries).any(|i| {
+            self.entry(i).is_some_and(|x| self.scheme.untag(W::from_u64(x as u64)) == finger)
+        })
+    }
+
+    /// Fingerprint in slot `i`, or None if the slot is empty.
+    fn entry(&self, i: usize) -> Option<u32> {
+        let width = self.width();
+        let at = HEADER_LEN + i * width;
+        let mut b = [0u8; 4];
+        b[.. width].copy_from_slice(&self.map[at .. at + width]);
+        let finger = u32::from_le_bytes(b);
+        if self.config.occupancy_bitmap {
+            let word = read_u64(&self.map, self.bitmap_start() + i / 64 * 8);
+            Some(finger).filter(|_| word & (1 << (i % 64)) != 0)
+        } else {
+            Some(finger).filter(|&x| x != 0)
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.config.finger_bits.div_ceil(8) as usize
+    }
+
+    fn bitmap_start(&self) -> usize {
+        HEADER_LEN + self.capacity() as usize * self.width()
+    }
+
+    fn bitmap_len(&self) -> usize {
+        if self.config.occupancy_bitmap {
+            (self.capacity() as usize).div_ceil(64) * 8
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::FrozenFilter;
+    use crate::tests::config;
+    use crate::{Config, Filter};
+
+    /// Path of a scratch file unique to this process and `name`.
+    fn scratch(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("cuckoo-frozen-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn saved_filter_answers_from_mapping() {
+        for &(finger_bits, occupancy_bitmap) in &[(8, false), (12, true), (16, false), (32, true)] {
+            let filter = Filter::new(&Config { finger_bits, occupancy_bitmap, ..config(1024) }).unwrap();
+            for i in 0 .. 2000u64 {
+                filter.insert(&i).unwrap();
+            }
+            let path = scratch(&format!("{}", finger_bits));
+            filter.save(&path).unwrap();
+            let frozen = FrozenFilter::open_mmap(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert!(frozen.verify_checksum());
+            assert_eq!(frozen.used(), 2000);
+            assert_eq!(frozen.capacity(), filter.capacity());
+            assert_eq!(frozen.config().finger_bits, finger_bits);
+            assert!((0 .. 2000u64).all(|i| frozen.contains(&i)));
+            assert!((2000 .. 100_000u64).all(|i| frozen.contains(&i) == filter.contains(&i)));
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let filter = Filter::new(&config(64)).unwrap();
+        filter.insert(&1).unwrap();
+        let bytes = filter.to_bytes();
+        let path = scratch("truncated");
+        fs::write(&path, &bytes[.. bytes.len() - 1]).unwrap();
+        let err = FrozenFilter::open_mmap(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
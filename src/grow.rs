@@ -0,0 +1,141 @@
+//! Growing a filter on demand.
+//!
+//! Fingerprints cannot move to a larger bucket count, since the keys that
+//! would say which new bucket each belongs in are gone. Buckets get more
+//! entries instead: both candidate buckets of every value stay the same,
+//! so fingerprints keep their bucket and entry and nothing is rehashed.
+//! Each doubling of the entries doubles the false positive rate.
+
+use std::hash::Hash;
+
+use crate::{Buckets, Config, Filter, InsertError};
+
+impl Filter {
+    /// Inserts `x`, and if the filter is full, doubles the entries per
+    /// bucket (or grows as far as `max_capacity` allows) and retries once.
+    /// A failed attempt drops nothing. Fails with `CapacityExceeded` if
+    /// the filter cannot grow within `max_capacity` (or past 65535 entries),
+    /// or `Full`, `Cycle` or `Degenerate` if the retry does not fit either.
+    ///
+    /// Growing converts sparse storage to dense and is not recorded in the
+    /// op log, so replicas need a fresh snapshot. An open checkpoint stays
+    /// open; restoring it keeps the new size.
+    pub fn insert_grow<T: ?Sized + Hash>(&mut self, x: &T, max_capacity: u64) -> Result<u8, InsertError> {
+        let h = self.hash_key(x);
+        match self.or_roll_back(|| self.insert_slot(h, 0)) {
+            Ok((swaps, _)) => return Ok(swaps),
+            Err(InsertError::Full) | Err(InsertError::Cycle) | Err(InsertError::Degenerate) => {},
+            Err(e) => return Err(e),
+        }
+        self.grow_entries(max_capacity)?;
+        self.or_roll_back(|| self.insert_slot(h, 0)).map(|(swaps, _)| swaps)
+    }
+
+    /// Doubles `num_entries`, or grows it as far as `max_capacity` allows,
+    /// and notifies the observer.
+    fn grow_entries(&mut self, max_capacity: u64) -> Result<(), InsertError> {
+        let budget = max_capacity / self.num_buckets as u64;
+        let num_entries = (self.num_entries as u64 * 2).min(budget).min(u16::MAX as u64) as u16;
+        if num_entries <= self.num_entries {
+            return Err(InsertError::CapacityExceeded);
+        }
+        let config = Config { num_entries, huge_pages: self.huge_pages, lazy_pages: self.lazy_pages, ..self.config() };
+        let n = config.slot_count().ok_or(InsertError::CapacityExceeded)?;
+
+        let (old, new) = (self.num_entries as usize, num_entries as usize);
+        let moved = |i: usize| i / old * new + i % old;
+        let buckets = self.buckets.get_mut();
+        let mut grown = Buckets::dense(&config, n);
+        for i in 0 .. buckets.len() {
+            if let Some(finger) = buckets.entry(i) {
+                grown.put(moved(i), finger);
+            }
+        }
+        grown.journal = buckets.journal.take()
+            .map(|journal| journal.into_iter().map(|(i, old)| (moved(i), old)).collect());
+        *buckets = grown;
+        self.num_entries = num_entries;
+
+        let (old_capacity, new_capacity) = (self.num_buckets as u64 * old as u64, self.capacity());
+        if let Some(ref observer) = self.observer {
+            observer.on_grow(old_capacity, new_capacity);
+        }
+        #[cfg(feature = "tracing")]
+        tracing::info!(old_capacity, new_capacity, load_factor = self.load_factor(), "grew filter");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use crate::observer::tests::{Counter, Counts};
+    use crate::tests::config;
+    use crate::{Config, Filter, InsertError};
+
+    #[test]
+    fn inserts_past_capacity_within_budget() {
+        let counts = Arc::new(Counts::default());
+        let mut filter = Filter::new(&config(16)).unwrap();
+        filter.set_observer(Box::new(Counter(counts.clone())));
+        for i in 0 .. 2000u64 {
+            filter.insert_grow(&i, 1 << 12).unwrap();
+        }
+        assert_eq!(filter.used(), 2000);
+        assert!((0 .. 2000u64).all(|i| filter.contains(&i)));
+        assert!(filter.capacity() <= 1 << 12);
+        assert!(counts.grows.load(Ordering::Relaxed) >= 5);
+        assert_eq!(counts.grown_to.load(Ordering::Relaxed), filter.capacity());
+        assert_eq!(counts.inserts.load(Ordering::Relaxed), 2000);
+    }
+
+    #[test]
+    fn growth_keeps_lazy_pages() {
+        let mut filter = Filter::new(&Config { lazy_pages: true, ..config(1024) }).unwrap();
+        let capacity = filter.capacity();
+        let mut next = 0u64;
+        while filter.capacity() == capacity {
+            filter.insert_grow(&next, 1 << 20).unwrap();
+            next += 1;
+        }
+        let pages = filter.allocated_pages().unwrap();
+        assert!(pages > 0 && pages as u64 <= filter.capacity() / 4096);
+        assert!((0 .. next).all(|i| filter.contains(&i)));
+    }
+
+    #[test]
+    fn budget_caps_growth() {
+        let mut filter = Filter::new(&config(16)).unwrap();
+        let mut result = Ok(0);
+        for i in 0 .. 1000u64 {
+            result = filter.insert_grow(&i, 128);
+            if result.is_err() {
+                break;
+            }
+        }
+        assert_eq!(result, Err(InsertError::CapacityExceeded));
+        assert_eq!(filter.capacity(), 128);
+    }
+
+    #[test]
+    fn hovering_at_the_boundary_grows_once() {
+        let mut filter = Filter::new(&config(16)).unwrap();
+        let mut next = 0u64;
+        while filter.insert(&next).is_ok() {
+            next += 1;
+        }
+        let mut resizes = 0;
+        for _ in 0 .. 50 {
+            let capacity = filter.capacity();
+            next += 1;
+            filter.insert_grow(&next, 1 << 20).unwrap();
+            if filter.capacity() != capacity {
+                resizes += 1;
+            }
+            assert!(filter.delete(&next));
+        }
+        assert_eq!(resizes, 1);
+    }
+}
@@ -0,0 +1,76 @@
+//! Transparent huge page hints for large bucket arrays (see
+//! `Config::huge_pages`). Lookups touch two random buckets, so on
+//! gigabyte-scale filters most of them miss the TLB with 4 KiB pages.
+
+/// Asks the kernel to back the page-aligned interior of `slots` with huge
+/// pages. A hint only: failures are ignored and the memory stays usable.
+#[cfg(target_os = "linux")]
+pub(crate) fn advise<T>(slots: &mut [T]) {
+    use std::ffi::c_void;
+    use std::mem;
+
+    const PAGE: usize = 4096;
+    const MADV_HUGEPAGE: i32 = 14;
+
+    extern "C" {
+        fn madvise(addr: *mut c_void, len: usize, advice: i32) -> i32;
+    }
+
+    let start = slots.as_mut_ptr() as usize;
+    let end = start + mem::size_of_val(slots);
+    let aligned = (start + PAGE - 1) & !(PAGE - 1);
+    if end <= aligned {
+        return;
+    }
+    let len = (end - aligned) & !(PAGE - 1);
+    if len == 0 {
+        return;
+    }
+    // SAFETY: the range lies within `slots`, and MADV_HUGEPAGE only changes
+    // how the kernel backs it, not its contents.
+    let _result = unsafe { madvise(aligned as *mut c_void, len, MADV_HUGEPAGE) };
+    #[cfg(feature = "tracing")]
+    if _result != 0 {
+        tracing::debug!(len, "huge page advice rejected");
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn advise<T>(_slots: &mut [T]) {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::tests::{check_membership, config};
+    use crate::{Config, Filter};
+
+    #[test]
+    fn huge_pages_change_nothing_but_backing() {
+        let (plain, huge) = (config(1 << 18), Config { huge_pages: true, ..config(1 << 18) });
+        check_membership(&Filter::new(&huge).unwrap(), 100_000);
+        let (a, b) = (Filter::new(&plain).unwrap(), Filter::new(&huge).unwrap());
+        for i in 0 .. 100_000 {
+            a.insert(&i).unwrap();
+            b.insert(&i).unwrap();
+        }
+        assert_eq!(a.to_bytes(), b.to_bytes());
+        assert_eq!(a.memory_usage(), b.memory_usage());
+    }
+
+    /// Lookup throughput with and without huge pages on a 512 MiB filter.
+    /// Run with `cargo test --release -- --ignored --nocapture`; the
+    /// advice is silently ignored where huge pages are unsupported.
+    #[test]
+    #[ignore]
+    fn huge_pages_lookup_throughput() {
+        for &huge_pages in &[false, true] {
+            let filter = Filter::new(&Config { huge_pages, ..config(1 << 26) }).unwrap();
+            filter.fill_random(0.5);
+            let start = Instant::now();
+            let hits = (0 .. 10_000_000u64).filter(|i| filter.contains(i)).count();
+            let elapsed = start.elapsed();
+            println!("huge_pages {}: {:.1} ns per lookup ({} hits)", huge_pages, elapsed.as_nanos() as f64 / 1e7, hits);
+        }
+    }
+}
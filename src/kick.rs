@@ -0,0 +1,140 @@
+//! The kick walk that places a fingerprint when all of its candidate
+//! buckets are full, shared by `Filter` and `StaticFilter`.
+//!
+//! The walk evicts a random entry of a candidate bucket, moves the evicted
+//! fingerprint to a free entry of one of its alternates if there is one,
+//! and otherwise carries on from an alternate other than the bucket just
+//! left. It never evicts the fingerprint it just placed, and gives up
+//! early once the buckets it can reach are all full.
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::word::FingerprintWord;
+use crate::Candidates;
+
+/// Most buckets `trapped` explores before giving up.
+const TRAP_SEARCH_BUCKETS: usize = 8;
+
+/// Slots a kick walk moves fingerprints between.
+pub(crate) trait KickSlots<W: FingerprintWord> {
+    fn num_entries(&self) -> u16;
+
+    /// Buckets a fingerprint evicted from a bucket may move to.
+    fn alternates(&self, finger: W) -> Candidates;
+
+    /// Fingerprint in `entry` of `bucket`, if any.
+    fn get(&self, bucket: u32, entry: u16) -> Option<W>;
+
+    /// Stores `finger` in a free entry of `bucket`, returning its slot.
+    fn try_insert(&mut self, bucket: u32, finger: W) -> Option<usize>;
+
+    /// Replaces the fingerprint in `entry` of `bucket` with `finger`,
+    /// returning the one replaced.
+    fn swap(&mut self, bucket: u32, entry: u16, finger: W) -> W;
+
+    /// Called after each swap with the fingerprint evicted.
+    fn on_swap(&mut self, _bucket: u32, _entry: u16, _evicted: W) {}
+}
+
+/// Outcome of `walk`.
+pub(crate) enum Walk<W> {
+    /// Placed after this many swaps; the slot holds the new fingerprint
+    Placed(u8, usize),
+    /// Gave up after this many swaps, as every bucket reachable was full;
+    /// this fingerprint, evicted last, was dropped
+    Cycle(u8, W),
+    /// Ran out of swaps; this fingerprint, relocated last, was dropped
+    Full(W),
+}
+
+/// Places `finger` by relocating fingerprints, starting from a random
+/// one of `candidates`, in at most `max_swaps` swaps.
+pub(crate) fn walk<W, S, R>(slots: &mut S, rng: &mut R, finger: W, candidates: &[u32], max_swaps: u8) -> Walk<W>
+where
+    W: FingerprintWord,
+    S: KickSlots<W> + ?Sized,
+    R: Rng + ?Sized,
+{
+    let entries = slots.num_entries();
+    let mut idx = *candidates.choose(rng).unwrap();
+    let mut finger = finger;
+    // `home` is the slot holding the new fingerprint, or None while it is
+    // the one being relocated
+    let mut home = None;
+    // Buckets of the last few swaps; revisiting one may mean the chain is
+    // trapped
+    let mut recent = [u32::MAX; 8];
+    let mut last = None;
+    for swaps in 1 ..= max_swaps {
+        if recent.contains(&idx) && trapped(slots, idx) {
+            return Walk::Cycle(swaps, finger);
+        }
+        recent[swaps as usize % recent.len()] = idx;
+        // Evicting the fingerprint just placed would only put it back
+        let entry = match last {
+            Some((bucket, entry)) if bucket == idx && entries > 1 => {
+                let other = rng.gen_range(0, entries - 1);
+                if other >= entry { other + 1 } else { other }
+            },
+            _ => rng.gen_range(0, entries),
+        };
+        last = Some((idx, entry));
+        let slot = idx as usize * entries as usize + entry as usize;
+        home = match home {
+            None => Some(slot),
+            Some(h) if h == slot => None,
+            h => h,
+        };
+        finger = slots.swap(idx, entry, finger);
+        slots.on_swap(idx, entry, finger);
+        let alternates = slots.alternates(finger);
+        if let Some(slot) = alternates.iter().find_map(|&b| slots.try_insert(b, finger)) {
+            return Walk::Placed(swaps, home.unwrap_or(slot));
+        }
+        // Move on to an alternate other than the bucket just left, if
+        // there is one
+        let mut others = Candidates::default();
+        alternates.iter().filter(|&&b| b != idx).for_each(|&b| others.push(b));
+        idx = match others.len() {
+            0 => alternates[0],
+            1 => others[0],
+            n => others[rng.gen_range(0, n)],
+        };
+    }
+    Walk::Full(finger)
+}
+
+/// True if every bucket a kick chain at `start` can reach is full. A
+/// fingerprint evicted from any bucket moves to one of its alternate
+/// buckets, so the buckets reachable are closed under the alternates of
+/// their contents. Only sets of up to `TRAP_SEARCH_BUCKETS` are recognized.
+fn trapped<W, S>(slots: &S, start: u32) -> bool
+where
+    W: FingerprintWord,
+    S: KickSlots<W> + ?Sized,
+{
+    let mut reached = [start; TRAP_SEARCH_BUCKETS];
+    let mut len = 1;
+    let mut next = 0;
+    while next < len {
+        let bucket = reached[next];
+        next += 1;
+        for entry in 0 .. slots.num_entries() {
+            let alternates = match slots.get(bucket, entry) {
+                Some(finger) => slots.alternates(finger),
+                None => return false,
+            };
+            for &alt in alternates.iter() {
+                if !reached[.. len].contains(&alt) {
+                    if len == TRAP_SEARCH_BUCKETS {
+                        return false;
+                    }
+                    reached[len] = alt;
+                    len += 1;
+                }
+            }
+        }
+    }
+    true
+}
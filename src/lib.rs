@@ -1,12 +1,90 @@
-#![feature(refcell_replace_swap)]
+#![allow(clippy::result_unit_err)]
 
-use rand::rngs::ThreadRng;
-use rand::seq::SliceRandom;
-use rand::{Rng, thread_rng};
-use std::cell::RefCell;
+use rand::rngs::StdRng;
+#[cfg(any(test, feature = "testkit"))]
+use rand::Rng;
+use rand::{FromEntropy, SeedableRng};
+use std::cell::{RefCell, RefMut};
 use std::collections::hash_map::DefaultHasher;
-use std::fmt::Debug;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fmt::{self, Debug};
 use std::hash::{Hash, Hasher};
+use std::mem;
+
+use crate::checksum::slot_checksum;
+use crate::kick::{KickSlots, Walk};
+use crate::oplog::OpLog;
+use crate::sparse::SparseSlots;
+use crate::store::{store_bytes, store_for, BucketStore, PagedStore};
+use crate::word::FingerprintWord;
+
+#[cfg(feature = "tokio")]
+mod async_io;
+mod batch;
+mod canonical;
+mod checkpoint;
+mod checksum;
+#[cfg(feature = "flate2")]
+mod compress;
+mod dedup;
+mod delta;
+mod distinct;
+mod dot;
+mod dual;
+mod dump;
+mod entry;
+mod expiry;
+mod fixed;
+pub mod experiment;
+#[cfg(feature = "memmap2")]
+mod frozen;
+mod grow;
+mod hugepage;
+mod kick;
+mod merge;
+mod migrate;
+mod observer;
+mod oplog;
+mod overflow;
+#[cfg(feature = "rayon")]
+mod parallel;
+pub mod plan;
+mod rehash;
+mod serialize;
+#[cfg(feature = "zeroize")]
+mod scrub;
+mod simulate;
+mod skew;
+mod sparse;
+mod spill;
+mod split;
+mod store;
+#[cfg(feature = "testkit")]
+pub mod testkit;
+mod transmit;
+mod verify;
+mod word;
+
+pub use crate::batch::{BatchError, RemoveReport};
+pub use crate::checkpoint::Checkpoint;
+pub use crate::dedup::DedupWriter;
+pub use crate::delta::{DiffError, FilterDelta, SlotWrite};
+pub use crate::distinct::DistinctEstimate;
+pub use crate::dual::{DualFilter, DualSide};
+pub use crate::entry::{Entry, OccupiedEntry, VacantEntry};
+pub use crate::fixed::StaticFilter;
+#[cfg(feature = "memmap2")]
+pub use crate::frozen::FrozenFilter;
+pub use crate::observer::FilterObserver;
+pub use crate::oplog::{decode_ops, encode_ops, ApplyReport, FilterOp};
+pub use crate::overflow::OverflowFilter;
+pub use crate::serialize::{inspect_serialized, DecodeError, SerializedInfo, CONFIG_HEADER_LEN, FORMAT_VERSION};
+pub use crate::simulate::FillSimulation;
+pub use crate::skew::SkewReport;
+pub use crate::spill::SpillFilter;
+pub use crate::transmit::TransmitFrame;
+pub use crate::verify::{FppReport, VerifyReport};
 
 #[derive(Debug)]
 pub struct Filter {
@@ -17,14 +95,38 @@ pub struct Filter {
     num_buckets: u32,
 
     /// Number of entries per bucket
-    num_entries: u8,
+    num_entries: u16,
 
     /// Max swaps
     max_swaps: u8,
 
+    /// Number of independently salted hash derivations (1 or 2)
+    hash_count: u8,
+
+    /// Candidate buckets per value (2 to `MAX_HASHES`)
+    num_hashes: u8,
+
+    /// Whether occupancy is tracked in a bitmap, freeing fingerprint 0
+    occupancy_bitmap: bool,
+
+    /// Top fingerprint bits holding an expiry generation
+    generation_bits: u8,
+
+    /// Constant mixed into key hashes, if any
+    domain: Option<u64>,
+
+    /// `Config::huge_pages` and `Config::lazy_pages`, for storage rebuilt
+    /// when the filter grows
+    huge_pages: bool,
+    lazy_pages: bool,
+
     /// Bucket type
     bucket_type: BucketType,
 
+    /// Alternate buckets of every stored fingerprint, indexed by it, for
+    /// the `U8` bucket type; computed once instead of hashing per lookup
+    alt_table: Option<Box<[Candidates]>>,
+
     /// Buckets
     buckets: RefCell<Buckets>,
 
@@ -32,50 +134,702 @@ pub struct Filter {
     used: RefCell<u64>,
 
     /// Random number generator
-    rng: RefCell<ThreadRng>,
+    rng: RefCell<StdRng>,
+
+    /// Seed `rng` was created from, if fixed
+    seed: Option<u64>,
+
+    /// Observer notified of inserts and deletes
+    observer: Option<Box<dyn FilterObserver + Send + Sync>>,
+
+    /// Whether `contains` updates `query_stats`
+    track_queries: bool,
+
+    /// Lookup hits and misses since the last reset
+    query_stats: RefCell<QueryStats>,
+
+    /// Recorder of successful operations, for replication
+    op_log: Option<RefCell<OpLog>>,
+
+    /// Relocations made by the last insert that kicked, if tracing kicks
+    kick_path: Option<RefCell<Vec<KickStep>>>,
 }
 
+/// Word the fingerprints are handled in: the narrowest holding
+/// `finger_bits`.
 #[derive(Debug)]
 enum BucketType {
     U8,
-    U16
+    U16,
+    U32,
 }
 
-#[derive(Debug)]
-enum Buckets {
-    U8(Vec<u8>),
-    U16(Vec<u16>),
+#[derive(Clone, Debug)]
+struct Buckets {
+    /// Fingerprints
+    slots: Slots,
+
+    /// Occupancy bitmap, one bit per slot. Without it, fingerprint 0 marks
+    /// an empty slot. Always None for sparse slots, which track occupancy
+    /// themselves.
+    occupied: Option<Box<[u64]>>,
+
+    /// Order of the slots in `slots` and `occupied`
+    layout: Layout,
+
+    /// Number of buckets
+    num_buckets: usize,
+
+    /// Number of entries per bucket
+    num_entries: usize,
+
+    /// Previous contents of each slot written while journaling, oldest
+    /// first, for rolling back (see `batch` and `checkpoint`)
+    journal: Option<Vec<(usize, Option<u32>)>>,
+
+    /// XOR of `slot_checksum` over the occupied slots (see `checksum`)
+    checksum: u64,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
+enum Slots {
+    /// Every slot, in the store for the fingerprint width
+    Dense(Box<dyn BucketStore>),
+    /// Only occupied buckets, until converted to `Dense`
+    Sparse(Box<SparseSlots>),
+}
+
+impl Buckets {
+    /// Dense storage for the geometry in `c`, which has `n` slots.
+    fn dense(c: &Config, n: usize) -> Buckets {
+        let mut store: Box<dyn BucketStore> = if c.lazy_pages {
+            Box::new(PagedStore::new(c.finger_bits, n).expect("supported finger_bits"))
+        } else {
+            store_for(c.finger_bits, n).expect("supported finger_bits")
+        };
+        if c.huge_pages {
+            store.advise_huge_pages();
+        }
+        let occupied = if c.occupancy_bitmap {
+            Some(vec![0u64; n.div_ceil(64)].into_boxed_slice())
+        } else {
+            None
+        };
+        Buckets {
+            slots: Slots::Dense(store),
+            occupied,
+            layout: c.layout,
+            num_buckets: c.num_buckets as usize,
+            num_entries: c.num_entries as usize,
+            journal: None,
+            checksum: 0,
+        }
+    }
+
+    /// Position in `slots` and `occupied` of slot `i` (that is, entry
+    /// `i % num_entries` of bucket `i / num_entries`).
+    #[inline]
+    fn pos(&self, i: usize) -> usize {
+        match self.layout {
+            Layout::RowMajor => i,
+            Layout::ColumnMajor => (i % self.num_entries) * self.num_buckets + i / self.num_entries,
+        }
+    }
+
+    /// Total number of entries (slots).
+    fn len(&self) -> usize {
+        match self.slots {
+            Slots::Dense(ref store) => store.len(),
+            Slots::Sparse(ref sparse) => sparse.capacity,
+        }
+    }
+
+    /// Raw fingerprint stored in slot `i`, widened; 0 for empty sparse
+    /// slots.
+    fn get(&self, i: usize) -> u32 {
+        match self.slots {
+            Slots::Dense(ref store) => store.get(self.pos(i)),
+            Slots::Sparse(ref sparse) => sparse.entry(i).unwrap_or(0),
+        }
+    }
+
+    /// Stores `finger` in dense slot `i` without touching occupancy; it
+    /// must fit in `finger_bits`.
+    fn set(&mut self, i: usize, finger: u32) {
+        let p = self.pos(i);
+        match self.slots {
+            Slots::Dense(ref mut store) => {
+                debug_assert!(finger as u64 >> store.entry_bits() == 0, "fingerprint too wide");
+                store.set(p, finger)
+            },
+            Slots::Sparse(_) => unreachable!(),
+        }
+    }
+
+    fn is_occupied(&self, i: usize) -> bool {
+        self.entry(i).is_some()
+    }
+
+    /// First slot of `bucket` holding `finger` once masked with `mask`
+    /// (see `Filter::key_mask`).
+    fn find_in<W: FingerprintWord>(&self, bucket: u32, finger: W, mask: u32) -> Option<usize> {
+        let start = bucket as usize * self.num_entries;
+        let range = start .. start + self.num_entries;
+        let finger = finger.widen();
+        match W::slice(&self.slots) {
+            Some(vec) => range.into_iter().find(|&i| vec[self.pos(i)].widen() & mask == finger && self.is_occupied(i)),
+            None => range.into_iter().find(|&i| self.entry(i).map(|x| x & mask) == Some(finger)),
+        }
+    }
+
+    /// Fingerprint in slot `i`, or None if the slot is empty.
+    fn entry(&self, i: usize) -> Option<u32> {
+        match (&self.slots, &self.occupied) {
+            (Slots::Sparse(sparse), _) => sparse.entry(i),
+            (_, Some(bits)) if bits[self.pos(i) / 64] & (1 << (self.pos(i) % 64)) == 0 => None,
+            (_, Some(_)) => Some(self.get(i)),
+            (_, None) => Some(self.get(i)).filter(|&x| x != 0),
+        }
+    }
+
+    /// First empty slot in `range`.
+    fn first_free(&self, range: std::ops::Range<usize>) -> Option<usize> {
+        range.into_iter().find(|&i| !self.is_occupied(i))
+    }
+
+    /// Stores `finger` in slot `i` and marks it occupied. Sparse slots
+    /// convert to dense once past their limit.
+    fn put(&mut self, i: usize, finger: u32) {
+        self.log_write(i);
+        self.toggle_checksum(i);
+        if let Slots::Sparse(ref mut sparse) = self.slots {
+            sparse.put(i, finger);
+            if sparse.occupied > sparse.limit {
+                // Rebuilds the checksum along with the slots
+                self.densify();
+                return;
+            }
+        } else {
+            self.set(i, finger);
+            let p = self.pos(i);
+            if let Some(ref mut bits) = self.occupied {
+                bits[p / 64] |= 1 << (p % 64);
+            }
+        }
+        self.toggle_checksum(i);
+    }
+
+    /// Empties slot `i`.
+    fn remove(&mut self, i: usize) {
+        self.log_write(i);
+        self.toggle_checksum(i);
+        if let Slots::Sparse(ref mut sparse) = self.slots {
+            sparse.remove(i);
+            return;
+        }
+        self.set(i, 0);
+        let p = self.pos(i);
+        if let Some(ref mut bits) = self.occupied {
+            bits[p / 64] &= !(1 << (p % 64));
+        }
+    }
+
+    /// Empties every slot.
+    fn clear(&mut self) {
+        if self.journal.is_some() {
+            for i in 0 .. self.len() {
+                if self.is_occupied(i) {
+                    self.log_write(i);
+                }
+            }
+        }
+        match self.slots {
+            Slots::Dense(ref mut store) => store.clear(),
+            Slots::Sparse(ref mut sparse) => sparse.clear(),
+        }
+        if let Some(ref mut bits) = self.occupied {
+            bits.iter_mut().for_each(|x| *x = 0);
+        }
+        self.checksum = 0;
+    }
+
+    /// Heap bytes held by the slots, bitmap and journal.
+    fn heap_bytes(&self) -> usize {
+        let slots = match self.slots {
+            Slots::Dense(ref store) => store.heap_bytes(),
+            Slots::Sparse(ref sparse) => mem::size_of::<SparseSlots>() + sparse.heap_bytes(),
+        };
+        let journal = self.journal.as_ref()
+            .map_or(0, |journal| journal.capacity() * mem::size_of::<(usize, Option<u32>)>());
+        slots + self.occupied.as_ref().map_or(0, |bits| bits.len() * 8) + journal
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Config {
-    /// Fingerprint bit length
+    /// Fingerprint bit length, from 1 to 32. Widths of 8, 16 and 32 bits
+    /// are stored as whole words; other widths are bit packed, saving
+    /// memory at some cost in speed.
     pub finger_bits: u8,
 
-    /// Number of buckets
+    /// Number of buckets, rounded up by `Filter::new` as
+    /// `bucket_rounding` says
     pub num_buckets: u32,
 
-    /// Number of entries per bucket
-    pub num_entries: u8,
+    /// How `num_buckets` is rounded when building a filter
+    pub bucket_rounding: Rounding,
+
+    /// Number of entries per bucket, at least 1. Fewer entries lower the
+    /// load reached before the first insert fails; with 16384 buckets and
+    /// 100 max swaps it is about 13% with 1 entry (a plain cuckoo hash
+    /// table, where every kick evicts the bucket's only fingerprint), 37%
+    /// with 2, 68% with 4 and 85% with 8.
+    pub num_entries: u16,
 
     /// Max swaps
     pub max_swaps: u8,
+
+    /// Count `contains` hits and misses (see `Filter::query_stats`)
+    pub track_queries: bool,
+
+    /// Number of independently salted hash derivations. With 1 the
+    /// fingerprint and primary index are split from one hash; with 2 the
+    /// fingerprint and alternate index are rehashed with their own salts,
+    /// decorrelating them from the primary index at very high loads.
+    pub hash_count: u8,
+
+    /// Candidate buckets per value, from 2 to `MAX_HASHES`. The first is
+    /// the primary bucket; the others are derived from the fingerprint by
+    /// independent hashes, and a fingerprint kicked from any bucket moves
+    /// to one of those. More candidates reach higher loads before inserts
+    /// fail (with 4 entries, about 98% with 3 against 65% with 2), so the
+    /// same items fit in fewer buckets. A lookup probes every candidate,
+    /// and more fingerprints end up in alternate buckets, where they match
+    /// every value sharing the fingerprint, so false positives rise well
+    /// beyond `estimated_fpp` at high loads.
+    pub num_hashes: u8,
+
+    /// Track occupied slots in a separate bitmap (one bit per slot) instead
+    /// of reserving fingerprint 0 for empty slots. All `2^finger_bits`
+    /// values become usable fingerprints, slightly lowering the false
+    /// positive rate.
+    pub occupancy_bitmap: bool,
+
+    /// Reserve the top bits of each fingerprint for the generation at which
+    /// it expires (see `Filter::insert_with_ttl` and `Filter::sweep`): 0 to
+    /// disable, or from 2 up to half of `finger_bits` and at most 15, the
+    /// most the serialized header holds. Every reserved bit halves the
+    /// number of fingerprint values, doubling the false positive rate; 4
+    /// bits on 16-bit fingerprints raise it sixteenfold.
+    pub generation_bits: u8,
+
+    /// Mix this constant into every key's hash, so that filters for
+    /// different categories place the same key differently and a key from
+    /// one is no likelier than any other to be found in another. Filters
+    /// only agree on hashes (see `Filter::hash_key`) within one domain.
+    pub domain: Option<u64>,
+
+    /// Start with sparse storage that only allocates occupied buckets, and
+    /// convert to the usual dense array once the load factor passes this
+    /// fraction. Saves memory for filters provisioned far beyond their
+    /// contents; the conversion is one-way. Filters read by `from_bytes`
+    /// are always dense.
+    pub sparse_until: Option<f64>,
+
+    /// Record the relocations made by inserts (see `Filter::last_kick_path`)
+    pub trace_kicks: bool,
+
+    /// Order of the slots in memory
+    pub layout: Layout,
+
+    /// Seed for the random choices made when kicking and by `fill_random`,
+    /// making a filter's placements reproducible (hashes never vary between
+    /// runs). None seeds from the OS.
+    pub seed: Option<u64>,
+
+    /// Ask the OS to back the bucket array with huge pages, cutting TLB
+    /// misses on lookups in gigabyte-scale filters. Only a hint, and only
+    /// acted on under Linux with transparent huge pages enabled.
+    pub huge_pages: bool,
+
+    /// Allocate the bucket array in pages of 4096 slots on first write
+    /// instead of all at once, so that a huge filter holding few
+    /// fingerprints only takes memory for the pages they landed in. Every
+    /// access pays a page lookup, and the occupancy bitmap, if any, is
+    /// still allocated up front. Unlike `sparse_until`, storage stays
+    /// paged however full the filter gets; `clear` frees the pages.
+    pub lazy_pages: bool,
+}
+
+/// Load factor at which inserts start failing with `num_entries` entries
+/// per bucket, taken from the low end of runs with 16384 buckets, 16-bit
+/// fingerprints and 100 max swaps.
+fn expected_max_load(num_entries: u16) -> f64 {
+    match num_entries {
+        1 => 0.10,
+        2 => 0.35,
+        3 => 0.50,
+        4 ..= 5 => 0.65,
+        6 ..= 7 => 0.78,
+        8 ..= 11 => 0.85,
+        12 ..= 15 => 0.91,
+        16 ..= 31 => 0.94,
+        32 ..= 63 => 0.98,
+        _ => 0.99,
+    }
+}
+
+impl Config {
+    /// Configuration for `capacity` values with 4 entries per bucket,
+    /// sized so they fill 90% of the load at which inserts start failing,
+    /// and with the narrowest fingerprints whose `Filter::estimated_fpp` at
+    /// that load is at most `fpp`. Fails if 16-bit fingerprints cannot
+    /// reach `fpp` or too many buckets are needed.
+    ///
+    /// The load estimate comes from 16384-bucket tables; alternate buckets
+    /// depend only on the fingerprint, so much larger tables fill less
+    /// before inserts fail and may not reach `capacity`.
+    pub fn for_capacity(capacity: u64, fpp: f64) -> Result<Config, ()> {
+        let num_entries = 4;
+        let load = 0.9 * expected_max_load(num_entries);
+        let mut config = [8, 16].iter()
+            .map(|&finger_bits| Config { finger_bits, num_entries, ..Config::default() })
+            .find(|c| c.estimated_fpp(load) <= fpp)
+            .ok_or(())?;
+        let num_buckets = (capacity as f64 / (load * num_entries as f64)).ceil().max(1.0);
+        if num_buckets > u32::MAX as f64 {
+            return Err(());
+        }
+        config.num_buckets = num_buckets as u32;
+        Ok(config)
+    }
+
+    /// For each fingerprint width from 1 to 32 bits, in order, the
+    /// `(finger_bits, required_bytes, estimated_fpp)` of a filter sized
+    /// for `expected_items` as `for_capacity` sizes it, for comparing the
+    /// memory each width costs. Widths whose rate stays above `fpp` are
+    /// left out, so the first tuple is the cheapest width reaching it;
+    /// later ones trade memory for lower rates. Empty if none reaches
+    /// `fpp` or too many buckets are needed.
+    pub fn compare_widths(expected_items: u64, fpp: f64) -> Vec<(u8, u64, f64)> {
+        let num_entries = 4;
+        let load = 0.9 * expected_max_load(num_entries);
+        let num_buckets = (expected_items as f64 / (load * num_entries as f64)).ceil().max(1.0);
+        if num_buckets > u32::MAX as f64 {
+            return Vec::new();
+        }
+        (1 ..= 32)
+            .map(|finger_bits| Config { finger_bits, num_buckets: num_buckets as u32, num_entries, ..Config::default() })
+            .map(|c| (c.finger_bits, c.required_bytes(), c.estimated_fpp(load)))
+            .filter(|&(_, _, achieved)| achieved <= fpp)
+            .collect()
+    }
+
+    /// Configuration with the lowest `estimated_fpp` at `expected_items`
+    /// whose `Filter::memory_usage` stays within `budget_bytes`, searching
+    /// fingerprint widths and 1 to 32 entries per bucket, with as many
+    /// buckets as fit. As with `for_capacity`, the items must fill at most
+    /// 90% of the load at which inserts start failing. Fails if the budget
+    /// cannot hold them.
+    pub fn within_bytes(budget_bytes: usize, expected_items: u64) -> Result<Config, ()> {
+        let mut best: Option<(f64, Config)> = None;
+        for &finger_bits in &[8u8, 16] {
+            // 8-bit filters also keep a table of every fingerprint's
+            // alternate buckets
+            let table = if finger_bits == 8 { 256 * mem::size_of::<Candidates>() } else { 0 };
+            let available = match budget_bytes.checked_sub(mem::size_of::<Filter>() + table) {
+                Some(available) => available,
+                None => continue,
+            };
+            for num_entries in 1 ..= 32u16 {
+                let slot_bytes = finger_bits as usize / 8 * num_entries as usize;
+                let num_buckets = (available / slot_bytes).min(u32::MAX as usize) as u32;
+                let config = Config { finger_bits, num_buckets, num_entries, ..Config::default() };
+                let load = expected_items as f64 / config.capacity() as f64;
+                if num_buckets == 0 || load > 0.9 * expected_max_load(num_entries) || config.slot_count().is_none() {
+                    continue;
+                }
+                let fpp = config.estimated_fpp(load);
+                if best.as_ref().is_none_or(|&(best_fpp, _)| fpp < best_fpp) {
+                    best = Some((fpp, config));
+                }
+            }
+        }
+        best.map(|(_, config)| config).ok_or(())
+    }
+
+    /// Smallest configuration, in bits, holding `expected_items` at
+    /// `target_load` with `estimated_fpp` at most `fpp` there, searching
+    /// fingerprint widths and 1 to 32 entries per bucket. More entries
+    /// raise the load a filter can reach but compare each lookup against
+    /// more fingerprints, raising the false positive rate; of equally
+    /// small configurations the one with fewest entries wins. Fails if no
+    /// configuration reaches `target_load` (see `for_capacity` on how
+    /// the load limits were estimated) with that rate.
+    pub fn optimal_entries(expected_items: u64, fpp: f64, target_load: f64) -> Result<Config, ()> {
+        if !(target_load > 0.0 && target_load <= 1.0) {
+            return Err(());
+        }
+        let mut best: Option<(u64, Config)> = None;
+        for &finger_bits in &[8u8, 16] {
+            for num_entries in 1 ..= 32u16 {
+                let num_buckets = (expected_items as f64 / (target_load * num_entries as f64)).ceil().max(1.0);
+                if num_buckets > u32::MAX as f64 || target_load > expected_max_load(num_entries) {
+                    continue;
+                }
+                let config = Config { finger_bits, num_buckets: num_buckets as u32, num_entries, ..Config::default() };
+                let bits = config.capacity() * finger_bits as u64;
+                if config.estimated_fpp(target_load) <= fpp && best.as_ref().is_none_or(|&(best_bits, _)| bits < best_bits) {
+                    best = Some((bits, config));
+                }
+            }
+        }
+        best.map(|(_, config)| config).ok_or(())
+    }
+
+    /// Entries in a filter with this configuration.
+    fn capacity(&self) -> u64 {
+        self.num_buckets as u64 * self.num_entries as u64
+    }
+
+    /// `Filter::estimated_fpp` of a filter with this configuration at
+    /// `load_factor`.
+    pub fn estimated_fpp(&self, load_factor: f64) -> f64 {
+        let probed = self.num_hashes as f64 * self.num_entries as f64 * load_factor;
+        1.0 - (1.0 - 1.0 / self.finger_values() as f64).powf(probed)
+    }
+
+    /// Number of distinct fingerprints a value can get.
+    fn finger_values(&self) -> u64 {
+        let key_bits = self.finger_bits - self.generation_bits;
+        if self.occupancy_bitmap {
+            1 << key_bits
+        } else {
+            (1 << key_bits) - 1
+        }
+    }
+
+    /// `num_buckets` rounded as `bucket_rounding` says, or None if that
+    /// does not fit a u32.
+    pub(crate) fn rounded_buckets(&self) -> Option<u32> {
+        let n = self.num_buckets;
+        match self.bucket_rounding {
+            Rounding::Exact => Some(n),
+            Rounding::NextPowerOfTwo => n.checked_next_power_of_two(),
+            Rounding::NextPrime => (n.max(2) ..= u32::MAX).find(|&x| is_prime(x)),
+        }
+    }
+
+    /// Bytes of the bucket array a `Filter` built from this configuration
+    /// allocates, after rounding `num_buckets`: the fingerprints at their
+    /// width in memory (see `finger_bits`) and any occupancy bitmap.
+    /// Computed without allocating, so that oversized configurations can
+    /// be turned away before `Filter::new`; meaningless for ones it
+    /// rejects. Sparse storage (`sparse_until`) and `lazy_pages` hold less
+    /// until they fill.
+    pub fn required_bytes(&self) -> u64 {
+        let buckets = self.rounded_buckets().unwrap_or(self.num_buckets);
+        let slots = buckets as u64 * self.num_entries as u64;
+        let bitmap = if self.occupancy_bitmap { slots.div_ceil(64) * 8 } else { 0 };
+        store_bytes(self.finger_bits, slots) + bitmap
+    }
+
+    /// Number of slots, or None if the slots or their bytes exceed what
+    /// this target can address. Checked once at construction, so slot
+    /// arithmetic (`bucket * num_entries + entry` and the like) cannot
+    /// overflow `usize` afterwards.
+    pub(crate) fn slot_count(&self) -> Option<usize> {
+        let slots = self.num_buckets as u64 * self.num_entries as u64;
+        let bytes = slots.checked_mul(self.finger_bits.div_ceil(8) as u64)?;
+        if bytes > isize::MAX as u64 {
+            return None;
+        }
+        usize::try_from(slots).ok()
+    }
+}
+
+/// Trial division; prime gaps below 2^32 are short enough for
+/// `Config::rounded_buckets` to call this a few hundred times at most.
+fn is_prime(n: u32) -> bool {
+    if n < 4 {
+        return n >= 2;
+    }
+    if n.is_multiple_of(2) {
+        return false;
+    }
+    (3 ..).step_by(2).take_while(|&d: &u64| d * d <= n as u64).all(|d| !(n as u64).is_multiple_of(d))
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            finger_bits: 16,
+            num_buckets: 1024,
+            bucket_rounding: Rounding::Exact,
+            num_entries: 4,
+            max_swaps: 100,
+            track_queries: false,
+            hash_count: 1,
+            num_hashes: 2,
+            occupancy_bitmap: false,
+            generation_bits: 0,
+            domain: None,
+            sparse_until: None,
+            trace_kicks: false,
+            huge_pages: false,
+            lazy_pages: false,
+            layout: Layout::RowMajor,
+            seed: None,
+        }
+    }
+}
+
+/// Rounding applied to `Config::num_buckets`. Buckets are picked by
+/// reducing hashes modulo the bucket count, which works for any count.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    /// Use the count as given, keeping memory to the budget
+    Exact,
+    /// Round up to a power of two, as some other implementations require
+    NextPowerOfTwo,
+    /// Round up to a prime, so that structured keys whose hashes share
+    /// factors with the count do not crowd into few buckets when the hash
+    /// is weak
+    NextPrime,
+}
+
+/// Memory order of the slots of a dense filter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// Each bucket's entries are contiguous, so a lookup reads two short
+    /// runs of memory. Best for inserts and lookups.
+    RowMajor,
+    /// Entry 0 of every bucket comes first, then entry 1 and so on. Suits
+    /// scans over one entry position across all buckets.
+    ColumnMajor,
+}
+
+/// Reasons an insert can fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InsertError {
+    /// No empty entry was found within `max_swaps` relocations. The
+    /// fingerprint relocated last was dropped.
+    Full,
+    /// The filter is full and growing it would exceed the capacity given
+    /// to `Filter::insert_grow`. Nothing was dropped.
+    CapacityExceeded,
+    /// Relocations went round a set of full buckets that every
+    /// fingerprint in them can only be relocated within, so no number of
+    /// swaps could succeed. The fingerprint relocated last was dropped.
+    Cycle,
+    /// The bucket or fingerprint given to `Filter::insert_fingerprint` is
+    /// out of range. Nothing was stored.
+    InvalidFingerprint,
+    /// Every candidate bucket is full and the filter has a single bucket,
+    /// so relocating could only swap fingerprints within it. Nothing was
+    /// dropped.
+    Degenerate,
+}
+
+/// What inserting a value would take, as returned by
+/// `Filter::estimate_insert_cost`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InsertCost {
+    /// A candidate bucket has a free entry
+    Immediate,
+    /// Both candidate buckets are full, so the insert would kick and may
+    /// fail
+    Eviction,
+}
+
+/// Which candidate buckets of a value hold a matching fingerprint, as
+/// returned by `Filter::contains_detailed`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Match {
+    /// No candidate bucket
+    None,
+    /// Only the primary bucket
+    Primary,
+    /// Only alternate buckets
+    Alternate,
+    /// The primary bucket and an alternate bucket, or the primary bucket
+    /// when it is also an alternate
+    Both,
+}
+
+/// What a filter can say about a value, as returned by `Filter::query`.
+/// A matching fingerprint may belong to another value, so there is no
+/// "definitely present".
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Answer {
+    /// No candidate bucket holds the value's fingerprint, so it was never
+    /// inserted (or has been deleted)
+    DefinitelyAbsent,
+    /// A candidate bucket holds the value's fingerprint: it was inserted,
+    /// or it is a false positive (see `Filter::estimated_fpp`)
+    ProbablyPresent,
+}
+
+/// One relocation made by an insert, recorded when `Config::trace_kicks` is
+/// set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KickStep {
+    pub bucket: u32,
+    pub entry: u16,
+    /// Fingerprint evicted from the slot to make room
+    pub evicted: u32,
+}
+
+/// Lookup counts collected when `Config::track_queries` is set.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QueryStats {
+    /// `contains` calls that returned true
+    pub hits: u64,
+
+    /// `contains` calls that returned false
+    pub misses: u64,
 }
 
 impl Filter{
     pub fn new(c: &Config) -> Result<Filter, ()> {
-        match Filter::init_buckets(c.num_buckets, c.num_entries, c.finger_bits) {
+        if c.hash_count == 0 || c.hash_count > 2 || c.num_entries == 0 || c.num_buckets == 0
+            || c.num_hashes < 2 || c.num_hashes as usize > MAX_HASHES
+            || c.generation_bits == 1 || c.generation_bits > c.finger_bits / 2
+            || c.generation_bits > serialize::MAX_GENERATION_BITS {
+            return Err(());
+        }
+        let c = &Config { num_buckets: c.rounded_buckets().ok_or(())?, ..c.clone() };
+        match Filter::init_buckets(c) {
             Ok((buckets, bucket_type)) => {
-                Ok(Filter {
+                let mut filter = Filter {
                     finger_bits: c.finger_bits,
                     num_buckets: c.num_buckets,
                     num_entries: c.num_entries,
                     max_swaps: c.max_swaps,
+                    hash_count: c.hash_count,
+                    num_hashes: c.num_hashes,
+                    occupancy_bitmap: c.occupancy_bitmap,
+                    generation_bits: c.generation_bits,
+                    domain: c.domain,
+                    huge_pages: c.huge_pages,
+                    lazy_pages: c.lazy_pages,
                     bucket_type,
+                    alt_table: None,
                     buckets: RefCell::new(buckets),
                     used: RefCell::new(0),
-                    rng: RefCell::new(thread_rng()),
-                })
+                    rng: RefCell::new(c.seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64)),
+                    seed: c.seed,
+                    observer: None,
+                    track_queries: c.track_queries,
+                    query_stats: RefCell::new(QueryStats::default()),
+                    op_log: None,
+                    kick_path: if c.trace_kicks { Some(RefCell::new(Vec::new())) } else { None },
+                };
+                if let BucketType::U8 = filter.bucket_type {
+                    let scheme = filter.scheme();
+                    filter.alt_table = Some((0 ..= u8::MAX).map(|stored| scheme.alternates(stored)).collect());
+                }
+                Ok(filter)
             }
             Err(_) => Err(()),
         }
@@ -83,6 +837,18 @@ impl Filter{
 }
 
 impl Filter {
+    /// Builds the filter `Config::within_bytes` picks for `expected_items`
+    /// within `budget_bytes`; `config` tells which geometry was chosen.
+    pub fn within_bytes(budget_bytes: usize, expected_items: u64) -> Result<Filter, ()> {
+        Filter::new(&Config::within_bytes(budget_bytes, expected_items)?)
+    }
+}
+
+impl Filter {
+    /// Occupied entries, counting every stored copy. At most `capacity`,
+    /// since each stored fingerprint takes an entry; the count is updated
+    /// with saturating arithmetic, so a miscount, e.g. from restoring a
+    /// `Checkpoint` taken on another filter, cannot wrap around.
     pub fn used(&self) -> u64 {
         *self.used.borrow_mut()
     }
@@ -91,229 +857,2007 @@ impl Filter {
         self.num_buckets as u64 * self.num_entries as u64
     }
 
+    /// `used / capacity`, clamped to 1.0 in case `used` was miscounted.
     pub fn load_factor(&self) -> f64 {
-        self.used() as f64 / self.capacity() as f64
+        (self.used() as f64 / self.capacity() as f64).min(1.0)
     }
 
     pub fn bits(&self) -> u64 {
         self.capacity() * self.finger_bits as u64
     }
-}
 
-impl Filter {
-    pub fn insert<T: ?Sized + Hash>(&self, x: &T) -> Result<u8, u8> where T: Debug {
-        let result = match self.bucket_type {
-            BucketType::U8 => self.insert_u8(x),
-            BucketType::U16 => self.insert_u16(x),
-        };
-        if result.is_ok() {
-            self.used.replace_with(|&mut x| x + 1);
-        }
-        result
+    /// Bytes of memory held by the filter, including the heap allocations
+    /// of its buckets and op log. Sparse storage is estimated from its
+    /// allocated capacity.
+    pub fn memory_usage(&self) -> usize {
+        let log = self.op_log.as_ref().map_or(0, |log| log.borrow().heap_bytes());
+        let alt_table = self.alt_table.as_ref().map_or(0, |table| mem::size_of_val(&**table));
+        mem::size_of::<Filter>() + self.buckets.borrow().heap_bytes() + log + alt_table
     }
-}
 
-impl Filter {
-    fn insert_u8<T: ?Sized + Hash>(&self, x: &T) -> Result<u8, u8> {
-        let (finger, idx_1) = self.finger8_index(x);
-
-        // Try to place fingerprint in empty entry
-        if self.try_insert_u8(idx_1, finger) {
-            return Ok(0);
-        }
-        let idx_2 = self.index(&finger);
-        if self.try_insert_u8(idx_2, finger) {
-            return Ok(0);
+    /// Pages of the bucket array allocated so far, if it is allocated
+    /// page by page (see `Config::lazy_pages`).
+    pub fn allocated_pages(&self) -> Option<usize> {
+        match self.buckets.borrow().slots {
+            Slots::Dense(ref store) => store.as_any().downcast_ref::<PagedStore>().map(PagedStore::allocated_pages),
+            Slots::Sparse(_) => None,
         }
+    }
 
-        // Must relocate existing items
-        let mut rng = self.rng.borrow_mut();
-        let mut idx = *([idx_1, idx_2].choose(&mut *rng).unwrap());
-        let mut finger = finger;
-        for swaps in 1 ..= self.max_swaps {
-            let entry = rng.gen_range(0, self.num_entries);
-            finger = self.swap_u8(idx, entry, finger);
-            idx = self.index(&finger);
-            if self.try_insert_u8(idx, finger) {
-                return Ok(swaps);
-            }
+    /// Fingerprint-array bits per stored item; infinite when empty.
+    pub fn bits_per_item(&self) -> f64 {
+        match self.used() {
+            0 => f64::INFINITY,
+            used => self.bits() as f64 / used as f64,
         }
-        return Err(self.max_swaps);
     }
 
-    fn insert_u16<T: ?Sized + Hash>(&self, x: &T) -> Result<u8, u8> {
-        let (finger, idx_1) = self.finger16_index(x);
+    /// Expected false positive rate at the current load: the chance that
+    /// one of the occupied entries in a value's candidate buckets holds
+    /// a matching fingerprint by accident.
+    pub fn estimated_fpp(&self) -> f64 {
+        self.config().estimated_fpp(self.load_factor())
+    }
 
-        // Try to place fingerprint in empty entry
-        if self.try_insert_u16(idx_1, finger) {
-            return Ok(0);
-        }
-        let idx_2 = self.index(&finger);
-        if self.try_insert_u16(idx_2, finger) {
-            return Ok(0);
-        }
+    /// Number of further inserts likely to succeed before the first one
+    /// fails, from the load at which failures typically begin for this
+    /// bucket size (see `Config::num_entries`). Plan to grow or rebuild
+    /// before it reaches 0.
+    pub fn estimated_headroom(&self) -> u64 {
+        let limit = (expected_max_load(self.num_entries) * self.capacity() as f64) as u64;
+        limit.saturating_sub(self.used())
+    }
 
-        // Must relocate existing items
-        let mut rng = self.rng.borrow_mut();
-        let mut idx = *([idx_1, idx_2].choose(&mut *rng).unwrap());
-        let mut finger = finger;
-        for swaps in 1 ..= self.max_swaps {
-            let entry = rng.gen_range(0, self.num_entries);
-            finger = self.swap_u16(idx, entry, finger);
-            idx = self.index(&finger);
-            if self.try_insert_u16(idx, finger) {
-                return Ok(swaps);
-            }
-        }
-        return Err(self.max_swaps);    }
-}
+    /// Number of distinct fingerprints a value can get.
+    fn finger_values(&self) -> u64 {
+        self.config().finger_values()
+    }
 
-impl Filter {
-    fn try_insert_u8(&self, bucket: u32, finger: u8) -> bool {
-        match *self.buckets.borrow_mut() {
-            Buckets::U8(ref mut vec) => {
-                let entries = self.num_entries as usize;
-                let start = bucket as usize * entries;
-                for i in start .. (start + entries) {
-                    if vec[i] == 0 {
-                        vec[i] = finger;
-                        return true;
-                    }
-                }
-            },
-            _ => unimplemented!(),
-        }
-        false
+    /// Returns lookup hits and misses since construction or the last
+    /// `reset_query_stats`. Always zero unless `Config::track_queries` is set.
+    pub fn query_stats(&self) -> QueryStats {
+        *self.query_stats.borrow()
     }
 
-    fn try_insert_u16(&self, index: u32, finger: u16) -> bool {
-        match *self.buckets.borrow_mut() {
-            Buckets::U16(ref mut vec) => {
-                let entries = self.num_entries as usize;
-                let start = index as usize * entries;
-                for i in start .. (start + entries) {
-                    if vec[i] == 0 {
-                        vec[i] = finger;
-                        return true;
-                    }
+    pub fn reset_query_stats(&self) {
+        self.query_stats.replace(QueryStats::default());
+    }
+
+    /// Relocations made by the most recent insert that had to kick, in
+    /// order; its length is that insert's swap count, or `max_swaps` if it
+    /// failed. None unless `Config::trace_kicks` is set.
+    pub fn last_kick_path(&self) -> Option<Vec<KickStep>> {
+        self.kick_path.as_ref().map(|path| path.borrow().clone())
+    }
+
+    /// The `k` fullest buckets as `(bucket, occupied entries)`, fullest
+    /// first and ties in bucket order, for finding where inserts struggle.
+    pub fn hottest_buckets(&self, k: usize) -> Vec<(u32, u16)> {
+        let mut counts: Vec<(u32, u16)> = (0 ..).zip(self.bucket_loads()).collect();
+        counts.sort_by(|x, y| y.1.cmp(&x.1).then(x.0.cmp(&y.0)));
+        counts.truncate(k);
+        counts
+    }
+
+    /// Each pair of buckets a stored fingerprint can move between, the
+    /// bucket holding it and one of its alternates, smaller index first
+    /// and in ascending order without duplicates: the edges of the cuckoo
+    /// graph. A fingerprint whose alternate is its own bucket gives a
+    /// pair of equal buckets.
+    pub fn bucket_pairs(&self) -> impl Iterator<Item = (u32, u32)> {
+        let buckets = self.buckets.borrow();
+        let mut pairs = BTreeSet::new();
+        for i in 0 .. buckets.len() {
+            if let Some(finger) = buckets.entry(i) {
+                let bucket = self.bucket_entry(i).0;
+                for &alt in self.alternates(finger).iter() {
+                    pairs.insert((bucket.min(alt), bucket.max(alt)));
                 }
-            },
-            _ => unimplemented!(),
+            }
         }
-        false
+        pairs.into_iter()
     }
-}
 
-impl Filter {
-    fn swap_u8(&self, index: u32, entry: u8, finger: u8) -> u8 {
-        match *self.buckets.borrow_mut() {
-            Buckets::U8(ref mut vec) => {
-                let i = index as usize * self.num_entries as usize + entry as usize;
-                let x = vec[i];
-                vec[i] = finger;
-                x
-            },
-            _ => unimplemented!(),
+    /// Shannon entropy of how the occupied entries spread over buckets,
+    /// normalized to [0, 1]: 1.0 when every bucket holds the same number,
+    /// lower the more they crowd into few buckets, which points to a poor
+    /// hash or clustered keys. 1.0 for an empty filter.
+    pub fn fill_entropy(&self) -> f64 {
+        let used = self.used();
+        if used == 0 || self.num_buckets == 1 {
+            return 1.0;
         }
+        let entropy: f64 = self.bucket_loads().iter()
+            .filter(|&&n| n > 0)
+            .map(|&n| {
+                let p = n as f64 / used as f64;
+                -p * p.ln()
+            })
+            .sum();
+        entropy / (self.num_buckets as f64).ln()
     }
 
-    fn swap_u16(&self, index: u32, entry: u8, finger: u16) -> u16 {
-        match *self.buckets.borrow_mut() {
-            Buckets::U16(ref mut vec) => {
-                let i = index as usize * self.num_entries as usize + entry as usize;
-                let x = vec[i];
-                vec[i] = finger;
-                x
-            },
-            _ => unimplemented!(),
+    /// Number of occupied entries in each bucket, in bucket order.
+    fn bucket_loads(&self) -> Vec<u16> {
+        let buckets = self.buckets.borrow();
+        let entries = self.num_entries as usize;
+        (0 .. self.num_buckets as usize)
+            .map(|b| {
+                let start = b * entries;
+                (start .. start + entries).filter(|&i| buckets.is_occupied(i)).count() as u16
+            })
+            .collect()
+    }
+
+    /// Configuration describing this filter, e.g. to build an empty filter
+    /// compatible with it. `sparse_until`, `huge_pages` and `lazy_pages`
+    /// only affect storage and are returned as their defaults, as is
+    /// `bucket_rounding`, which `num_buckets` already reflects.
+    pub fn config(&self) -> Config {
+        let buckets = self.buckets.borrow();
+        Config {
+            finger_bits: self.finger_bits,
+            num_buckets: self.num_buckets,
+            bucket_rounding: Rounding::Exact,
+            num_entries: self.num_entries,
+            max_swaps: self.max_swaps,
+            track_queries: self.track_queries,
+            hash_count: self.hash_count,
+            num_hashes: self.num_hashes,
+            occupancy_bitmap: self.occupancy_bitmap,
+            generation_bits: self.generation_bits,
+            domain: self.domain,
+            sparse_until: None,
+            trace_kicks: self.kick_path.is_some(),
+            huge_pages: false,
+            lazy_pages: false,
+            layout: buckets.layout,
+            seed: self.seed,
         }
     }
+
+    /// Installs an observer notified of inserts, deletes and growth,
+    /// replacing any previous one.
+    pub fn set_observer(&mut self, observer: Box<dyn FilterObserver + Send + Sync>) {
+        self.observer = Some(observer);
+    }
 }
 
 impl Filter {
-    pub fn to_string(&self) -> String {
-        let mut s = String::new();
-        let entries = self.num_entries as usize;
-        match *self.buckets.borrow() {
-            Buckets::U8(ref vec) => {
-                let n = vec.len();
-                for (i, x) in vec.iter().enumerate() {
-                    if i % entries == 0 {
-                        s.push_str(&format!("{:3} [", i / entries));
-                    }
-                    s.push_str(&format!(" {:3} ", x));  // 2 ^ 8 requires 3 digits
-                    if i % entries == entries - 1 {
-                        if i == n - 1 {
-                            s.push_str("]");
-                        } else {
-                            s.push_str("]\n");
-                        }
-                    }
-                }
-            },
-            Buckets::U16(ref vec) => {
-                let n = vec.len();
-                for (i, x) in vec.iter().enumerate() {
-                    if i % entries == 0 {
-                        s.push_str(&format!("{:3} [", i / entries));
-                    }
-                    s.push_str(&format!(" {:5} ", x)); // 2 ^ 16 requires 5 digits
-                    if i % entries == entries - 1 {
-                        if i == n - 1 {
-                            s.push_str("]");
-                        } else {
-                            s.push_str("]\n");
-                        }
-                    }
-                }
-            }
+    /// Hash this filter derives `x`'s fingerprint and primary bucket from,
+    /// mixed with its `Config::domain`, for use with `insert_hash` and
+    /// friends.
+    pub fn hash_key<T: ?Sized + Hash>(&self, x: &T) -> u64 {
+        domain_hash(self.domain, x)
+    }
+
+    pub fn insert<T: ?Sized + Hash>(&self, x: &T) -> Result<u8, InsertError> {
+        self.insert_slot(self.hash_key(x), 0).map(|(swaps, _)| swaps)
+    }
+
+    /// Inserts a value given only its 64-bit hash (as computed by
+    /// `Filter::hash_key`), e.g. when replaying an operation log.
+    pub fn insert_hash(&self, h: u64) -> Result<u8, InsertError> {
+        self.insert_slot(h, 0).map(|(swaps, _)| swaps)
+    }
+
+    /// Inserts `x` and returns the `(bucket, entry)` where its fingerprint
+    /// ended up after any relocations. Later inserts may relocate it again.
+    pub fn insert_locate<T: ?Sized + Hash>(&self, x: &T) -> Result<(u32, u16), InsertError> {
+        self.insert_slot(self.hash_key(x), 0).map(|(_, slot)| self.bucket_entry(slot))
+    }
+
+    /// Inserts each item in turn, calling `on_result` with its position and
+    /// what `insert` returned as soon as it is known, so a long load can be
+    /// monitored without collecting the results.
+    pub fn insert_many_monitored<T: Hash>(
+        &self,
+        items: impl IntoIterator<Item = T>,
+        mut on_result: impl FnMut(usize, Result<u8, InsertError>),
+    ) {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("insert_many_monitored", load_factor = self.load_factor()).entered();
+        for (i, x) in items.into_iter().enumerate() {
+            on_result(i, self.insert_slot(self.hash_key(&x), 0).map(|(swaps, _)| swaps));
         }
-        s
     }
-}
 
-impl Filter {
-    fn init_buckets(num_buckets: u32, num_entries: u8, finger_bits: u8)
-        -> Result<(Buckets, BucketType), ()> {
-        let n = num_buckets as usize * num_entries as usize;
-        if finger_bits == 8 {
-            Ok((Buckets::U8(vec![0u8; n]), BucketType::U8))
-        } else if finger_bits == 16 {
-            Ok((Buckets::U16(vec![0u16; n]), BucketType::U16))
-        } else {
-            Err(())
+    /// Returns the `(bucket, entry)` of the first fingerprint matching `x`,
+    /// checking the primary bucket first.
+    pub fn locate<T: ?Sized + Hash>(&self, x: &T) -> Option<(u32, u16)> {
+        self.find(self.hash_key(x)).map(|slot| self.bucket_entry(slot))
+    }
+
+    /// Inserts the value hashing to `h` with generation `tag`, returning
+    /// the swap count and final slot of its fingerprint.
+    fn insert_slot(&self, h: u64, tag: u32) -> Result<(u8, usize), InsertError> {
+        let result = match self.bucket_type {
+            BucketType::U8 => self.insert_word::<u8>(h, tag),
+            BucketType::U16 => self.insert_word::<u16>(h, tag),
+            BucketType::U32 => self.insert_word::<u32>(h, tag),
+        };
+        let op = match tag {
+            0 => FilterOp::InsertHash(h),
+            tag => FilterOp::InsertHashTagged(h, tag),
+        };
+        self.record_insert(op, result)
+    }
+
+    /// Inserts a fingerprint computed outside this crate, with bucket
+    /// `index` as one of its candidates, for systems sharing a fingerprint
+    /// space. The other candidates are derived from `finger` as usual.
+    /// Returns the swap count, as `insert`.
+    ///
+    /// Fails with `InvalidFingerprint` unless `index` is below
+    /// `num_buckets` and `finger` is a valid fingerprint: it must fit in
+    /// `finger_bits - generation_bits` bits and, without an occupancy
+    /// bitmap, must not be 0.
+    pub fn insert_fingerprint(&self, index: u64, finger: u64) -> Result<u8, InsertError> {
+        if !self.valid_fingerprint(index, finger) {
+            return Err(InsertError::InvalidFingerprint);
         }
+        let (bucket, finger) = (index as u32, finger as u32);
+        let result = self.insert_finger(finger, bucket);
+        self.record_insert(FilterOp::InsertFingerprint(bucket, finger), result)
+            .map(|(swaps, _)| swaps)
     }
-}
 
-impl Filter {
-    /// Hashes an arbitrary value and returns (fingerprint, index).
-    /// Fingerprint cannot be 0.
-    fn finger8_index<T: ?Sized + Hash>(&self, x: &T) -> (u8, u32) {
-        let h = hash64(x);
-        let finger = ((h >> 32) % 255) as u8 + 1u8;
-        let index = (h as u32) % self.num_buckets;
-        (finger, index)
+    /// True if `finger` is stored in bucket `index` or one of its
+    /// alternate buckets. False if they are out of range (see
+    /// `insert_fingerprint`), as such a fingerprint cannot be stored.
+    pub fn contains_fingerprint(&self, index: u64, finger: u64) -> bool {
+        let found = self.valid_fingerprint(index, finger)
+            && self.probe(&self.finger_candidates(index as u32, finger as u32), finger as u32).is_ok();
+        self.count_query(found);
+        found
     }
 
-    /// Hashes an arbitrary value and returns (fingerprint, index).
-    /// Fingerprint cannot be 0.
-    fn finger16_index<T: ?Sized + Hash>(&self, x: &T) -> (u16, u32) {
-        let h = hash64(x);
-        let finger = ((h >> 32) % 65535) as u16 + 1u16;
-        let index = (h as u32) % self.num_buckets;
-        (finger, index)
+    /// The fingerprint and primary bucket of `x`, for `contains_prehashed`.
+    /// Any filter whose configuration agrees on `finger_bits`,
+    /// `num_buckets`, `hash_count`, `occupancy_bitmap`, `generation_bits`
+    /// and `domain` derives the same pair, so a value hashed once can be
+    /// looked up in many such filters.
+    pub fn prehash<T: ?Sized + Hash>(&self, x: &T) -> (u64, u32) {
+        let (finger, primary) = self.candidates(self.hash_key(x));
+        (finger as u64, primary[0])
     }
 
-    /// Hashes an arbitrary value.
-    fn index<T: ?Sized + Hash>(&self, x: &T) -> u32 {
-        (hash64(x) as u32) % self.num_buckets
+    /// `contains` for a value given by `prehash`, skipping the hashing of
+    /// the value (the alternate buckets are still hashed from `finger`).
+    pub fn contains_prehashed(&self, finger: u64, primary: u32) -> bool {
+        self.contains_fingerprint(primary as u64, finger)
     }
-}
 
-/// Hashes an arbitrary value.
-fn hash64<T: ?Sized + Hash>(x: &T) -> u64 {
-    let mut hasher = DefaultHasher::new();
+    /// Removes one copy of `finger` from bucket `index` or one of its
+    /// alternate buckets, checking `index` first, and returns whether one
+    /// was found. False if they are out of range (see `insert_fingerprint`).
+    pub fn remove_fingerprint(&self, index: u64, finger: u64) -> bool {
+        let (bucket, finger) = (index as u32, finger as u32);
+        let found = if self.valid_fingerprint(index, finger as u64) {
+            self.probe(&self.finger_candidates(bucket, finger), finger).ok()
+        } else {
+            None
+        };
+        if let Some(i) = found {
+            self.clear_slot(i);
+        }
+        self.record_remove(FilterOp::RemoveFingerprint(bucket, finger), found.is_some())
+    }
+
+    fn valid_fingerprint(&self, index: u64, finger: u64) -> bool {
+        index < self.num_buckets as u64
+            && finger <= self.key_mask() as u64
+            && (finger != 0 || self.occupancy_bitmap)
+    }
+
+    /// Returns `Ok(true)` if `x` is already present, otherwise inserts it
+    /// and returns `Ok(false)`. The key is hashed once and each candidate
+    /// bucket is scanned once, for both the lookup and a free entry.
+    pub fn contains_or_insert<T: ?Sized + Hash>(&self, x: &T) -> Result<bool, InsertError> {
+        let h = self.hash_key(x);
+        let (finger, candidates) = self.candidates(h);
+        let result = match self.probe(&candidates, finger) {
+            Ok(_) => return Ok(true),
+            Err(empty) => self.place(finger, &candidates, empty),
+        };
+        self.record_insert(FilterOp::InsertHash(h), result).map(|_| false)
+    }
+
+    /// Reports whether inserting `x` now would find a free entry or have to
+    /// kick, without modifying the filter.
+    pub fn estimate_insert_cost<T: ?Sized + Hash>(&self, x: &T) -> InsertCost {
+        let (_, candidates) = self.candidates(self.hash_key(x));
+        let buckets = self.buckets.borrow();
+        let entries = self.num_entries as usize;
+        let free = candidates.iter().any(|&b| {
+            let start = b as usize * entries;
+            buckets.first_free(start .. start + entries).is_some()
+        });
+        if free { InsertCost::Immediate } else { InsertCost::Eviction }
+    }
+
+    /// Fingerprint and candidate buckets, primary first, of the value
+    /// hashing to `h`.
+    fn candidates(&self, h: u64) -> (u32, Candidates) {
+        match self.bucket_type {
+            BucketType::U8 => self.candidates_word::<u8>(h),
+            BucketType::U16 => self.candidates_word::<u16>(h),
+            BucketType::U32 => self.candidates_word::<u32>(h),
+        }
+    }
+
+    fn candidates_word<W: FingerprintWord>(&self, h: u64) -> (u32, Candidates) {
+        let (finger, idx_1) = self.finger_index::<W>(h);
+        (finger.widen(), self.word_candidates(idx_1, finger))
+    }
+
+    /// Stores `finger` in the slot `empty` found by `probe`, or by kicking
+    /// when every candidate bucket was full.
+    fn place(&self, finger: u32, candidates: &[u32], empty: Option<usize>)
+        -> Result<(u8, usize), InsertError> {
+        match (empty, &self.bucket_type) {
+            (Some(slot), _) => Ok((0, self.fill_slot(slot, finger))),
+            (None, BucketType::U8) => self.kick(u8::from_u64(finger as u64), candidates),
+            (None, BucketType::U16) => self.kick(u16::from_u64(finger as u64), candidates),
+            (None, BucketType::U32) => self.kick(finger, candidates),
+        }
+    }
+
+    /// Updates `used`, the observer and the op log after an insert attempt,
+    /// which `op` describes.
+    fn record_insert(&self, op: FilterOp, result: Result<(u8, usize), InsertError>)
+        -> Result<(u8, usize), InsertError> {
+        if result.is_ok() {
+            self.used.replace_with(|&mut x| x.saturating_add(1));
+            self.record_op(op);
+        }
+        if let Some(ref observer) = self.observer {
+            match result {
+                Ok((swaps, _)) => observer.on_insert(swaps as u32),
+                Err(_) => observer.on_insert_failed(self.load_factor()),
+            }
+        }
+        result
+    }
+
+    /// Returns true if `x` may have been inserted. False positives are
+    /// possible; false negatives are not.
+    pub fn contains<T: ?Sized + Hash>(&self, x: &T) -> bool {
+        self.query(x) == Answer::ProbablyPresent
+    }
+
+    /// `contains`, spelling out that a hit is only probable.
+    pub fn query<T: ?Sized + Hash>(&self, x: &T) -> Answer {
+        if self.contains_hash(self.hash_key(x)) {
+            Answer::ProbablyPresent
+        } else {
+            Answer::DefinitelyAbsent
+        }
+    }
+
+    /// `contains` for each of `items`, in order.
+    pub fn contains_all<T: Hash>(&self, items: &[T]) -> Vec<bool> {
+        items.iter().map(|x| self.contains(x)).collect()
+    }
+
+    /// Confidence in the answer `contains` would give for `x`: 1.0 when no
+    /// fingerprint matches (definitely absent), otherwise
+    /// `1 - estimated_fpp()` that it is really present. Does not count
+    /// towards `query_stats`.
+    pub fn membership_confidence<T: ?Sized + Hash>(&self, x: &T) -> f64 {
+        match self.find(self.hash_key(x)) {
+            Some(_) => 1.0 - self.estimated_fpp(),
+            None => 1.0,
+        }
+    }
+
+    /// `contains`, also telling whether the match was in the primary
+    /// bucket, an alternate bucket or both. Counts towards `query_stats`
+    /// as `contains` does.
+    pub fn contains_detailed<T: ?Sized + Hash>(&self, x: &T) -> Match {
+        let h = self.hash_key(x);
+        let found = match self.bucket_type {
+            BucketType::U8 => self.match_word::<u8>(h),
+            BucketType::U16 => self.match_word::<u16>(h),
+            BucketType::U32 => self.match_word::<u32>(h),
+        };
+        self.count_query(found != Match::None);
+        found
+    }
+
+    fn match_word<W: FingerprintWord>(&self, h: u64) -> Match {
+        let (finger, idx_1) = self.finger_index::<W>(h);
+        let primary = self.find_in(idx_1, finger).is_some();
+        let alternate = self.alternates_word(finger).iter().any(|&b| self.find_in(b, finger).is_some());
+        match (primary, alternate) {
+            (false, false) => Match::None,
+            (true, false) => Match::Primary,
+            (false, true) => Match::Alternate,
+            (true, true) => Match::Both,
+        }
+    }
+
+    /// `contains` for a value given by its hash (see `Filter::hash_key`).
+    pub fn contains_hash(&self, h: u64) -> bool {
+        let found = self.find(h).is_some();
+        self.count_query(found);
+        found
+    }
+
+    /// Counts a lookup in `query_stats`, if tracking queries.
+    fn count_query(&self, found: bool) {
+        if self.track_queries {
+            let mut stats = self.query_stats.borrow_mut();
+            if found {
+                stats.hits += 1;
+            } else {
+                stats.misses += 1;
+            }
+        }
+    }
+
+    /// Removes one fingerprint matching `x`, returning whether one was
+    /// found. Only delete values that were actually inserted; otherwise a
+    /// colliding fingerprint of another value may be removed.
+    pub fn delete<T: ?Sized + Hash>(&self, x: &T) -> bool {
+        self.delete_hash(self.hash_key(x))
+    }
+
+    /// `delete` for a value given by its hash (see `Filter::hash_key`).
+    pub fn delete_hash(&self, h: u64) -> bool {
+        let found = self.find(h);
+        if let Some(i) = found {
+            self.clear_slot(i);
+        }
+        self.record_remove(FilterOp::RemoveHash(h), found.is_some())
+    }
+
+    /// Updates `used`, the observer and the op log after a delete attempt,
+    /// which `op` describes.
+    fn record_remove(&self, op: FilterOp, found: bool) -> bool {
+        if found {
+            self.used.replace_with(|&mut x| x.saturating_sub(1));
+            self.record_op(op);
+        }
+        if let Some(ref observer) = self.observer {
+            observer.on_remove(found);
+        }
+        found
+    }
+
+    /// Empties the filter.
+    pub fn clear(&self) {
+        self.buckets.borrow_mut().clear();
+        self.used.replace(0);
+        self.record_op(FilterOp::Clear);
+    }
+
+    /// Returns the slot holding a fingerprint of the value hashing to `h`,
+    /// checking the candidate buckets in order, primary first.
+    fn find(&self, h: u64) -> Option<usize> {
+        match self.bucket_type {
+            BucketType::U8 => self.find_word::<u8>(h),
+            BucketType::U16 => self.find_word::<u16>(h),
+            BucketType::U32 => self.find_word::<u32>(h),
+        }
+    }
+
+    fn find_word<W: FingerprintWord>(&self, h: u64) -> Option<usize> {
+        self.find_word_in::<W>(&self.buckets.borrow(), h)
+    }
+
+    /// `find_word` in `buckets`, which the caller has borrowed.
+    fn find_word_in<W: FingerprintWord>(&self, buckets: &Buckets, h: u64) -> Option<usize> {
+        let (finger, idx_1) = self.finger_index::<W>(h);
+        let mask = self.key_mask();
+        buckets.find_in(idx_1, finger, mask)
+            .or_else(|| self.alternates_word(finger).iter().find_map(|&b| buckets.find_in(b, finger, mask)))
+    }
+}
+
+impl Filter {
+    fn insert_word<W: FingerprintWord>(&self, h: u64, tag: u32) -> Result<(u8, usize), InsertError> {
+        let (finger, idx_1) = self.finger_index::<W>(h);
+        let stored = self.scheme().tagged(finger, tag);
+        let candidates = self.word_candidates(idx_1, finger);
+
+        // Try to place fingerprint in empty entry
+        if let Some(slot) = candidates.iter().find_map(|&b| self.try_insert(b, stored)) {
+            return Ok((0, slot));
+        }
+        self.kick(stored, &candidates)
+    }
+
+    /// Places `finger` by relocating existing fingerprints, when all of
+    /// its candidate buckets are full.
+    fn kick<W: FingerprintWord>(&self, finger: W, candidates: &[u32])
+        -> Result<(u8, usize), InsertError> {
+        if self.num_buckets == 1 {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(
+                load_factor = self.load_factor(),
+                victim = finger.widen() as u64,
+                "insert failed: single full bucket"
+            );
+            return Err(InsertError::Degenerate);
+        }
+        let mut path = self.kick_path.as_ref().map(|path| path.borrow_mut());
+        if let Some(ref mut path) = path {
+            path.clear();
+        }
+        let mut slots = FilterSlots { filter: self, path };
+        match kick::walk(&mut slots, &mut *self.rng.borrow_mut(), finger, candidates, self.max_swaps) {
+            Walk::Placed(swaps, slot) => {
+                #[cfg(feature = "tracing")]
+                self.trace_kicks(swaps);
+                Ok((swaps, slot))
+            },
+            Walk::Cycle(_swaps, _victim) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(
+                    load_factor = self.load_factor(),
+                    victim = _victim.widen() as u64,
+                    swaps = _swaps,
+                    "insert failed: kick cycle"
+                );
+                Err(InsertError::Cycle)
+            },
+            Walk::Full(_victim) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(
+                    load_factor = self.load_factor(),
+                    victim = _victim.widen() as u64,
+                    swaps = self.max_swaps,
+                    "insert failed"
+                );
+                Err(InsertError::Full)
+            },
+        }
+    }
+}
+
+/// A filter's slots as a kick walk moves through them, recording the
+/// path when `Config::trace_kicks` is set.
+struct FilterSlots<'a> {
+    filter: &'a Filter,
+    path: Option<RefMut<'a, Vec<KickStep>>>,
+}
+
+impl<'a, W: FingerprintWord> KickSlots<W> for FilterSlots<'a> {
+    fn num_entries(&self) -> u16 {
+        self.filter.num_entries
+    }
+
+    fn alternates(&self, finger: W) -> Candidates {
+        self.filter.alternates_word(finger)
+    }
+
+    fn get(&self, bucket: u32, entry: u16) -> Option<W> {
+        let i = self.filter.slot(bucket, entry);
+        self.filter.buckets.borrow().entry(i).map(|finger| W::from_u64(finger as u64))
+    }
+
+    fn try_insert(&mut self, bucket: u32, finger: W) -> Option<usize> {
+        self.filter.try_insert(bucket, finger)
+    }
+
+    fn swap(&mut self, bucket: u32, entry: u16, finger: W) -> W {
+        self.filter.swap(bucket, entry, finger)
+    }
+
+    fn on_swap(&mut self, bucket: u32, entry: u16, evicted: W) {
+        if let Some(ref mut path) = self.path {
+            path.push(KickStep { bucket, entry, evicted: evicted.widen() });
+        }
+    }
+}
+
+/// Kick chains longer than this are reported when `tracing` is enabled.
+#[cfg(feature = "tracing")]
+const KICK_CHAIN_THRESHOLD: u8 = 16;
+
+#[cfg(feature = "tracing")]
+impl Filter {
+    fn trace_kicks(&self, swaps: u8) {
+        if swaps > KICK_CHAIN_THRESHOLD {
+            tracing::debug!(swaps, load_factor = self.load_factor(), "long kick chain");
+        }
+    }
+}
+
+impl Filter {
+    fn try_insert<W: FingerprintWord>(&self, bucket: u32, finger: W) -> Option<usize> {
+        let mut buckets = self.buckets.borrow_mut();
+        let entries = self.num_entries as usize;
+        let start = bucket as usize * entries;
+        let i = buckets.first_free(start .. start + entries)?;
+        buckets.put(i, finger.widen());
+        Some(i)
+    }
+}
+
+impl Filter {
+    fn find_in<W: FingerprintWord>(&self, bucket: u32, finger: W) -> Option<usize> {
+        self.buckets.borrow().find_in(bucket, finger, self.key_mask())
+    }
+
+    /// True if both filters have the same fingerprint width and bucket
+    /// layout.
+    fn same_geometry(&self, other: &Filter) -> bool {
+        self.finger_bits == other.finger_bits
+            && self.num_buckets == other.num_buckets
+            && self.num_entries == other.num_entries
+    }
+
+    /// Position of `entry` of `bucket` in the bucket vector; in range, as
+    /// construction checked `Config::slot_count`.
+    fn slot(&self, bucket: u32, entry: u16) -> usize {
+        bucket as usize * self.num_entries as usize + entry as usize
+    }
+
+    /// Inverse of `slot`.
+    fn bucket_entry(&self, slot: usize) -> (u32, u16) {
+        let entries = self.num_entries as usize;
+        ((slot / entries) as u32, (slot % entries) as u16)
+    }
+
+    /// Scans each candidate bucket once, returning the first slot holding
+    /// `finger` (with any generation), or else the first empty slot if any.
+    fn probe(&self, candidates: &[u32], finger: u32) -> Result<usize, Option<usize>> {
+        let buckets = self.buckets.borrow();
+        let entries = self.num_entries as usize;
+        let mask = self.key_mask();
+        let mut empty = None;
+        for &bucket in candidates {
+            let start = bucket as usize * entries;
+            for i in start .. start + entries {
+                match buckets.entry(i) {
+                    Some(x) if x & mask == finger => return Ok(i),
+                    None if empty.is_none() => empty = Some(i),
+                    _ => {},
+                }
+            }
+        }
+        Err(empty)
+    }
+
+    /// Stores `finger` in the empty slot `i`, returning `i`.
+    fn fill_slot(&self, i: usize, finger: u32) -> usize {
+        self.buckets.borrow_mut().put(i, finger);
+        i
+    }
+
+    fn clear_slot(&self, i: usize) {
+        self.buckets.borrow_mut().remove(i);
+    }
+}
+
+impl Filter {
+    fn swap<W: FingerprintWord>(&self, index: u32, entry: u16, finger: W) -> W {
+        let mut buckets = self.buckets.borrow_mut();
+        let i = self.slot(index, entry);
+        let p = buckets.pos(i);
+        match W::slice_mut(&mut buckets.slots) {
+            Some(vec) => {
+                let x = vec[p];
+                vec[p] = finger;
+                buckets.note_write(i, Some(x.widen()));
+                buckets.checksum ^= slot_checksum(i, x.widen()) ^ slot_checksum(i, finger.widen());
+                x
+            },
+            None => {
+                let x = buckets.get(i);
+                buckets.put(i, finger.widen());
+                W::from_u64(x as u64)
+            },
+        }
+    }
+}
+
+#[cfg(any(test, feature = "testkit"))]
+impl Filter {
+    /// Sets random empty slots to random valid fingerprints, with random
+    /// generation tags, until the load factor reaches `target_load`. The
+    /// fingerprints do not correspond to any key; intended for setting up
+    /// lookup benchmarks and tests at a known load, so only built for
+    /// tests and with the `testkit` feature. The writes are not recorded
+    /// in the op log, so a replica following it no longer matches.
+    pub fn fill_random(&self, target_load: f64) {
+        let target = (target_load.clamp(0.0, 1.0) * self.capacity() as f64) as u64;
+        let mut used = self.used.borrow_mut();
+        let mut rng = self.rng.borrow_mut();
+        let mut buckets = self.buckets.borrow_mut();
+        let n = buckets.len();
+        let lowest: u64 = if self.occupancy_bitmap { 0 } else { 1 };
+        let scheme = self.scheme();
+        while *used < target {
+            let i = rng.gen_range(0, n);
+            if !buckets.is_occupied(i) {
+                // The part below the generation tag is never 0 without a
+                // bitmap, as for fingerprints derived from hashes
+                let key = rng.gen_range(lowest, scheme.key_max() + 1) as u32;
+                let tag = match self.generation_bits {
+                    0 => 0,
+                    bits => rng.gen_range(0, 1 << bits),
+                };
+                buckets.put(i, scheme.tagged(key, tag));
+                *used += 1;
+            }
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = String::new();
+        let entries = self.num_entries as usize;
+        let buckets = self.buckets.borrow();
+        let n = buckets.len();
+        // Digits of the largest fingerprint, e.g. 3 for 8 bits
+        let width = (u32::MAX >> (32 - self.finger_bits)).to_string().len();
+        for i in 0 .. n {
+            if i % entries == 0 {
+                s.push_str(&format!("{:3} [", i / entries));
+            }
+            s.push_str(&format!(" {:width$} ", buckets.get(i)));
+            if i % entries == entries - 1 {
+                if i == n - 1 {
+                    s.push(']');
+                } else {
+                    s.push_str("]\n");
+                }
+            }
+        }
+        f.write_str(&s)
+    }
+}
+
+impl Filter {
+    fn init_buckets(c: &Config) -> Result<(Buckets, BucketType), ()> {
+        let n = c.slot_count().ok_or(())?;
+        let bucket_type = match c.finger_bits {
+            1 ..= 8 => BucketType::U8,
+            9 ..= 16 => BucketType::U16,
+            17 ..= 32 => BucketType::U32,
+            _ => return Err(()),
+        };
+        let buckets = match c.sparse_until {
+            Some(t) if (0.0 ..= 1.0).contains(&t) => {
+                let limit = (t * n as f64) as u64;
+                Buckets {
+                    slots: Slots::Sparse(Box::new(SparseSlots::new(c, n, limit))),
+                    occupied: None,
+                    layout: c.layout,
+                    num_buckets: c.num_buckets as usize,
+                    num_entries: c.num_entries as usize,
+                    journal: None,
+                    checksum: 0,
+                }
+            },
+            Some(_) => return Err(()),
+            None => Buckets::dense(c, n),
+        };
+        Ok((buckets, bucket_type))
+    }
+}
+
+impl Filter {
+    /// Splits a value's hash into (fingerprint, index).
+    /// Fingerprint cannot be 0 unless occupancy is tracked in a bitmap.
+    fn finger_index<W: FingerprintWord>(&self, h: u64) -> (W, u32) {
+        self.scheme().finger_index(h)
+    }
+
+    /// Alternate buckets of a stored fingerprint.
+    fn alternates(&self, finger: u32) -> Candidates {
+        match self.bucket_type {
+            BucketType::U8 => self.alternates_word(u8::from_u64(finger as u64)),
+            BucketType::U16 => self.scheme().alternates(u16::from_u64(finger as u64)),
+            BucketType::U32 => self.scheme().alternates(finger),
+        }
+    }
+
+    /// `Scheme::alternates`, read from `alt_table` when there is one.
+    #[inline]
+    fn alternates_word<W: FingerprintWord>(&self, stored: W) -> Candidates {
+        match self.alt_table {
+            Some(ref table) => table[stored.widen() as usize],
+            None => self.scheme().alternates(stored),
+        }
+    }
+
+    /// `Scheme::candidates`, with the alternates from `alternates_word`.
+    fn word_candidates<W: FingerprintWord>(&self, index: u32, finger: W) -> Candidates {
+        let mut candidates = Candidates::default();
+        candidates.push(index);
+        self.alternates_word(finger).iter().for_each(|&b| candidates.push(b));
+        candidates
+    }
+
+    /// `bucket` followed by the alternate buckets of a stored fingerprint.
+    fn finger_candidates(&self, bucket: u32, finger: u32) -> Candidates {
+        let mut candidates = Candidates::default();
+        candidates.push(bucket);
+        self.alternates(finger).iter().for_each(|&b| candidates.push(b));
+        candidates
+    }
+
+    /// Bits of a stored fingerprint below its generation tag.
+    fn key_mask(&self) -> u32 {
+        u32::MAX >> (32 - (self.finger_bits - self.generation_bits))
+    }
+
+    fn scheme(&self) -> Scheme {
+        Scheme {
+            num_buckets: self.num_buckets,
+            hash_count: self.hash_count,
+            num_hashes: self.num_hashes,
+            occupancy_bitmap: self.occupancy_bitmap,
+            finger_bits: self.finger_bits,
+            tag_bits: self.generation_bits,
+        }
+    }
+}
+
+/// The settings that map a value's hash to its fingerprint and buckets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Scheme {
+    pub(crate) num_buckets: u32,
+    pub(crate) hash_count: u8,
+    pub(crate) num_hashes: u8,
+    pub(crate) occupancy_bitmap: bool,
+    pub(crate) finger_bits: u8,
+    /// Top fingerprint bits holding a generation tag
+    pub(crate) tag_bits: u8,
+}
+
+impl Scheme {
+    /// Splits a value's hash into (fingerprint, index).
+    /// Fingerprint cannot be 0 unless occupancy is tracked in a bitmap.
+    pub(crate) fn finger_index<W: FingerprintWord>(self, h: u64) -> (W, u32) {
+        let finger = W::from_hash(self.finger_hash(h), self.occupancy_bitmap, self.key_max());
+        let index = (h as u32) % self.num_buckets;
+        (finger, index)
+    }
+
+    /// Bits of a value's hash the fingerprint is taken from.
+    fn finger_hash(self, h: u64) -> u64 {
+        if self.hash_count > 1 {
+            remix(h ^ FINGER_SALT)
+        } else {
+            h >> 32
+        }
+    }
+
+    /// Hashes a fingerprint to its alternate index.
+    pub(crate) fn index<T: ?Sized + Hash>(self, x: &T) -> u32 {
+        let h = if self.hash_count > 1 {
+            hash64(&(ALT_INDEX_SALT, x))
+        } else {
+            hash64(x)
+        };
+        (h as u32) % self.num_buckets
+    }
+
+    /// Alternate bucket of a stored fingerprint, ignoring its generation.
+    pub(crate) fn alt_index<W: FingerprintWord>(self, stored: W) -> u32 {
+        self.index(&self.untag(stored))
+    }
+
+    /// The `num_hashes - 1` alternate buckets of a stored fingerprint,
+    /// ignoring its generation: `alt_index` first, then further ones
+    /// hashed with their position.
+    pub(crate) fn alternates<W: FingerprintWord>(self, stored: W) -> Candidates {
+        let finger = self.untag(stored);
+        let mut alternates = Candidates::default();
+        alternates.push(self.index(&finger));
+        for j in 2 .. self.num_hashes {
+            alternates.push(self.index(&(j, finger)));
+        }
+        alternates
+    }
+
+    /// Candidate buckets of a value with primary bucket `index` and
+    /// fingerprint `finger`, primary first.
+    pub(crate) fn candidates<W: FingerprintWord>(self, index: u32, finger: W) -> Candidates {
+        let mut candidates = Candidates::default();
+        candidates.push(index);
+        self.alternates(finger).iter().for_each(|&b| candidates.push(b));
+        candidates
+    }
+
+    /// Stored form of `finger` with generation `tag` in its top bits.
+    pub(crate) fn tagged<W: FingerprintWord>(self, finger: W, tag: u32) -> W {
+        match self.tag_bits {
+            0 => finger,
+            bits => W::from_u64(finger.widen() as u64 | (tag as u64) << (self.finger_bits - bits)),
+        }
+    }
+
+    /// `stored` with its generation tag cleared.
+    pub(crate) fn untag<W: FingerprintWord>(self, stored: W) -> W {
+        W::from_u64(stored.widen() as u64 & self.key_max())
+    }
+
+    /// Largest fingerprint below the generation tag.
+    fn key_max(self) -> u64 {
+        (u64::MAX >> (64 - self.finger_bits)) >> self.tag_bits
+    }
+}
+
+/// Most candidate buckets per value (see `Config::num_hashes`).
+pub const MAX_HASHES: usize = 4;
+
+/// Candidate buckets of a value, or alternate buckets of a fingerprint.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Candidates {
+    buckets: [u32; MAX_HASHES],
+    len: usize,
+}
+
+impl Candidates {
+    fn push(&mut self, bucket: u32) {
+        self.buckets[self.len] = bucket;
+        self.len += 1;
+    }
+}
+
+impl std::ops::Deref for Candidates {
+    type Target = [u32];
+
+    fn deref(&self) -> &[u32] {
+        &self.buckets[.. self.len]
+    }
+}
+
+/// Returns the 64-bit hash a filter derives a value's fingerprint and
+/// primary bucket from, for use with `insert_hash` and friends. Only valid
+/// for filters without a `Config::domain`; see `Filter::hash_key`.
+pub fn hash_key<T: ?Sized + Hash>(x: &T) -> u64 {
+    hash64(x)
+}
+
+/// Hash of a value in `domain` (see `Config::domain`).
+pub(crate) fn domain_hash<T: ?Sized + Hash>(domain: Option<u64>, x: &T) -> u64 {
+    match domain {
+        Some(d) => hash64(&(d, x)),
+        None => hash64(x),
+    }
+}
+
+/// Salts for the derivations used when `hash_count` is 2.
+const FINGER_SALT: u64 = 0x9e37_79b9_7f4a_7c15;
+const ALT_INDEX_SALT: u64 = 0xc2b2_ae3d_27d4_eb4f;
+
+/// SplitMix64 finalizer; spreads every input bit over the output.
+fn remix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Hashes an arbitrary value.
+fn hash64<T: ?Sized + Hash>(x: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
     x.hash(&mut hasher);
     hasher.finish()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Seeded configuration of `num_buckets` buckets of 4 16-bit entries.
+    pub(crate) fn config(num_buckets: u32) -> Config {
+        Config { num_buckets, seed: Some(1), ..Config::default() }
+    }
+
+    /// The standard membership suite, for an empty filter that holds `n`
+    /// values without failing: inserts them, checks that each is found and
+    /// counted, deletes every other one and checks that the rest are still
+    /// found, then deletes the rest.
+    pub(crate) fn check_membership(filter: &Filter, n: u64) {
+        for i in 0 .. n {
+            filter.insert(&i).unwrap();
+        }
+        assert_eq!(filter.used(), n);
+        assert!((0 .. n).all(|i| filter.contains(&i)));
+        for i in (0 .. n).step_by(2) {
+            assert!(filter.delete(&i));
+        }
+        assert_eq!(filter.used(), n / 2);
+        assert!((1 .. n).step_by(2).all(|i| filter.contains(&i)));
+        for i in (1 .. n).step_by(2) {
+            assert!(filter.delete(&i));
+        }
+        assert_eq!(filter.used(), 0);
+        assert_eq!(filter.checksum(), 0);
+    }
+
+    #[test]
+    fn fill_random_reaches_target_load() {
+        for &(finger_bits, occupancy_bitmap) in &[(8, false), (12, false), (16, true), (32, false)] {
+            let filter = Filter::new(&Config { finger_bits, occupancy_bitmap, ..config(1000) }).unwrap();
+            filter.fill_random(0.5);
+            assert!((filter.load_factor() - 0.5).abs() < 0.001, "{} bits: {}", finger_bits, filter.load_factor());
+            filter.fill_random(0.9);
+            assert!((filter.load_factor() - 0.9).abs() < 0.001);
+            let occupied = (0 .. filter.capacity() as usize).filter(|&i| filter.buckets.borrow().is_occupied(i)).count();
+            assert_eq!(occupied as u64, filter.used());
+        }
+    }
+
+    #[test]
+    fn fill_random_tags_valid_fingerprints() {
+        let filter = Filter::new(&Config { finger_bits: 8, generation_bits: 4, ..config(1000) }).unwrap();
+        filter.fill_random(0.9);
+        let mut tags = [0u64; 16];
+        for i in 0 .. filter.capacity() as usize {
+            if let Some(x) = filter.buckets.borrow().entry(i) {
+                assert_ne!(x & filter.key_mask(), 0);
+                tags[(x >> 4) as usize] += 1;
+            }
+        }
+        assert!(tags.iter().all(|&n| n > 0), "{:?}", tags);
+        assert!(filter.fingerprint_skew().samples > 0);
+    }
+
+    #[test]
+    fn slot_count_at_boundaries() {
+        let c = |num_buckets, num_entries, finger_bits| Config { num_buckets, num_entries, finger_bits, ..config(1) };
+        assert_eq!(c(1, 1, 1).slot_count(), Some(1));
+        assert_eq!(c(1024, 4, 16).slot_count(), Some(4096));
+        let largest = c(u32::MAX, u16::MAX, 32).slot_count();
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(largest, Some(u32::MAX as usize * 65535));
+        #[cfg(target_pointer_width = "32")]
+        {
+            assert_eq!(largest, None);
+            // 2^31 - 4 bytes fit below isize::MAX, 2^31 do not
+            assert_eq!(c((1 << 29) - 1, 4, 8).slot_count(), Some((1 << 31) - 4));
+            assert_eq!(c(1 << 29, 4, 8).slot_count(), None);
+            assert_eq!(c(1 << 28, 4, 16).slot_count(), None);
+        }
+    }
+
+    #[test]
+    fn slot_position_round_trips() {
+        let filter = Filter::new(&Config { num_entries: 1024, ..config(1000) }).unwrap();
+        for &(bucket, entry) in &[(0, 0), (0, 1023), (999, 0), (999, 1023)] {
+            let slot = filter.slot(bucket, entry);
+            assert!(slot < filter.capacity() as usize);
+            assert_eq!(filter.bucket_entry(slot), (bucket, entry));
+        }
+        assert_eq!(filter.slot(999, 1023), 1_023_999);
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    #[test]
+    fn over_large_config_errors() {
+        assert!(Filter::new(&Config { num_buckets: 1 << 29, finger_bits: 8, ..config(1) }).is_err());
+        assert!(Filter::new(&Config { num_buckets: u32::MAX, num_entries: u16::MAX, ..config(1) }).is_err());
+    }
+
+    #[test]
+    fn within_bytes_respects_budget_and_load() {
+        for shift in 15 .. 24 {
+            let budget = 1usize << shift;
+            for &items in &[budget as u64 / 16, budget as u64 / 4, budget as u64 / 2] {
+                let filter = Filter::within_bytes(budget, items).unwrap();
+                let c = filter.config();
+                assert!(filter.memory_usage() <= budget, "{} bytes for {:?}", filter.memory_usage(), c);
+                assert!(items as f64 / filter.capacity() as f64 <= 0.9 * expected_max_load(c.num_entries));
+            }
+            // A byte per item leaves no room for 8-bit fingerprints
+            assert!(Filter::within_bytes(budget, budget as u64).is_err());
+        }
+        assert!(Filter::within_bytes(mem::size_of::<Filter>(), 1).is_err());
+        // Looser budgets never raise the false positive rate
+        let fpp = |budget| {
+            let c = Config::within_bytes(budget, 10_000).unwrap();
+            c.estimated_fpp(10_000.0 / c.capacity() as f64)
+        };
+        assert!(fpp(1 << 15) >= fpp(1 << 16) && fpp(1 << 16) >= fpp(1 << 18));
+    }
+
+    #[test]
+    fn required_bytes_matches_allocation() {
+        for finger_bits in 1 ..= 32 {
+            for &(occupancy_bitmap, bucket_rounding) in
+                &[(false, Rounding::NextPowerOfTwo), (true, Rounding::Exact), (false, Rounding::NextPrime)] {
+                let c = Config { finger_bits, occupancy_bitmap, bucket_rounding, ..config(1000) };
+                let filter = Filter::new(&c).unwrap();
+                let alt_table = filter.alt_table.as_ref().map_or(0, |table| mem::size_of_val(&**table));
+                let allocated = filter.memory_usage() - mem::size_of::<Filter>() - alt_table;
+                assert_eq!(c.required_bytes(), allocated as u64, "{:?}", c);
+            }
+        }
+    }
+
+    #[test]
+    fn compare_widths_orders_by_width() {
+        let widths = Config::compare_widths(100_000, 0.01);
+        assert_eq!(widths.first().map(|w| w.0), Some(9));
+        assert_eq!(widths.last().map(|w| w.0), Some(32));
+        for pair in widths.windows(2) {
+            assert_eq!(pair[1].0, pair[0].0 + 1);
+            assert!(pair[1].2 < pair[0].2, "{:?}", pair);
+            assert!(pair[1].1 >= pair[0].1, "{:?}", pair);
+        }
+        assert!(widths.iter().all(|&(_, _, fpp)| fpp <= 0.01));
+        // The width below the first misses the target
+        let all = Config::compare_widths(100_000, 1.0);
+        assert_eq!(all.len(), 32);
+        assert!(all[7].2 > 0.01);
+        assert!(Config::compare_widths(100_000, 1e-12).is_empty());
+        assert!(Config::compare_widths(u64::MAX, 0.01).is_empty());
+    }
+
+    #[test]
+    fn optimal_entries_meets_targets() {
+        for &(items, fpp, load) in &[(10_000, 0.01, 0.5), (10_000, 0.001, 0.9), (1_000_000, 0.001, 0.8)] {
+            let c = Config::optimal_entries(items, fpp, load).unwrap();
+            assert!(c.estimated_fpp(load) <= fpp, "{:?}", c);
+            assert!(expected_max_load(c.num_entries) >= load, "{:?}", c);
+            assert!(c.capacity() as f64 * load >= items as f64);
+            // No entry count that reaches the load does better at either width
+            for (finger_bits, num_entries) in [8u8, 16].iter().flat_map(|&b| (1 ..= 32).map(move |e| (b, e))) {
+                let num_buckets = (items as f64 / (load * num_entries as f64)).ceil() as u32;
+                let rival = Config { finger_bits, num_buckets, num_entries, ..Config::default() };
+                if expected_max_load(num_entries) >= load && rival.estimated_fpp(load) <= fpp {
+                    assert!(rival.capacity() * finger_bits as u64 >= c.capacity() * c.finger_bits as u64);
+                }
+            }
+        }
+        assert!(Config::optimal_entries(1000, 0.01, 1.0).is_err());
+        assert!(Config::optimal_entries(1000, 1e-9, 0.5).is_err());
+        assert!(Config::optimal_entries(1000, 0.01, 0.0).is_err());
+    }
+
+    #[test]
+    fn prime_bucket_counts() {
+        for &(n, prime) in &[(1, 2), (1000, 1009), (1021, 1021)] {
+            let c = Config { num_buckets: n, bucket_rounding: Rounding::NextPrime, ..config(1) };
+            let filter = Filter::new(&c).unwrap();
+            assert_eq!(filter.capacity(), 4 * prime);
+            assert_eq!(filter.config().num_buckets, prime as u32);
+        }
+        let filter = Filter::new(&Config { bucket_rounding: Rounding::NextPrime, ..config(1000) }).unwrap();
+        check_membership(&filter, 2000);
+        // Structured keys still spread evenly over primary buckets
+        let mut counts = vec![0u32; 1009];
+        for i in 0 .. 100_000u64 {
+            counts[filter.prehash(&(i * 1024)).1 as usize] += 1;
+        }
+        let (min, max) = (counts.iter().min().unwrap(), counts.iter().max().unwrap());
+        assert!(*min > 50 && *max < 150, "{} to {}", min, max);
+    }
+
+    #[test]
+    fn unroundable_bucket_counts_error() {
+        for &bucket_rounding in &[Rounding::NextPowerOfTwo, Rounding::NextPrime] {
+            let c = Config { num_buckets: u32::MAX, bucket_rounding, ..config(1) };
+            assert_eq!(c.rounded_buckets(), None);
+            assert!(Filter::new(&c).is_err());
+            assert!(DualFilter::new(&c).is_err());
+        }
+        assert!(Filter::new(&Config { bucket_rounding: Rounding::NextPrime, ..config(0) }).is_err());
+    }
+
+    #[test]
+    fn config_rebuilds_compatible_filter() {
+        let c = Config {
+            finger_bits: 12,
+            num_buckets: 1000,
+            bucket_rounding: Rounding::NextPowerOfTwo,
+            num_entries: 2,
+            max_swaps: 50,
+            generation_bits: 4,
+            layout: Layout::ColumnMajor,
+            ..config(1)
+        };
+        let filter = Filter::new(&c).unwrap();
+        let copy = Filter::new(&filter.config()).unwrap();
+        assert_eq!(copy.capacity(), filter.capacity());
+        assert_eq!(copy.capacity(), 2048);
+        assert_eq!(copy.config(), filter.config());
+        assert_eq!(copy.memory_usage(), filter.memory_usage());
+        for i in 0 .. 500u64 {
+            filter.insert(&i).unwrap();
+        }
+        assert!(copy.merge(&filter).is_ok());
+    }
+
+    #[test]
+    fn headroom_shrinks_as_filter_fills() {
+        let filter = Filter::new(&config(1024)).unwrap();
+        let mut headroom = filter.estimated_headroom();
+        assert_eq!(headroom, (0.65 * 4096.0) as u64);
+        let mut i = 0u64;
+        while filter.insert(&i).is_ok() {
+            let next = filter.estimated_headroom();
+            assert!(next < headroom || headroom == 0, "{} after {} inserts", next, i + 1);
+            headroom = next;
+            i += 1;
+        }
+        // The estimate is conservative: it runs out before the first failure
+        assert_eq!(headroom, 0);
+        assert!(i >= 2662, "first failure after {} inserts", i);
+    }
+
+    #[test]
+    fn raw_fingerprints_without_hash() {
+        let filter = Filter::new(&config(1024)).unwrap();
+        let pairs: Vec<(u64, u64)> = (0 .. 2000).map(|i| (i * 7919 % 1024, 1 + i * 104_729 % 65535)).collect();
+        for &(index, finger) in &pairs {
+            filter.insert_fingerprint(index, finger).unwrap();
+        }
+        assert_eq!(filter.used(), 2000);
+        assert!(pairs.iter().all(|&(index, finger)| filter.contains_fingerprint(index, finger)));
+        let other = Filter::new(&config(1024)).unwrap();
+        assert!(pairs.iter().all(|&(index, finger)| !other.contains_fingerprint(index, finger)));
+    }
+
+    #[test]
+    fn monitored_inserts_report_each_item_in_order() {
+        let c = Config { num_entries: 2, max_swaps: 10, ..config(64) };
+        let (filter, twin) = (Filter::new(&c).unwrap(), Filter::new(&c).unwrap());
+        let mut results = Vec::new();
+        filter.insert_many_monitored(0 .. 200u64, |i, result| {
+            assert_eq!(i, results.len());
+            results.push(result);
+            // Called as each insert finishes, before the next one starts
+            assert_eq!(filter.used(), results.iter().filter(|r| r.is_ok()).count() as u64);
+        });
+        assert_eq!(results.len(), 200);
+        assert!(results.iter().any(Result::is_err));
+        assert!(results.into_iter().zip(0 .. 200u64).all(|(result, i)| result == twin.insert(&i)));
+        filter.insert_many_monitored(Vec::<u64>::new(), |_, _| panic!("no items"));
+    }
+
+    #[test]
+    fn domains_salt_placement_independently() {
+        let fill = |domain| {
+            let filter = Filter::new(&Config { finger_bits: 8, domain, ..config(1024) }).unwrap();
+            for i in 0 .. 2000u64 {
+                filter.insert(&i).unwrap();
+            }
+            filter
+        };
+        assert_eq!(fill(Some(1)).to_bytes(), fill(Some(1)).to_bytes());
+        let (a, b) = (fill(Some(1)), fill(Some(2)));
+        assert_ne!(a.to_bytes(), b.to_bytes());
+        assert_ne!(fill(None).to_bytes(), a.to_bytes());
+        // False positives of differently salted filters coincide about as
+        // often as independent events would
+        let probes = 2000 .. 202_000u64;
+        let (mut in_a, mut in_b, mut in_both) = (0.0, 0.0, 0.0);
+        for i in probes.clone() {
+            let (x, y) = (a.contains(&i), b.contains(&i));
+            in_a += x as u8 as f64;
+            in_b += y as u8 as f64;
+            in_both += (x && y) as u8 as f64;
+        }
+        let n = (probes.end - probes.start) as f64;
+        let independent = in_a * in_b / n;
+        assert!(in_a > 1000.0 && in_b > 1000.0);
+        assert!((in_both - independent).abs() < 4.0 * independent.sqrt() + 5.0, "{} vs {}", in_both, independent);
+    }
+
+    #[test]
+    fn bucket_pairs_join_stored_fingerprints_to_alternates() {
+        assert_eq!(Filter::new(&config(1024)).unwrap().bucket_pairs().count(), 0);
+        for &num_hashes in &[2, 3] {
+            let filter = Filter::new(&Config { num_hashes, ..config(1024) }).unwrap();
+            for i in 0 .. 2000u64 {
+                filter.insert(&i).unwrap();
+            }
+            let pairs: Vec<(u32, u32)> = filter.bucket_pairs().collect();
+            assert!(pairs.windows(2).all(|w| w[0] < w[1]));
+            assert!(pairs.iter().all(|&(a, b)| a <= b && b < filter.num_buckets));
+            assert!(pairs.len() as u64 <= filter.used() * (num_hashes as u64 - 1));
+            // Each value's bucket is paired with every alternate of its
+            // fingerprint
+            for i in 0 .. 2000u64 {
+                let (bucket, entry) = filter.locate(&i).unwrap();
+                let finger = filter.buckets.borrow().entry(filter.slot(bucket, entry)).unwrap();
+                for &alt in filter.alternates(finger).iter() {
+                    assert!(pairs.binary_search(&(bucket.min(alt), bucket.max(alt))).is_ok(), "{}", i);
+                }
+            }
+            // and every pair comes from a fingerprint stored at one end
+            let entries = filter.num_entries as usize;
+            let moves = |from: u32, to: u32| {
+                let buckets = filter.buckets.borrow();
+                (0 .. entries).filter_map(|e| buckets.entry(from as usize * entries + e))
+                    .any(|finger| filter.alternates(finger).contains(&to))
+            };
+            assert!(pairs.iter().all(|&(a, b)| moves(a, b) || moves(b, a)));
+        }
+    }
+
+    #[test]
+    fn query_answers_match_contains() {
+        let filter = Filter::new(&Config { track_queries: true, ..config(1024) }).unwrap();
+        for i in 0 .. 2000u64 {
+            filter.insert(&i).unwrap();
+        }
+        assert!((0 .. 2000u64).all(|i| filter.query(&i) == Answer::ProbablyPresent));
+        for i in 0 .. 1000u64 {
+            filter.delete(&i);
+        }
+        let absent = (0 .. 1000u64).chain(2000 .. 12_000).filter(|i| filter.query(i) == Answer::DefinitelyAbsent).count();
+        assert!(absent > 10_900, "{}", absent);
+        assert!((0 .. 12_000u64).all(|i| (filter.query(&i) == Answer::ProbablyPresent) == filter.contains(&i)));
+        let stats = filter.query_stats();
+        assert_eq!(stats.hits + stats.misses, 2000 + 11_000 + 2 * 12_000);
+    }
+
+    #[test]
+    fn unsized_and_non_debug_keys() {
+        // Implements Hash but not Debug
+        #[derive(Hash)]
+        struct Opaque(u64);
+
+        let filter = Filter::new(&config(1024)).unwrap();
+        for i in 0 .. 500u64 {
+            let text = format!("key-{}", i);
+            filter.insert(text.as_str()).unwrap();
+            filter.insert(text.as_bytes()).unwrap();
+            filter.insert(&Opaque(i)).unwrap();
+        }
+        assert_eq!(filter.used(), 1500);
+        for i in 0 .. 500u64 {
+            let text = format!("key-{}", i);
+            assert!(filter.contains(text.as_str()) && filter.contains(text.as_bytes()) && filter.contains(&Opaque(i)));
+            assert!(filter.delete(text.as_str()) && filter.delete(text.as_bytes()) && filter.delete(&Opaque(i)));
+        }
+        assert_eq!(filter.used(), 0);
+        let report = experiment::run_with_keys(&config(1024), (0 .. 2000).map(Opaque)).unwrap();
+        assert_eq!(report.summary.success, 2000);
+    }
+
+    #[test]
+    fn prehashed_lookups_agree_across_filters() {
+        let configs = [
+            config(1024),
+            Config { finger_bits: 12, occupancy_bitmap: true, ..config(1024) },
+            Config { finger_bits: 8, num_hashes: 3, domain: Some(7), ..config(1024) },
+        ];
+        for c in &configs {
+            // Same hashing, different kick randomness and contents
+            let filters: Vec<Filter> = (0 .. 3u64)
+                .map(|s| {
+                    let filter = Filter::new(&Config { seed: Some(s), ..c.clone() }).unwrap();
+                    for i in s * 1000 .. s * 1000 + 1500 {
+                        filter.insert(&i).unwrap();
+                    }
+                    filter
+                })
+                .collect();
+            for i in 0 .. 5000u64 {
+                let (finger, primary) = filters[0].prehash(&i);
+                for filter in &filters {
+                    assert_eq!(filter.prehash(&i), (finger, primary));
+                    assert_eq!(filter.contains_prehashed(finger, primary), filter.contains(&i), "{} in {:?}", i, c);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn contains_detailed_names_matching_buckets() {
+        let filter = Filter::new(&config(1024)).unwrap();
+        let key = (0 .. 100u64).find(|x| {
+            let (_, candidates) = filter.candidates(filter.hash_key(x));
+            candidates[0] != candidates[1]
+        }).unwrap();
+        assert_eq!(filter.contains_detailed(&key), Match::None);
+        filter.insert(&key).unwrap();
+        let (finger, primary) = filter.prehash(&key);
+        assert_eq!(filter.locate(&key).unwrap().0, primary);
+        assert_eq!(filter.contains_detailed(&key), Match::Primary);
+        // A fifth copy no longer fits in the primary bucket
+        for _ in 0 .. 4 {
+            filter.insert(&key).unwrap();
+        }
+        assert_eq!(filter.contains_detailed(&key), Match::Both);
+        filter.clear();
+        let alternate = filter.finger_candidates(primary, finger as u32)[1];
+        filter.insert_fingerprint(alternate as u64, finger).unwrap();
+        assert_eq!(filter.contains_detailed(&key), Match::Alternate);
+    }
+
+    #[test]
+    fn fingerprints_out_of_range() {
+        let filter = Filter::new(&Config { finger_bits: 12, ..config(1024) }).unwrap();
+        for &(index, finger) in &[(1024, 1), (u64::MAX, 1), (0, 0), (0, 4096), (0, u64::MAX)] {
+            assert_eq!(filter.insert_fingerprint(index, finger), Err(InsertError::InvalidFingerprint));
+            assert!(!filter.contains_fingerprint(index, finger));
+            assert!(!filter.remove_fingerprint(index, finger));
+        }
+        assert_eq!(filter.used(), 0);
+        assert!(filter.insert_fingerprint(1023, 4095).is_ok());
+        let bitmap = Filter::new(&Config { occupancy_bitmap: true, ..config(1024) }).unwrap();
+        assert!(bitmap.insert_fingerprint(0, 0).is_ok());
+        assert!(bitmap.contains_fingerprint(0, 0));
+    }
+
+    #[test]
+    fn fingerprints_agree_with_keys() {
+        let filter = Filter::new(&config(1024)).unwrap();
+        for i in 0 .. 1000u64 {
+            let (finger, primary) = filter.prehash(&i);
+            filter.insert_fingerprint(primary as u64, finger).unwrap();
+        }
+        for i in 1000 .. 2000u64 {
+            filter.insert(&i).unwrap();
+        }
+        assert!((0 .. 2000u64).all(|i| filter.contains(&i)));
+        for i in 1000 .. 2000u64 {
+            let (finger, primary) = filter.prehash(&i);
+            assert!(filter.contains_fingerprint(primary as u64, finger));
+        }
+        for i in 0 .. 1000u64 {
+            assert!(filter.delete(&i));
+            let (finger, primary) = filter.prehash(&(i + 1000));
+            assert!(filter.remove_fingerprint(primary as u64, finger));
+        }
+        assert_eq!(filter.used(), 0);
+    }
+
+    #[test]
+    fn hottest_buckets_under_skew() {
+        let filter = Filter::new(&Config { num_entries: 8, ..config(256) }).unwrap();
+        for i in 0 .. 200u64 {
+            filter.insert(&i).unwrap();
+        }
+        // Crowd buckets 17 and 200
+        for finger in 1 .. 9 {
+            filter.insert_fingerprint(17, finger).unwrap();
+            filter.insert_fingerprint(200, 100 + finger).unwrap();
+        }
+        let mut loads = vec![0u16; 256];
+        for i in 0 .. filter.capacity() as usize {
+            if filter.buckets.borrow().is_occupied(i) {
+                loads[filter.bucket_entry(i).0 as usize] += 1;
+            }
+        }
+        let hottest = filter.hottest_buckets(5);
+        assert_eq!(&hottest[.. 2], &[(17, 8), (200, 8)]);
+        assert!(hottest.windows(2).all(|w| w[0].1 >= w[1].1));
+        for &(bucket, count) in &hottest {
+            assert_eq!(loads[bucket as usize], count);
+        }
+        let coldest_reported = hottest[4].1;
+        let hotter = loads.iter().filter(|&&n| n > coldest_reported).count();
+        assert!(hotter < 5);
+        assert_eq!(filter.hottest_buckets(1000).len(), 256);
+    }
+
+    #[test]
+    fn insert_cost_immediate_until_candidates_fill() {
+        let filter = Filter::new(&config(64)).unwrap();
+        assert_eq!(filter.estimate_insert_cost(&"x"), InsertCost::Immediate);
+        let (_, candidates) = filter.candidates(filter.hash_key(&"x"));
+        for (n, &bucket) in candidates.iter().enumerate() {
+            for entry in 0 .. 4 {
+                assert_eq!(filter.estimate_insert_cost(&"x"), InsertCost::Immediate);
+                filter.fill_slot(filter.slot(bucket, entry), 1000 + 4 * n as u32 + entry as u32);
+            }
+        }
+        let before = filter.to_bytes();
+        assert_eq!(filter.estimate_insert_cost(&"x"), InsertCost::Eviction);
+        assert_eq!(filter.to_bytes(), before);
+        assert!(filter.insert(&"x").unwrap() > 0);
+    }
+
+    #[test]
+    fn domains_separate_keys() {
+        let c = Config { seed: Some(1), ..Config::for_capacity(10_000, 0.001).unwrap() };
+        let a = Filter::new(&Config { domain: Some(1), ..c.clone() }).unwrap();
+        let b = Filter::new(&Config { domain: Some(2), ..c.clone() }).unwrap();
+        // b holds exactly what a holds, placed by a's hashes
+        for i in 0 .. 10_000u64 {
+            a.insert(&i).unwrap();
+            b.insert_hash(a.hash_key(&i)).unwrap();
+        }
+        assert!((0 .. 10_000u64).all(|i| a.contains(&i)));
+        // No likelier to be found than keys never inserted
+        let found = (0 .. 10_000u64).filter(|i| b.contains(i)).count();
+        let absent = (10_000 .. 20_000u64).filter(|i| a.contains(i)).count();
+        assert!(absent > 0 && found < 2 * absent, "{} found across domains, {} absent keys found", found, absent);
+        assert_ne!(a.hash_key(&"x"), b.hash_key(&"x"));
+        let plain = Filter::new(&c).unwrap();
+        assert_eq!(plain.hash_key(&"x"), Filter::new(&c).unwrap().hash_key(&"x"));
+        assert_ne!(plain.hash_key(&"x"), a.hash_key(&"x"));
+    }
+
+    /// Key whose hash only sees its value modulo 16, as with a poor `Hash`
+    /// implementation.
+    struct Clustered(u64);
+
+    impl Hash for Clustered {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            (self.0 % 16).hash(state);
+        }
+    }
+
+    #[test]
+    fn fill_entropy_uniform_vs_clustered() {
+        let uniform = Filter::new(&config(1024)).unwrap();
+        assert_eq!(uniform.fill_entropy(), 1.0);
+        for i in 0 .. 2000u64 {
+            uniform.insert(&i).unwrap();
+        }
+        let clustered = Filter::new(&config(1024)).unwrap();
+        for i in 0 .. 2000 {
+            let _ = clustered.insert(&Clustered(i));
+        }
+        assert!(clustered.used() > 100);
+        let (high, low) = (uniform.fill_entropy(), clustered.fill_entropy());
+        assert!(high > 0.95, "{}", high);
+        assert!(low < 0.6, "{}", low);
+    }
+
+    #[test]
+    fn query_stats_count_known_probes() {
+        let filter = Filter::new(&Config { track_queries: true, ..config(1024) }).unwrap();
+        for i in 0 .. 100 {
+            filter.insert(&i).unwrap();
+        }
+        assert_eq!(filter.query_stats(), QueryStats::default());
+        let hits = (0 .. 100).filter(|i| filter.contains(i)).count() as u64;
+        let misses = (1000 .. 1050).filter(|i| !filter.contains(i)).count() as u64;
+        assert_eq!(hits, 100);
+        assert_eq!(filter.query_stats(), QueryStats { hits: 100 + 50 - misses, misses });
+
+        filter.reset_query_stats();
+        assert_eq!(filter.query_stats(), QueryStats::default());
+        assert!(filter.contains(&7));
+        assert_eq!(filter.query_stats(), QueryStats { hits: 1, misses: 0 });
+    }
+
+    #[test]
+    fn query_stats_off_by_default() {
+        let filter = Filter::new(&config(64)).unwrap();
+        filter.insert("a").unwrap();
+        assert!(filter.contains("a"));
+        assert_eq!(filter.query_stats(), QueryStats::default());
+    }
+
+    #[test]
+    fn insert_locate_returns_slot_holding_fingerprint() {
+        let filter = Filter::new(&config(128)).unwrap();
+        for i in 0 .. 400 {
+            let (bucket, entry) = filter.insert_locate(&i).unwrap();
+            let (finger, candidates) = filter.candidates(filter.hash_key(&i));
+            assert!(candidates.contains(&bucket));
+            assert_eq!(filter.buckets.borrow().entry(filter.slot(bucket, entry)), Some(finger));
+            assert_eq!(filter.locate(&i).map(|(b, e)| filter.buckets.borrow().get(filter.slot(b, e))), Some(finger));
+        }
+    }
+
+    /// Key counting how often it is hashed.
+    struct Counted<'a>(u64, &'a std::cell::Cell<u32>);
+
+    impl Hash for Counted<'_> {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.1.set(self.1.get() + 1);
+            self.0.hash(state);
+        }
+    }
+
+    #[test]
+    fn contains_or_insert_hashes_once() {
+        let filter = Filter::new(&config(64)).unwrap();
+        let calls = std::cell::Cell::new(0);
+        assert!(!filter.contains_or_insert(&Counted(1, &calls)).unwrap());
+        assert_eq!(calls.get(), 1);
+        assert!(filter.contains_or_insert(&Counted(1, &calls)).unwrap());
+        assert_eq!(calls.get(), 2);
+        assert_eq!(filter.used(), 1);
+
+        // The two-call sequence hashes twice
+        calls.set(0);
+        let key = Counted(2, &calls);
+        if !filter.contains(&key) {
+            filter.insert(&key).unwrap();
+        }
+        assert_eq!(calls.get(), 2);
+    }
+
+    /// Failed inserts out of enough attempts to fill a filter of
+    /// `hash_count` derivations to `load`.
+    fn failures_at(hash_count: u8, load: f64) -> u64 {
+        let filter = Filter::new(&Config { hash_count, ..config(4096) }).unwrap();
+        let attempts = (load * filter.capacity() as f64) as u64;
+        (0 .. attempts).filter(|i| filter.insert(i).is_err()).count() as u64
+    }
+
+    #[test]
+    fn hash_count_failures_at_95_percent() {
+        // With 4 entries inserts start failing near 80%; salting the
+        // derivations separately must not make that worse
+        let (one, two) = (failures_at(1, 0.95), failures_at(2, 0.95));
+        let attempts = (0.95 * 4096.0 * 4.0) as u64;
+        assert!(one > 0 && two > 0);
+        assert!(one < attempts / 10 && two < attempts / 10, "{} and {} of {}", one, two, attempts);
+        assert!(two as f64 <= one as f64 * 1.25, "hash_count 2: {}, 1: {}", two, one);
+        assert_eq!((failures_at(1, 0.6), failures_at(2, 0.6)), (0, 0));
+    }
+
+    #[test]
+    fn bits_per_item_at_known_fill() {
+        let filter = Filter::new(&config(1000)).unwrap();
+        assert_eq!(filter.bits_per_item(), f64::INFINITY);
+        for i in 0 .. 1600 {
+            filter.insert(&i).unwrap();
+        }
+        assert_eq!(filter.bits(), 1000 * 4 * 16);
+        assert_eq!(filter.bits_per_item(), 64_000.0 / 1600.0);
+        assert_eq!(filter.bits_per_item(), 16.0 / filter.load_factor());
+    }
+
+    #[test]
+    fn occupancy_bitmap_stores_fingerprint_zero() {
+        let filter = Filter::new(&Config { finger_bits: 8, occupancy_bitmap: true, ..config(64) }).unwrap();
+        let key = (0u64 ..).find(|x| filter.candidates(filter.hash_key(x)).0 == 0).unwrap();
+        assert!(!filter.contains(&key));
+        filter.insert(&key).unwrap();
+        assert!(filter.contains(&key));
+        assert_eq!(filter.used(), 1);
+        let (bucket, entry) = filter.locate(&key).unwrap();
+        assert_eq!(filter.buckets.borrow().entry(filter.slot(bucket, entry)), Some(0));
+        assert!(filter.delete(&key));
+        assert!(!filter.contains(&key));
+
+        // Without the bitmap 0 marks empty slots, so no key gets it
+        let filter = Filter::new(&Config { finger_bits: 8, ..config(64) }).unwrap();
+        assert!((0 .. 10_000).all(|x| filter.candidates(filter.hash_key(&x)).0 != 0));
+    }
+
+    #[test]
+    fn membership_confidence_of_absent_and_present_keys() {
+        let filter = Filter::new(&config(256)).unwrap();
+        for i in 0 .. 500 {
+            filter.insert(&i).unwrap();
+        }
+        let present = 1.0 - filter.estimated_fpp();
+        assert!(present > 0.99 && present < 1.0);
+        assert!((0 .. 500).all(|i| filter.membership_confidence(&i) == present));
+        let absent = (1000 .. 2000).filter(|i| !filter.contains(i));
+        assert!(absent.map(|i| filter.membership_confidence(&i)).all(|c| c == 1.0));
+    }
+
+    #[test]
+    fn kick_path_length_is_swap_count() {
+        let c = Config { num_entries: 2, max_swaps: 3, trace_kicks: true, ..config(64) };
+        let filter = Filter::new(&c).unwrap();
+        assert_eq!(filter.last_kick_path(), Some(Vec::new()));
+        let (mut kicked, mut failed) = (false, false);
+        for i in 0 .. 128 {
+            match filter.insert(&i) {
+                Ok(0) => {},
+                Ok(swaps) => {
+                    kicked = true;
+                    assert_eq!(filter.last_kick_path().unwrap().len(), swaps as usize);
+                },
+                Err(InsertError::Full) => {
+                    assert_eq!(filter.last_kick_path().unwrap().len(), c.max_swaps as usize);
+                    failed = true;
+                    break;
+                },
+                Err(e) => {
+                    assert_eq!(e, InsertError::Cycle);
+                    assert!(filter.last_kick_path().unwrap().len() <= c.max_swaps as usize);
+                },
+            }
+        }
+        assert!(kicked && failed);
+        // Each step evicted what the slot held before the step
+        let path = filter.last_kick_path().unwrap();
+        assert!(path.iter().all(|step| step.bucket < 64 && step.entry < 2 && step.evicted != 0));
+        assert_eq!(Filter::new(&config(8)).unwrap().last_kick_path(), None);
+    }
+
+    #[test]
+    fn more_hashes_probe_more_buckets_and_fill_further() {
+        for &(num_hashes, n) in &[(2, 2000u64), (3, 3700), (4, 3900)] {
+            let filter = Filter::new(&Config { num_hashes, ..config(1024) }).unwrap();
+            for i in 0 .. n {
+                let (_, candidates) = filter.candidates(filter.hash_key(&i));
+                assert_eq!(candidates.len(), num_hashes as usize);
+                assert!(candidates.iter().all(|&b| b < 1024));
+            }
+            check_membership(&filter, n);
+            // Each extra bucket probed adds as many chances of a match
+            let fpp = Config { num_hashes, ..config(1024) }.estimated_fpp(0.5);
+            let ratio = fpp / config(1024).estimated_fpp(0.5);
+            assert!((ratio - num_hashes as f64 / 2.0).abs() < 0.01, "{}", ratio);
+        }
+        let load = |num_hashes| Filter::new(&Config { num_hashes, ..config(1024) }).unwrap().simulate_fill(3).mean_load;
+        assert!(load(2) < load(3) && load(3) < load(4));
+    }
+
+    #[test]
+    fn miscounted_used_saturates_and_clamps() {
+        let filter = Filter::new(&config(64)).unwrap();
+        filter.used.replace(u64::MAX - 1);
+        assert_eq!(filter.load_factor(), 1.0);
+        for i in 0 .. 3 {
+            filter.insert(&i).unwrap();
+        }
+        assert_eq!(filter.used(), u64::MAX);
+        assert_eq!(filter.load_factor(), 1.0);
+        assert!(filter.estimated_fpp() <= 1.0);
+        filter.used.replace(1000);
+        assert_eq!(filter.load_factor(), 1.0);
+        filter.used.replace(0);
+        assert!(filter.delete(&0));
+        assert_eq!(filter.used(), 0);
+    }
+
+    #[test]
+    fn kick_cycles_abort_early() {
+        let c = Config { num_entries: 2, max_swaps: 200, trace_kicks: true, ..config(2) };
+        let filter = Filter::new(&c).unwrap();
+        let mut i = 0u64;
+        let e = loop {
+            match filter.insert(&i) {
+                Ok(_) => i += 1,
+                Err(e) => break e,
+            }
+        };
+        // Both buckets are full, and every kick stays between them
+        assert_eq!(e, InsertError::Cycle);
+        assert_eq!(filter.used(), 4);
+        assert!(filter.last_kick_path().unwrap().len() < 20, "{:?}", filter.last_kick_path());
+        for _ in 0 .. 10 {
+            i += 1;
+            assert_eq!(filter.insert(&i), Err(InsertError::Cycle));
+        }
+        check_membership(&Filter::new(&Config { max_swaps: 200, ..config(1024) }).unwrap(), 2000);
+    }
+
+    #[test]
+    fn alternate_table_matches_scheme() {
+        let configs = [
+            Config { finger_bits: 8, ..config(1024) },
+            Config { finger_bits: 8, num_hashes: 4, bucket_rounding: Rounding::Exact, ..config(1000) },
+            Config { finger_bits: 8, occupancy_bitmap: true, hash_count: 2, ..config(1024) },
+            Config { finger_bits: 8, generation_bits: 2, num_hashes: 3, ..config(1024) },
+        ];
+        for c in &configs {
+            let filter = Filter::new(c).unwrap();
+            let table = filter.alt_table.as_ref().unwrap();
+            for stored in 0 ..= u8::MAX {
+                assert_eq!(&*table[stored as usize], &*filter.scheme().alternates(stored), "{} in {:?}", stored, c);
+            }
+            // Seeded fills through the table are reproducible and lose no
+            // value before the first failure
+            let fill = || {
+                let filter = Filter::new(c).unwrap();
+                let n = (0 .. 4096u64).take_while(|i| filter.insert(i).is_ok()).count() as u64;
+                (filter, n)
+            };
+            let (filter, n) = fill();
+            assert_eq!(filter.to_bytes(), fill().0.to_bytes());
+            assert!(n > 2000 && (0 .. n).all(|i| filter.contains(&i)), "{} in {:?}", n, c);
+        }
+    }
+
+    #[test]
+    fn wide_buckets_hold_and_serialize() {
+        let c = Config { num_entries: 1024, ..config(16) };
+        check_membership(&Filter::new(&c).unwrap(), 8000);
+        let filter = Filter::new(&c).unwrap();
+        for i in 0 .. 12_000u64 {
+            filter.insert(&i).unwrap();
+        }
+        let copy = Filter::from_bytes(&filter.to_bytes()).unwrap();
+        assert_eq!(copy.config().num_entries, 1024);
+        assert!((0 .. 12_000u64).all(|i| copy.contains(&i)));
+    }
+
+    #[test]
+    fn full_single_bucket_is_degenerate() {
+        let filter = Filter::new(&Config { max_swaps: 255, ..config(1) }).unwrap();
+        for i in 0 .. 4u64 {
+            filter.insert(&i).unwrap();
+        }
+        let bytes = filter.to_bytes();
+        for i in 4 .. 20u64 {
+            assert_eq!(filter.insert(&i), Err(InsertError::Degenerate));
+        }
+        // Nothing was moved or dropped
+        assert_eq!(filter.to_bytes(), bytes);
+        assert!((0 .. 4u64).all(|i| filter.contains(&i)));
+
+        let dual = DualFilter::new(&config(1)).unwrap();
+        for i in 0 .. 4u64 {
+            dual.insert(DualSide::First, &i).unwrap();
+        }
+        assert_eq!(dual.insert(DualSide::First, &4u64), Err(InsertError::Degenerate));
+        assert_eq!(dual.used(DualSide::First), 4);
+        let mut fixed = StaticFilter::<1, 4>::new(255);
+        let mut rng = StdRng::seed_from_u64(1);
+        for i in 0 .. 4u64 {
+            fixed.insert(&i, &mut rng).unwrap();
+        }
+        assert_eq!(fixed.insert(&4u64, &mut rng), Err(InsertError::Degenerate));
+        assert_eq!(fixed.used(), 4);
+    }
+
+    #[test]
+    fn single_entry_buckets() {
+        assert!(Filter::new(&Config { num_entries: 0, ..config(64) }).is_err());
+        let c = Config { num_entries: 1, ..config(64) };
+        // A failed insert drops a fingerprint, so find where the first
+        // failure happens and stop short of it on an identical filter
+        let probe = Filter::new(&c).unwrap();
+        let n = (0 .. 64u64).find(|i| probe.insert(i).is_err()).unwrap();
+        assert!(n > 4);
+
+        let filter = Filter::new(&c).unwrap();
+        let mut evicted = false;
+        for i in 0 .. n {
+            evicted |= filter.insert(&i).unwrap() > 0;
+            assert!((0 ..= i).all(|x| filter.contains(&x)));
+        }
+        assert!(evicted);
+        assert_eq!(filter.used(), n);
+        assert_eq!(filter.hottest_buckets(1)[0].1, 1);
+        for i in 0 .. n {
+            assert!(filter.delete(&i));
+        }
+        assert_eq!(filter.used(), 0);
+        assert!((0 .. n).all(|i| !filter.contains(&i)));
+    }
+
+    #[test]
+    fn both_layouts_pass_membership_in_equal_memory() {
+        for &finger_bits in &[8, 12, 16, 32] {
+            let row = Filter::new(&Config { finger_bits, layout: Layout::RowMajor, ..config(1024) }).unwrap();
+            let column = Filter::new(&Config { finger_bits, layout: Layout::ColumnMajor, ..config(1024) }).unwrap();
+            assert_eq!(row.memory_usage(), column.memory_usage());
+            check_membership(&row, 2000);
+            check_membership(&column, 2000);
+            assert_eq!(row.memory_usage(), column.memory_usage());
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    mod tracing_events {
+        use std::sync::{Arc, Mutex};
+
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        use crate::{Config, Filter};
+
+        /// Message and fields of one event, as `name=value` strings.
+        type Captured = (String, Vec<String>);
+
+        /// Subscriber keeping every event, and the name of every span.
+        #[derive(Clone, Default)]
+        struct Capture(Arc<Mutex<Vec<Captured>>>, Arc<Mutex<Vec<&'static str>>>);
+
+        #[derive(Default)]
+        struct Fields(Captured);
+
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                match field.name() {
+                    "message" => self.0 .0 = format!("{:?}", value),
+                    name => self.0 .1.push(format!("{}={:?}", name, value)),
+                }
+            }
+        }
+
+        impl Subscriber for Capture {
+            fn enabled(&self, _: &Metadata) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes) -> Id {
+                self.1.lock().unwrap().push(span.metadata().name());
+                Id::from_u64(1)
+            }
+
+            fn record(&self, _: &Id, _: &Record) {}
+
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+            fn event(&self, event: &Event) {
+                let mut fields = Fields::default();
+                event.record(&mut fields);
+                self.0.lock().unwrap().push(fields.0);
+            }
+
+            fn enter(&self, _: &Id) {}
+
+            fn exit(&self, _: &Id) {}
+        }
+
+        #[test]
+        fn failed_insert_reports_load_and_swaps() {
+            let capture = Capture::default();
+            // Two single-entry buckets, so the third insert must fail
+            let config = Config { num_buckets: 2, num_entries: 1, max_swaps: 8, seed: Some(1), ..Config::default() };
+            let filter = Filter::new(&config).unwrap();
+            let failed = tracing::subscriber::with_default(capture.clone(), || {
+                (0 .. 16).find(|i| filter.insert(i).is_err()).is_some()
+            });
+            assert!(failed);
+            let events = capture.0.lock().unwrap();
+            let (message, fields) = events.iter().find(|(message, _)| message.starts_with("insert failed")).unwrap();
+            assert!(fields.iter().any(|f| f.starts_with("load_factor=")), "{}: {:?}", message, fields);
+            assert!(fields.iter().any(|f| f.starts_with("swaps=")), "{}: {:?}", message, fields);
+            assert!(fields.iter().any(|f| f.starts_with("victim=")), "{}: {:?}", message, fields);
+        }
+
+        #[test]
+        fn degenerate_insert_reports_victim() {
+            let capture = Capture::default();
+            let config = Config { num_buckets: 1, num_entries: 1, seed: Some(1), ..Config::default() };
+            let filter = Filter::new(&config).unwrap();
+            tracing::subscriber::with_default(capture.clone(), || {
+                filter.insert(&0).unwrap();
+                assert!(filter.insert(&1).is_err());
+            });
+            let events = capture.0.lock().unwrap();
+            let (_, fields) = events.iter().find(|(message, _)| message == "insert failed: single full bucket").unwrap();
+            assert!(fields[0].starts_with("load_factor=") && fields[1].starts_with("victim="), "{:?}", fields);
+        }
+
+        #[test]
+        fn bulk_operations_open_spans() {
+            let capture = Capture::default();
+            let config = Config { num_buckets: 64, seed: Some(1), ..Config::default() };
+            let mut filter = Filter::new(&config).unwrap();
+            let other = Filter::new(&config).unwrap();
+            tracing::subscriber::with_default(capture.clone(), || {
+                filter.insert_many_monitored(0 .. 10, |_, _| {});
+                filter.insert_all_or_rollback(&[10, 11]).unwrap();
+                filter.remove_many(&[10]);
+                filter.merge(&other).unwrap();
+                Filter::merge_many(&[other]).unwrap();
+                filter.apply_ops(Vec::new());
+            });
+            let spans = capture.1.lock().unwrap();
+            let expected = [
+                "insert_many_monitored", "insert_all_or_rollback", "remove_many", "merge", "merge_many", "merge", "apply_ops",
+            ];
+            assert_eq!(&spans[..], &expected[..]);
+        }
+
+        #[test]
+        fn rejected_buffer_reports_reason() {
+            let capture = Capture::default();
+            tracing::subscriber::with_default(capture.clone(), || {
+                assert!(Filter::from_bytes(b"CKOO\x63\x00").is_err());
+            });
+            let events = capture.0.lock().unwrap();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].0, "rejected serialized filter");
+            let reason = format!("reason=UnsupportedVersion {{ found: 99, supported: {} }}", crate::FORMAT_VERSION);
+            assert_eq!(events[0].1, vec![reason]);
+        }
+
+        #[test]
+        fn growth_reports_capacities() {
+            let capture = Capture::default();
+            let mut filter = Filter::new(&Config { num_buckets: 2, num_entries: 1, seed: Some(1), ..Config::default() }).unwrap();
+            tracing::subscriber::with_default(capture.clone(), || {
+                for i in 0 .. 3 {
+                    filter.insert_grow(&i, 64).unwrap();
+                }
+            });
+            let events = capture.0.lock().unwrap();
+            let (_, fields) = events.iter().find(|(message, _)| message == "grew filter").unwrap();
+            assert_eq!(&fields[.. 2], &["old_capacity=2", "new_capacity=4"]);
+        }
+    }
+}
@@ -1,112 +1,523 @@
-use rand::distributions::{Alphanumeric};
-use rand::seq::SliceRandom;
-use rand::{thread_rng, Rng};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process;
 
-use cuckoo_filter::{Config, Filter};
+use rand::rngs::StdRng;
+use rand::{FromEntropy, Rng, SeedableRng};
 
+use cuckoo_filter::experiment::{self, Adversarial, ExperimentConfig, SteadyOps, SteadyStateConfig};
+use cuckoo_filter::plan::{self, KeySource, PlannedRun, Workload};
+use cuckoo_filter::{Config, Filter, SerializedInfo, FORMAT_VERSION};
+
+/// Usage:
+///
+/// ```text
+/// cuckoo-filter [--format text|json] [--seed N] [--hashes D] [--keys words|adversarial] [--keyed] [keys]
+/// cuckoo-filter [--format text|json] [--seed N] [--hashes D] --baseline hashset [keys]
+/// cuckoo-filter [--format text|json] [--seed N] [--hashes D] --sweep fp [keys]
+/// cuckoo-filter [--format text|json] [--seed N] [--hashes D] --warm L [--ops N] [--mix M]
+/// cuckoo-filter [--format text|json] [--seed N] [--hashes D] --workload delete-audit [--ops N] [keys]
+/// cuckoo-filter run PLAN
+/// cuckoo-filter build --capacity N [--fp P] --out FILE [--binary] [INPUT]
+/// cuckoo-filter query FILE [--count-only] [--binary] [KEYS...]
+/// cuckoo-filter stats FILE [--histogram]
+/// cuckoo-filter upgrade IN OUT
+/// ```
+///
+/// The experiment inserts random words into a filter with D candidate
+/// buckets per key (2 by default), so runs differing only in D compare
+/// them on equal memory. With `--baseline hashset` it runs the same words
+/// through a `HashSet<String>` and a `HashSet<u64>` of their hashes too,
+/// and prints memory, build time, lookup throughput and false positive
+/// rate for each.
+///
+/// With `--sweep fp` it fills one filter in steps of 5% load, looking up
+/// the same `keys` absent words after each step, and prints the false
+/// positive rate observed next to the one `Filter::estimated_fpp` predicts.
+///
+/// With `--keys adversarial` the experiment inserts keys chosen, knowing
+/// the hash, to crowd into just enough buckets to hold a quarter of them,
+/// so inserts start failing far earlier than with random words. Such runs
+/// are slow: finding the keys takes many hashes each, and every insert
+/// after the first failure kicks until it fails too. `--keyed` mixes a
+/// secret random domain into the filter's hashes (see `Config::domain`),
+/// which the keys were not chosen for, so they spread like random words.
+///
+/// With `--warm`, the experiment instead fills the filter to load factor L
+/// without timing, then times N operations (100000 by default) at that
+/// load: `inserts`, `lookups` or `mixed` (the default), reporting the
+/// throughput, swaps and latency of that steady phase alone.
+///
+/// With `--workload delete-audit` (built with the `testkit` feature), the
+/// experiment runs N random inserts and deletes (100000 by default) on a
+/// filter sized to hold `keys` keys, checking after every delete that no
+/// key still inserted has become a false negative (see
+/// `testkit::audit_deletes`). It prints the ops replaying a violation and
+/// exits with 1 if it finds one.
+///
+/// `run` executes each experiment described in the file PLAN in turn,
+/// writing each report to the files the plan names; see `plan` for the
+/// format and `experiments.toml` for an example. It exits with 1 if a
+/// delete audit found a violation.
+///
+/// `build` reads keys from INPUT or stdin, one per line, or with `--binary`
+/// each preceded by its length as a little-endian u32. Keys are inserted
+/// as byte strings.
+///
+/// `query` looks up KEYS, or keys read from stdin as by `build`, printing
+/// `present` or `absent` for each, or with `--count-only` the number
+/// present. It exits with 1 unless every key was present, and with 2 on
+/// errors.
+///
+/// `stats` describes a saved filter from its header, streaming the rest
+/// only to check its length and checksum; `--histogram` loads it to count
+/// buckets by occupied entries and test the fingerprints for the skew a
+/// poor hash causes (see `Filter::fingerprint_skew`).
+/// It exits with 1 if the checksum does not match, and with 2 on errors
+/// such as a truncated file. A filter saved by an older version is loaded
+/// whole, which checks its checksum if it has one, and its version is
+/// reported with a reminder to `upgrade` it.
+///
+/// `upgrade` rewrites a filter saved by any older version in the current
+/// format.
 pub fn main() {
-    println!("Cuckoo Filter");
-    let mut rng = thread_rng();
-    run_experiment(&mut rng);
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("build") => build(&args[1 ..]).map(|()| true),
+        Some("query") => query(&args[1 ..]),
+        Some("stats") => stats(&args[1 ..]),
+        Some("upgrade") => upgrade(&args[1 ..]).map(|()| true),
+        Some("run") => run_plan(&args[1 ..]),
+        _ => run_experiment(&args),
+    };
+    match result {
+        Ok(true) => {},
+        Ok(false) => process::exit(1),
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(2);
+        },
+    }
+}
+
+/// Returns false if a delete audit found a violation.
+fn run_experiment(args: &[String]) -> Result<bool, String> {
+    let mut config = ExperimentConfig::default();
+    let mut json = false;
+    let mut warm_load = None;
+    let mut ops = 100000;
+    let mut mix = SteadyOps::Mixed;
+    let mut baseline = false;
+    let mut adversarial = false;
+    let mut keyed = false;
+    let mut sweep = false;
+    let mut audit = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => match args.next().map(String::as_str) {
+                Some("text") => json = false,
+                Some("json") => json = true,
+                other => return Err(format!("Expected text or json after --format, got {:?}", other)),
+            },
+            "--seed" => config.filter.seed = Some(parse_value(args.next(), "--seed")?),
+            "--hashes" => config.filter.num_hashes = parse_value(args.next(), "--hashes")?,
+            "--warm" => warm_load = Some(parse_value(args.next(), "--warm")?),
+            "--baseline" => match args.next().map(String::as_str) {
+                Some("hashset") => baseline = true,
+                other => return Err(format!("Expected hashset after --baseline, got {:?}", other)),
+            },
+            "--keys" => adversarial = match args.next().map(String::as_str) {
+                Some("words") => false,
+                Some("adversarial") => true,
+                other => return Err(format!("Expected words or adversarial after --keys, got {:?}", other)),
+            },
+            "--keyed" => keyed = true,
+            "--sweep" => match args.next().map(String::as_str) {
+                Some("fp") => sweep = true,
+                other => return Err(format!("Expected fp after --sweep, got {:?}", other)),
+            },
+            "--workload" => match args.next().map(String::as_str) {
+                Some("delete-audit") => audit = true,
+                other => return Err(format!("Expected delete-audit after --workload, got {:?}", other)),
+            },
+            "--ops" => ops = parse_value(args.next(), "--ops")?,
+            "--mix" => mix = match args.next().map(String::as_str) {
+                Some("inserts") => SteadyOps::Inserts,
+                Some("lookups") => SteadyOps::Lookups,
+                Some("mixed") => SteadyOps::Mixed,
+                other => return Err(format!("Expected inserts, lookups or mixed after --mix, got {:?}", other)),
+            },
+            _ => config.keys = arg.parse().map_err(|_| format!("Expected a number of keys, got {:?}", arg))?,
+        }
+    }
+    if adversarial as u8 + warm_load.is_some() as u8 + baseline as u8 + sweep as u8 + audit as u8 > 1 {
+        return Err("Only one of --keys adversarial, --warm, --baseline, --sweep and --workload can be given".to_string());
+    }
+    if audit {
+        config.filter.num_buckets = plan::audit_buckets(&config);
+    }
+    let workload = match warm_load {
+        Some(warm_load) => Workload::Steady { warm_load, ops, mix },
+        None if baseline => Workload::Baseline,
+        None if sweep => Workload::SweepFp,
+        None if audit => Workload::DeleteAudit { ops },
+        None => Workload::Insert,
+    };
+    let key_source = if adversarial { KeySource::Adversarial } else { KeySource::Words };
+    let run = PlannedRun { name: None, config, workload, key_source, keyed, json: None, text: None };
+    let filter = keyed_config(&run);
+    if !json {
+        println!("Cuckoo Filter");
+        println!("Experiment : config={:?}", filter);
+    }
+    let report = execute(&run, &filter)?;
+    if json {
+        println!("{}", report.json);
+    } else {
+        print!("{}", report.text);
+    }
+    Ok(report.clean)
 }
 
-fn run_experiment<R>(rng: &mut R) where R: Rng {
-    let config = Config {
-        finger_bits: 16,    //    16      8      8     8     8
-        num_buckets: 10000, // 20000  20000  10000  5000  4000
-        num_entries: 100,   //    50     50    100   200   250
-        max_swaps: 99,
+/// Runs each experiment of the plan in `args[0]` in turn (see `plan`).
+/// Returns false if a delete audit found a violation.
+fn run_plan(args: &[String]) -> Result<bool, String> {
+    let path = match args {
+        [path] => Path::new(path),
+        _ => return Err("Expected an experiment plan file".to_string()),
     };
-    println!("Experiment : config={:?}", config);
-    match Filter::new(&config) {
-        Ok(f) => {
-            let n = 990000;
-            let mut words = words(rng, n);
-            // rng.shuffle(&mut words);
-            words.shuffle(rng);
-
-            let mut summary = Summary::new(config.max_swaps as usize + 1);
-            for (i, word) in words.iter().enumerate() {
-                let (status, swaps) = insert(&f, word);
-                summary.update(i, status, swaps);
-            }
-            println!("load_factor   : {}", f.load_factor());
-            println!("bits          : {}", f.bits());
-            println!("bits_per_key  : {}", f.bits() as f64 / n as f64);
-            summary.print_status();
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let runs = plan::parse(&text).map_err(|e| format!("{}:{}: {}", path.display(), e.line, e.message))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut clean = true;
+    for (i, run) in runs.iter().enumerate() {
+        let name = run.name.clone().unwrap_or_else(|| format!("run {}", i + 1));
+        println!("{} : {:?}", name, run.workload);
+        let report = execute(run, &keyed_config(run)).map_err(|e| format!("{}: {}", name, e))?;
+        clean &= report.clean;
+        if run.json.is_none() && run.text.is_none() {
+            print!("{}", report.text);
         }
-        Err(_) => {
-            println!("Bucket type does not have enough bits");
+        for (out, contents) in [(&run.json, report.json + "\n"), (&run.text, report.text)].iter() {
+            if let Some(ref out) = **out {
+                let out = dir.join(out);
+                let error = |e: io::Error| format!("{}: {}", out.display(), e);
+                if let Some(parent) = out.parent() {
+                    std::fs::create_dir_all(parent).map_err(error)?;
+                }
+                std::fs::write(&out, contents).map_err(error)?;
+                println!("  wrote {}", out.display());
+            }
         }
     }
+    Ok(clean)
 }
 
-struct Summary {
-    success: u64,
-    failure: u64,
-    swaps: u64,
-    first_failure: usize,
-    swap_histogram: Vec<usize>,
+/// A report rendered both ways.
+struct Report {
+    text: String,
+    json: String,
+    /// False if a delete audit found a violation
+    clean: bool,
 }
 
-impl Summary {
-    fn new(bins: usize) -> Summary {
-        Summary {
-            success: 0,
-            failure: 0,
-            swaps: 0,
-            first_failure: 0,
-            swap_histogram: vec![0; bins],
+impl Report {
+    fn new(report: &impl std::fmt::Display, json: String) -> Report {
+        Report { text: report.to_string(), json, clean: true }
+    }
+}
+
+/// The filter configuration `run` uses: as given, with a secret random
+/// domain if `keyed`.
+fn keyed_config(run: &PlannedRun) -> Config {
+    let mut config = run.config.filter.clone();
+    if run.keyed {
+        let mut rng = config.seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
+        config.domain = Some(rng.gen());
+    }
+    config
+}
+
+/// Runs `run` on filters built from `filter`.
+fn execute(run: &PlannedRun, filter: &Config) -> Result<Report, String> {
+    let invalid = |_| "Invalid filter configuration".to_string();
+    let config = ExperimentConfig { filter: filter.clone(), keys: run.config.keys };
+    match run.workload {
+        Workload::Steady { warm_load, ops, mix } => {
+            let steady = SteadyStateConfig { filter: config.filter, warm_load, ops, mix };
+            let report = experiment::measure_steady_state(&steady)
+                .map_err(|_| "Invalid filter configuration or warm load".to_string())?;
+            Ok(Report::new(&report, report.to_json()))
+        },
+        Workload::Baseline => experiment::compare_hashset(&config).map(|r| Report::new(&r, r.to_json())).map_err(invalid),
+        Workload::SweepFp => experiment::sweep_fp(&config).map(|r| Report::new(&r, r.to_json())).map_err(invalid),
+        Workload::DeleteAudit { ops } => delete_audit(filter, ops),
+        Workload::Insert => {
+            let report = match run.key_source {
+                KeySource::Words => experiment::run(&config),
+                KeySource::Adversarial => {
+                    // The keys are aimed at the filter as configured,
+                    // without the secret
+                    let target = &run.config.filter;
+                    let buckets = (config.keys / (4 * target.num_entries as usize).max(1)).max(1);
+                    let buckets = buckets.min(target.num_buckets as usize) as u32;
+                    Adversarial::new(target, buckets)
+                        .and_then(|mut keys| experiment::benchmark_with(filter, &mut keys, config.keys))
+                },
+            };
+            report.map(|r| Report::new(&r, r.to_json())).map_err(invalid)
+        },
+    }
+}
+
+#[cfg(feature = "testkit")]
+fn delete_audit(config: &Config, ops: usize) -> Result<Report, String> {
+    let run = experiment::audit_deletes(config, ops).map_err(|_| "Invalid filter configuration".to_string())?;
+    Ok(Report { clean: run.result.is_ok(), ..Report::new(&run, run.to_json()) })
+}
+
+#[cfg(not(feature = "testkit"))]
+fn delete_audit(_: &Config, _: usize) -> Result<Report, String> {
+    Err("The delete-audit workload needs the testkit feature".to_string())
+}
+
+fn build(args: &[String]) -> Result<(), String> {
+    let mut capacity = None;
+    let mut fpp = 0.001;
+    let mut out = None;
+    let mut binary = false;
+    let mut input = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--capacity" => capacity = Some(parse_value(args.next(), "--capacity")?),
+            "--fp" => fpp = parse_value(args.next(), "--fp")?,
+            "--out" => out = Some(PathBuf::from(parse_value::<String>(args.next(), "--out")?)),
+            "--binary" => binary = true,
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument {:?}", arg)),
         }
     }
+    let capacity = capacity.ok_or("--capacity is required")?;
+    let out = out.ok_or("--out is required")?;
+    let config = Config::for_capacity(capacity, fpp)
+        .map_err(|_| format!("Cannot reach a false positive rate of {} for {} items", fpp, capacity))?;
+    let filter = Filter::new(&config).map_err(|_| "Invalid filter configuration".to_string())?;
 
-    fn update(&mut self, i: usize, status: bool, swaps: u64) {
-        self.swaps += swaps;
-        self.swap_histogram[swaps as usize] += 1;
-        if status {
-            self.success += 1;
-        } else {
-            if self.failure == 0 {
-                self.first_failure = i;
-            }
-            self.failure += 1;
+    let reader: Box<dyn Read> = match input {
+        Some(ref path) => Box::new(File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?),
+        None => Box::new(io::stdin()),
+    };
+    let mut reader = BufReader::new(reader);
+    let (mut items, mut failures) = (0u64, 0u64);
+    let mut insert = |key: &[u8]| {
+        items += 1;
+        failures += filter.insert(key).is_err() as u64;
+    };
+    if binary {
+        read_binary_keys(&mut reader, &mut insert)
+    } else {
+        read_lines(&mut reader, &mut insert)
+    }.map_err(|e| format!("Reading keys: {}", e))?;
+
+    filter.save(&out).map_err(|e| format!("{}: {}", out.display(), e))?;
+    println!("items       : {}", items);
+    println!("failures    : {}", failures);
+    println!("load_factor : {}", filter.load_factor());
+    println!("bytes       : {}", filter.config().serialized_len().unwrap_or(0));
+    Ok(())
+}
+
+/// Returns whether every key queried was present.
+fn query(args: &[String]) -> Result<bool, String> {
+    let mut path = None;
+    let mut count_only = false;
+    let mut binary = false;
+    let mut keys = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--count-only" => count_only = true,
+            "--binary" => binary = true,
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => keys.push(arg.as_bytes()),
         }
     }
+    let path = path.ok_or("A filter file is required")?;
+    let contains = open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
 
-    fn print_status(&self) {
-        println!("first_failure : {}", self.first_failure);
-        println!("success       : {:8}", self.success);
-        println!("failure       : {:8}", self.failure);
-        println!("swaps         : {:8}", self.swaps);
-        for (i, x) in self.swap_histogram.iter().enumerate() {
-            println!("{:2} {:8}", i, *x);
+    let (mut queried, mut present) = (0u64, 0u64);
+    let mut lookup = |key: &[u8]| {
+        let found = contains(key);
+        queried += 1;
+        present += found as u64;
+        if !count_only {
+            println!("{}\t{}", String::from_utf8_lossy(key), if found { "present" } else { "absent" });
         }
+    };
+    if !keys.is_empty() {
+        keys.iter().for_each(|key| lookup(key));
+    } else {
+        let mut stdin = BufReader::new(io::stdin());
+        if binary {
+            read_binary_keys(&mut stdin, &mut lookup)
+        } else {
+            read_lines(&mut stdin, &mut lookup)
+        }.map_err(|e| format!("Reading keys: {}", e))?;
     }
+    if count_only {
+        println!("{}", present);
+    }
+    Ok(present == queried)
 }
 
-fn insert(f: &Filter, x: &str) -> (bool, u64) {
-    match f.insert(x) {
-        Ok(swaps) => {
-            // println!("{:20}   success   {:2} swaps", x, swaps);
-            (true, swaps as u64)
-        },
-        Err(swaps) => {
-            // println!("{:20}   failure   {:2} swaps", x, swaps);
-            (false, swaps as u64)
+/// Returns whether the checksum matched.
+fn stats(args: &[String]) -> Result<bool, String> {
+    let mut path = None;
+    let mut histogram = false;
+    for arg in args {
+        match arg.as_str() {
+            "--histogram" => histogram = true,
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument {:?}", arg)),
+        }
+    }
+    let path = path.ok_or("A filter file is required")?;
+    let error = |e: io::Error| format!("{}: {}", path.display(), e);
+    let file = File::open(&path).map_err(error)?;
+    let file_bytes = file.metadata().map_err(error)?.len();
+    let mut reader = BufReader::new(file);
+    // The version follows the 4 magic bytes
+    let version = match reader.fill_buf().map_err(error)? {
+        head if head.len() >= 6 => u16::from_le_bytes([head[4], head[5]]),
+        _ => FORMAT_VERSION,
+    };
+    let info = if version == FORMAT_VERSION {
+        cuckoo_filter::inspect_serialized(reader).map_err(error)?
+    } else {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).map_err(error)?;
+        let filter = Filter::from_bytes(&bytes).map_err(|e| format!("{}: {:?}", path.display(), e))?;
+        SerializedInfo { config: filter.config(), used: filter.used(), checksum_ok: true }
+    };
+    let c = &info.config;
+    let capacity = c.num_buckets as u64 * c.num_entries as u64;
+    let load_factor = info.used as f64 / capacity as f64;
+    if version == FORMAT_VERSION {
+        println!("format_version   : {}", version);
+    } else {
+        println!("format_version   : {} (current {}; convert with upgrade)", version, FORMAT_VERSION);
+    }
+    println!("finger_bits      : {}", c.finger_bits);
+    println!("num_buckets      : {}", c.num_buckets);
+    println!("num_entries      : {}", c.num_entries);
+    println!("max_swaps        : {}", c.max_swaps);
+    println!("hash             : std DefaultHasher, {} derivation(s)", c.hash_count);
+    println!("num_hashes       : {}", c.num_hashes);
+    println!("occupancy_bitmap : {}", c.occupancy_bitmap);
+    println!("generation_bits  : {}", c.generation_bits);
+    println!("domain           : {}", c.domain.map_or("none".to_string(), |d| d.to_string()));
+    println!("used             : {} / {}", info.used, capacity);
+    println!("load_factor      : {}", load_factor);
+    println!("file_bytes       : {}", file_bytes);
+    println!("memory_bytes     : {}", c.required_bytes());
+    println!("estimated_fpp    : {}", c.estimated_fpp(load_factor));
+    // Version 1 has no checksum
+    let checksum = match (version, info.checksum_ok) {
+        (1, _) => "none",
+        (_, true) => "ok",
+        (_, false) => "MISMATCH",
+    };
+    println!("checksum         : {}", checksum);
+    if histogram && info.checksum_ok {
+        let filter = Filter::from_bytes(&std::fs::read(&path).map_err(error)?)
+            .map_err(|e| format!("{}: {:?}", path.display(), e))?;
+        let mut counts = vec![0u64; c.num_entries as usize + 1];
+        for (_, used) in filter.hottest_buckets(usize::MAX) {
+            counts[used as usize] += 1;
+        }
+        println!("occupancy        : buckets");
+        for (used, count) in counts.iter().enumerate() {
+            println!("{:>16} : {}", used, count);
         }
+        let skew = filter.fingerprint_skew();
+        println!(
+            "fingerprint_skew : chi2 {:.1} over {} bins of {} samples, z {:.2}{}",
+            skew.chi_squared,
+            skew.bins,
+            skew.samples,
+            skew.z_score,
+            if skew.is_suspicious() { " (SUSPICIOUS)" } else { "" }
+        );
     }
+    Ok(info.checksum_ok)
+}
+
+fn upgrade(args: &[String]) -> Result<(), String> {
+    let (input, output) = match args {
+        [input, output] => (Path::new(input), Path::new(output)),
+        _ => return Err("Expected an input and an output file".to_string()),
+    };
+    let bytes = std::fs::read(input).map_err(|e| format!("{}: {}", input.display(), e))?;
+    let filter = Filter::from_bytes(&bytes).map_err(|e| format!("{}: {:?}", input.display(), e))?;
+    filter.save(output).map_err(|e| format!("{}: {}", output.display(), e))?;
+    println!("upgraded to version {}", FORMAT_VERSION);
+    Ok(())
+}
+
+/// Membership test of a filter opened by `open`.
+type Lookup = Box<dyn Fn(&[u8]) -> bool>;
+
+/// Opens a filter saved by `build` for lookups, mapping it into memory.
+#[cfg(feature = "memmap2")]
+fn open(path: &Path) -> io::Result<Lookup> {
+    let filter = cuckoo_filter::FrozenFilter::open_mmap(path)?;
+    Ok(Box::new(move |key| filter.contains(key)))
 }
 
-fn words<R>(rng: &mut R, n: usize) -> Vec<String> where R: Rng {
-    let mut vec = Vec::with_capacity(n);
-    for i in 0 .. n {
-        let s = rand_string(rng, 4);
-        vec.push(format!("{}_{}", s, i));
+/// Opens a filter saved by `build` for lookups, reading it whole.
+#[cfg(not(feature = "memmap2"))]
+fn open(path: &Path) -> io::Result<Lookup> {
+    let filter = Filter::from_bytes(&std::fs::read(path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+    Ok(Box::new(move |key| filter.contains(key)))
+}
+
+/// Calls `f` with each line of `reader`, without its line ending.
+fn read_lines(reader: &mut impl BufRead, f: &mut impl FnMut(&[u8])) -> io::Result<()> {
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        if line.last() == Some(&b'\n') {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+        }
+        f(&line);
+        line.clear();
     }
-    vec
+    Ok(())
 }
 
-fn rand_string<R>(rng: &mut R, k: usize) -> String where R: Rng {
-    rng.sample_iter(&Alphanumeric).take(k).collect()
+/// Calls `f` with each key of `reader`, read as a little-endian u32
+/// length followed by that many bytes.
+fn read_binary_keys(reader: &mut impl Read, f: &mut impl FnMut(&[u8])) -> io::Result<()> {
+    let mut key = Vec::new();
+    loop {
+        let mut len = [0u8; 4];
+        match reader.read_exact(&mut len) {
+            Ok(()) => {},
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        key.resize(u32::from_le_bytes(len) as usize, 0);
+        reader.read_exact(&mut key)?;
+        f(&key);
+    }
 }
 
+fn parse_value<T: std::str::FromStr>(value: Option<&String>, flag: &str) -> Result<T, String> {
+    value.and_then(|v| v.parse().ok()).ok_or_else(|| format!("Expected a value after {}", flag))
+}
@@ -0,0 +1,128 @@
+//! Combining filters built over different parts of a set, e.g. shards.
+//!
+//! Stored fingerprints are moved over together with the bucket they sit
+//! in, which is always one of the two candidate buckets of the value they
+//! came from, so every value found in a source is found in the result.
+
+use crate::{Filter, InsertError};
+
+impl Filter {
+    /// Adds every fingerprint stored in `other` to this filter. Both must
+    /// share geometry and fingerprint derivation. If this filter runs out of
+    /// room, returns `Err` with the fingerprints moved so far kept.
+    pub fn merge(&self, other: &Filter) -> Result<(), ()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("merge", load_factor = self.load_factor(), incoming = other.used()).entered();
+        if !self.same_hashing(other) {
+            return Err(());
+        }
+        let mut stored = Vec::with_capacity(other.used() as usize);
+        {
+            let buckets = other.buckets.borrow();
+            for i in 0 .. buckets.len() {
+                if let Some(finger) = buckets.entry(i) {
+                    stored.push((finger, other.bucket_entry(i).0));
+                }
+            }
+        }
+        for (finger, bucket) in stored {
+            if self.insert_finger(finger, bucket).is_err() {
+                return Err(());
+            }
+            self.used.replace_with(|&mut x| x.saturating_add(1));
+        }
+        Ok(())
+    }
+
+    /// Returns a new filter holding the fingerprints of all `filters`,
+    /// which must share geometry and fingerprint derivation. Fails if
+    /// `filters` is empty or the union does not fit.
+    pub fn merge_many(filters: &[Filter]) -> Result<Filter, ()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("merge_many", filters = filters.len()).entered();
+        let first = filters.first().ok_or(())?;
+        if !filters.iter().all(|f| first.same_hashing(f)) {
+            return Err(());
+        }
+        let total: u64 = filters.iter().map(Filter::used).sum();
+        if total > first.capacity() {
+            return Err(());
+        }
+        let merged = Filter::new(&first.config())?;
+        for f in filters {
+            merged.merge(f)?;
+        }
+        Ok(merged)
+    }
+
+    /// True if values get the same fingerprints and buckets in both
+    /// filters.
+    fn same_hashing(&self, other: &Filter) -> bool {
+        self.same_geometry(other)
+            && self.hash_count == other.hash_count
+            && self.num_hashes == other.num_hashes
+            && self.occupancy_bitmap == other.occupancy_bitmap
+            && self.generation_bits == other.generation_bits
+            && self.domain == other.domain
+    }
+
+    /// Stores `finger`, taken from `bucket`, in `bucket` or one of its
+    /// alternates, kicking if all are full.
+    pub(crate) fn insert_finger(&self, finger: u32, bucket: u32) -> Result<(u8, usize), InsertError> {
+        self.insert_among(finger, &self.finger_candidates(bucket, finger))
+    }
+
+    /// Stores `finger` in the first of `candidates` with a free entry,
+    /// kicking if all are full. Every candidate must be a bucket lookups
+    /// check for the fingerprint.
+    pub(crate) fn insert_among(&self, finger: u32, candidates: &[u32]) -> Result<(u8, usize), InsertError> {
+        let entries = self.num_entries as usize;
+        let empty = {
+            let buckets = self.buckets.borrow();
+            candidates.iter().find_map(|&b| {
+                let start = b as usize * entries;
+                buckets.first_free(start .. start + entries)
+            })
+        };
+        self.place(finger, candidates, empty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::config;
+    use crate::{Config, Filter};
+
+    #[test]
+    fn merge_many_unions_four_disjoint_filters() {
+        let shards: Vec<Filter> = (0 .. 4u64)
+            .map(|s| {
+                let filter = Filter::new(&config(1024)).unwrap();
+                for i in s * 500 .. (s + 1) * 500 {
+                    filter.insert(&i).unwrap();
+                }
+                filter
+            })
+            .collect();
+        let merged = Filter::merge_many(&shards).unwrap();
+        assert_eq!(merged.used(), 2000);
+        assert!((0 .. 2000u64).all(|i| merged.contains(&i)));
+    }
+
+    #[test]
+    fn merge_many_rejects_mismatch_and_overflow() {
+        assert!(Filter::merge_many(&[]).is_err());
+        let other = Filter::new(&Config { finger_bits: 8, ..config(1024) }).unwrap();
+        assert!(Filter::merge_many(&[Filter::new(&config(1024)).unwrap(), other]).is_err());
+        let full: Vec<Filter> = (0 .. 3u64)
+            .map(|s| {
+                let filter = Filter::new(&config(16)).unwrap();
+                for i in s * 30 .. (s + 1) * 30 {
+                    filter.insert(&i).unwrap();
+                }
+                filter
+            })
+            .collect();
+        assert!(Filter::merge_many(&full).is_err());
+    }
+}
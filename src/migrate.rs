@@ -0,0 +1,91 @@
+//! Moving fingerprints into a filter of another geometry.
+//!
+//! Without the keys, a fingerprint can only go where lookups in the new
+//! filter will find it knowing nothing but the fingerprint: its alternate
+//! buckets, which depend only on the fingerprint and the bucket count, or
+//! its primary bucket when that can be worked out. A primary bucket is the
+//! value's hash modulo the bucket count, so when the new count divides the
+//! old one (including staying the same), the new primary bucket is the old
+//! one modulo the new count. For any other count, larger ones included,
+//! every fingerprint moves to an alternate bucket.
+//!
+//! In an alternate bucket a fingerprint matches every value sharing it, so
+//! false positives rise (see `rehash`). With `Config::num_hashes` at 2 a
+//! fingerprint also has a single alternate bucket, and migration fails as
+//! soon as more fingerprints share one than it has entries; more entries
+//! per bucket or more hashes make room.
+
+use crate::{Config, Filter};
+
+impl Filter {
+    /// A new filter built from `config` holding this filter's fingerprints,
+    /// placed without recomputing any as the module docs describe. The
+    /// geometry (`num_buckets`, `num_entries`, `num_hashes`) may change,
+    /// but `config` must derive fingerprints the same way: same
+    /// `finger_bits`, `hash_count`, `occupancy_bitmap`, `generation_bits`
+    /// and `domain`. Fails if it does not, or if the fingerprints do not
+    /// fit.
+    pub fn migrate_into(&self, config: &Config) -> Result<Filter, ()> {
+        if config.finger_bits != self.finger_bits
+            || config.hash_count != self.hash_count
+            || config.occupancy_bitmap != self.occupancy_bitmap
+            || config.generation_bits != self.generation_bits
+            || config.domain != self.domain {
+            return Err(());
+        }
+        let target = Filter::new(config)?;
+        let keep_primary = self.num_buckets.is_multiple_of(target.num_buckets);
+        let buckets = self.buckets.borrow();
+        for i in 0 .. buckets.len() {
+            let finger = match buckets.entry(i) {
+                Some(finger) => finger,
+                None => continue,
+            };
+            // A fingerprint in one of its alternate buckets may not be in
+            // its primary one
+            let bucket = self.bucket_entry(i).0;
+            let candidates = if keep_primary && !self.alternates(finger).contains(&bucket) {
+                target.finger_candidates(bucket % target.num_buckets, finger)
+            } else {
+                target.alternates(finger)
+            };
+            target.insert_among(finger, &candidates).map_err(|_| ())?;
+            target.used.replace_with(|&mut x| x.saturating_add(1));
+        }
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::config;
+    use crate::{Config, Filter};
+
+    #[test]
+    fn migrates_to_twice_the_buckets() {
+        let filter = Filter::new(&config(1024)).unwrap();
+        for i in 0 .. 2000u64 {
+            filter.insert(&i).unwrap();
+        }
+        // With two hashes every fingerprint lands in its single alternate
+        // bucket, so a third makes room
+        let target = Config { num_buckets: 2048, num_hashes: 3, ..config(1) };
+        let larger = filter.migrate_into(&target).unwrap();
+        assert_eq!(larger.capacity(), 8192);
+        assert_eq!(larger.used(), 2000);
+        assert!((0 .. 2000u64).all(|i| larger.contains(&i)));
+    }
+
+    #[test]
+    fn dividing_counts_keep_primary_buckets() {
+        let filter = Filter::new(&config(1024)).unwrap();
+        for i in 0 .. 500u64 {
+            filter.insert(&i).unwrap();
+        }
+        let smaller = filter.migrate_into(&Config { num_entries: 8, ..config(512) }).unwrap();
+        assert!((0 .. 500u64).all(|i| smaller.contains(&i)));
+        let primaries = (0 .. 500u64).filter(|i| smaller.locate(i).unwrap().0 == smaller.prehash(i).1).count();
+        assert!(primaries > 400, "{}", primaries);
+        assert!(filter.migrate_into(&Config { finger_bits: 8, ..config(2048) }).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+//! Lightweight hooks for wiring filter activity into external metrics.
+
+use std::fmt;
+
+/// Receives notifications about filter state transitions.
+///
+/// Every method has a no-op default, so implementors only override the
+/// events they care about. Calls are made synchronously on the thread
+/// performing the operation and should be cheap.
+pub trait FilterObserver {
+    /// An insert succeeded after `swaps` relocations.
+    fn on_insert(&self, _swaps: u32) {}
+
+    /// An insert failed at load factor `load`.
+    fn on_insert_failed(&self, _load: f64) {}
+
+    /// A delete was attempted; `found` tells whether a fingerprint was removed.
+    fn on_remove(&self, _found: bool) {}
+
+    /// The filter grew from `old_capacity` to `new_capacity` entries (see
+    /// `Filter::insert_grow`).
+    fn on_grow(&self, _old_capacity: u64, _new_capacity: u64) {}
+}
+
+impl fmt::Debug for dyn FilterObserver + Send + Sync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("FilterObserver")
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{Config, Filter};
+
+    /// Counts of each notification, shared with the test.
+    #[derive(Default)]
+    pub(crate) struct Counts {
+        pub(crate) inserts: AtomicU64,
+        pub(crate) swaps: AtomicU64,
+        pub(crate) failures: AtomicU64,
+        pub(crate) removes_found: AtomicU64,
+        pub(crate) removes_missed: AtomicU64,
+        pub(crate) grows: AtomicU64,
+        /// Capacity reported by the latest `on_grow`
+        pub(crate) grown_to: AtomicU64,
+    }
+
+    pub(crate) struct Counter(pub(crate) Arc<Counts>);
+
+    impl FilterObserver for Counter {
+        fn on_insert(&self, swaps: u32) {
+            self.0.inserts.fetch_add(1, Ordering::Relaxed);
+            self.0.swaps.fetch_add(swaps as u64, Ordering::Relaxed);
+        }
+
+        fn on_insert_failed(&self, _load: f64) {
+            self.0.failures.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_remove(&self, found: bool) {
+            let count = if found { &self.0.removes_found } else { &self.0.removes_missed };
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_grow(&self, _old_capacity: u64, new_capacity: u64) {
+            self.0.grows.fetch_add(1, Ordering::Relaxed);
+            self.0.grown_to.store(new_capacity, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn counts_scripted_workload() {
+        let counts = Arc::new(Counts::default());
+        let mut filter = Filter::new(&Config { num_buckets: 64, seed: Some(3), ..Config::default() }).unwrap();
+        filter.set_observer(Box::new(Counter(counts.clone())));
+
+        let mut swaps = 0;
+        for i in 0 .. 100 {
+            swaps += filter.insert(&i).unwrap() as u64;
+        }
+        for i in 0 .. 40 {
+            assert!(filter.delete(&i));
+        }
+        let missed = (1000 .. 1010).filter(|i| !filter.delete(i)).count() as u64;
+        let mut failed = 0;
+        for i in 100 .. 1000 {
+            match filter.insert(&i) {
+                Ok(n) => swaps += n as u64,
+                Err(_) => failed += 1,
+            }
+        }
+        assert!(failed > 0);
+
+        assert_eq!(counts.inserts.load(Ordering::Relaxed), 100 + 900 - failed);
+        assert_eq!(counts.swaps.load(Ordering::Relaxed), swaps);
+        assert_eq!(counts.failures.load(Ordering::Relaxed), failed);
+        assert_eq!(counts.removes_found.load(Ordering::Relaxed), 40 + 10 - missed);
+        assert_eq!(counts.removes_missed.load(Ordering::Relaxed), missed);
+        assert_eq!(counts.grows.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn lookups_are_not_reported() {
+        let counts = Arc::new(Counts::default());
+        let mut filter = Filter::new(&Config { num_buckets: 64, ..Config::default() }).unwrap();
+        filter.set_observer(Box::new(Counter(counts.clone())));
+        filter.insert("a").unwrap();
+        assert!(filter.contains("a"));
+        assert!(filter.contains_or_insert("a").unwrap());
+        assert_eq!(counts.inserts.load(Ordering::Relaxed), 1);
+    }
+}
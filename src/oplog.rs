@@ -0,0 +1,425 @@
+//! Logical replication: recording the operations applied to a primary
+//! filter and replaying them on replicas.
+//!
+//! Placement depends on the kick loop's random choices, so a replica that
+//! replays the same operations holds the same fingerprints but not
+//! necessarily in the same slots. Replicas are therefore membership
+//! equivalent rather than byte-identical: they answer identically for
+//! every inserted value, while false positives may differ. Use
+//! `Filter::same_membership` to compare them.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::hash::Hash;
+use std::mem;
+
+use crate::serialize::{fnv1a, fnv1a_update, read_u32, read_u64};
+use crate::Filter;
+
+/// A logical filter operation, keyed by the value's hash (see `Filter::hash_key`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterOp {
+    InsertHash(u64),
+    RemoveHash(u64),
+    Clear,
+    /// Insert by `insert_with_ttl`: the hash and the expiry generation
+    InsertHashTagged(u64, u32),
+    /// `sweep` at the given generation
+    Sweep(u32),
+    /// Insert by `insert_fingerprint`: the bucket and fingerprint
+    InsertFingerprint(u32, u32),
+    /// Remove by `remove_fingerprint`: the bucket and fingerprint
+    RemoveFingerprint(u32, u32),
+}
+
+impl FilterOp {
+    /// Appends the op's encoding: a tag byte followed by the op's fields,
+    /// little-endian.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            FilterOp::InsertHash(h) => {
+                out.push(0);
+                out.extend_from_slice(&h.to_le_bytes());
+            },
+            FilterOp::RemoveHash(h) => {
+                out.push(1);
+                out.extend_from_slice(&h.to_le_bytes());
+            },
+            FilterOp::Clear => out.push(2),
+            FilterOp::InsertHashTagged(h, generation) => {
+                out.push(3);
+                out.extend_from_slice(&h.to_le_bytes());
+                out.extend_from_slice(&generation.to_le_bytes());
+            },
+            FilterOp::Sweep(generation) => {
+                out.push(4);
+                out.extend_from_slice(&generation.to_le_bytes());
+            },
+            FilterOp::InsertFingerprint(bucket, finger) => {
+                out.push(5);
+                out.extend_from_slice(&bucket.to_le_bytes());
+                out.extend_from_slice(&finger.to_le_bytes());
+            },
+            FilterOp::RemoveFingerprint(bucket, finger) => {
+                out.push(6);
+                out.extend_from_slice(&bucket.to_le_bytes());
+                out.extend_from_slice(&finger.to_le_bytes());
+            },
+        }
+    }
+
+    /// Decodes one op from the front of `bytes`, advancing it.
+    pub fn decode(bytes: &mut &[u8]) -> Result<FilterOp, ()> {
+        let (&tag, rest) = bytes.split_first().ok_or(())?;
+        let (op, rest) = match tag {
+            0 | 1 if rest.len() >= 8 => {
+                let h = read_u64(rest, 0);
+                let op = if tag == 0 { FilterOp::InsertHash(h) } else { FilterOp::RemoveHash(h) };
+                (op, &rest[8 ..])
+            },
+            2 => (FilterOp::Clear, rest),
+            3 if rest.len() >= 12 => {
+                let op = FilterOp::InsertHashTagged(read_u64(rest, 0), read_u32(rest, 8));
+                (op, &rest[12 ..])
+            },
+            4 if rest.len() >= 4 => (FilterOp::Sweep(read_u32(rest, 0)), &rest[4 ..]),
+            5 | 6 if rest.len() >= 8 => {
+                let (bucket, finger) = (read_u32(rest, 0), read_u32(rest, 4));
+                let op = if tag == 5 {
+                    FilterOp::InsertFingerprint(bucket, finger)
+                } else {
+                    FilterOp::RemoveFingerprint(bucket, finger)
+                };
+                (op, &rest[8 ..])
+            },
+            _ => return Err(()),
+        };
+        *bytes = rest;
+        Ok(op)
+    }
+}
+
+/// Encodes a sequence of ops back to back.
+pub fn encode_ops<'a>(ops: impl IntoIterator<Item = &'a FilterOp>) -> Vec<u8> {
+    let mut out = Vec::new();
+    for op in ops {
+        op.encode(&mut out);
+    }
+    out
+}
+
+/// Decodes a buffer written by `encode_ops`.
+pub fn decode_ops(mut bytes: &[u8]) -> Result<Vec<FilterOp>, ()> {
+    let mut ops = Vec::with_capacity(bytes.len() / 9);
+    while !bytes.is_empty() {
+        ops.push(FilterOp::decode(&mut bytes)?);
+    }
+    Ok(ops)
+}
+
+/// Where a filter records its successful operations.
+pub(crate) enum OpLog {
+    /// Keeps the most recent `capacity` ops, counting the ones pushed out.
+    Ring { capacity: usize, ops: VecDeque<FilterOp>, dropped: u64 },
+    /// Hands every op to a caller-supplied function.
+    Sink(Box<dyn FnMut(FilterOp) + Send>),
+}
+
+impl OpLog {
+    pub(crate) fn push(&mut self, op: FilterOp) {
+        match *self {
+            OpLog::Ring { capacity, ref mut ops, ref mut dropped } => {
+                if ops.len() == capacity {
+                    ops.pop_front();
+                    *dropped += 1;
+                }
+                if capacity > 0 {
+                    ops.push_back(op);
+                }
+            },
+            OpLog::Sink(ref mut sink) => sink(op),
+        }
+    }
+
+    /// Heap bytes held by recorded ops.
+    pub(crate) fn heap_bytes(&self) -> usize {
+        match *self {
+            OpLog::Ring { ref ops, .. } => ops.capacity() * mem::size_of::<FilterOp>(),
+            OpLog::Sink(_) => 0,
+        }
+    }
+}
+
+impl fmt::Debug for OpLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OpLog::Ring { capacity, ref ops, dropped } => f
+                .debug_struct("Ring")
+                .field("capacity", &capacity)
+                .field("len", &ops.len())
+                .field("dropped", &dropped)
+                .finish(),
+            OpLog::Sink(_) => f.write_str("Sink"),
+        }
+    }
+}
+
+/// Outcome of `Filter::apply_ops`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ApplyReport {
+    /// Ops applied successfully
+    pub applied: u64,
+    /// Inserts that failed because the replica was full
+    pub insert_failures: u64,
+    /// Removes whose value was not found
+    pub missing_removes: u64,
+}
+
+impl Filter {
+    /// Starts recording successful inserts, deletes and clears into a ring
+    /// buffer holding the most recent `capacity` ops.
+    pub fn record_ops(&mut self, capacity: usize) {
+        self.op_log = Some(std::cell::RefCell::new(OpLog::Ring {
+            capacity,
+            ops: VecDeque::with_capacity(capacity),
+            dropped: 0,
+        }));
+    }
+
+    /// Starts handing every successful insert, delete and clear to `sink`.
+    pub fn record_ops_to(&mut self, sink: impl FnMut(FilterOp) + Send + 'static) {
+        self.op_log = Some(std::cell::RefCell::new(OpLog::Sink(Box::new(sink))));
+    }
+
+    pub fn stop_recording(&mut self) {
+        self.op_log = None;
+    }
+
+    /// Drains the ops recorded by `record_ops`.
+    pub fn take_ops(&self) -> Vec<FilterOp> {
+        match self.op_log {
+            Some(ref log) => match *log.borrow_mut() {
+                OpLog::Ring { ref mut ops, .. } => ops.drain(..).collect(),
+                OpLog::Sink(_) => Vec::new(),
+            },
+            None => Vec::new(),
+        }
+    }
+
+    /// Number of ops pushed out of the `record_ops` ring before being
+    /// taken. A replica that missed any needs a full snapshot.
+    pub fn dropped_ops(&self) -> u64 {
+        match self.op_log {
+            Some(ref log) => match *log.borrow() {
+                OpLog::Ring { dropped, .. } => dropped,
+                OpLog::Sink(_) => 0,
+            },
+            None => 0,
+        }
+    }
+
+    pub(crate) fn record_op(&self, op: FilterOp) {
+        if let Some(ref log) = self.op_log {
+            log.borrow_mut().push(op);
+        }
+    }
+
+    /// Replays ops recorded on another filter.
+    pub fn apply_ops(&self, ops: impl IntoIterator<Item = FilterOp>) -> ApplyReport {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("apply_ops", load_factor = self.load_factor()).entered();
+        let mut report = ApplyReport::default();
+        for op in ops {
+            let ok = match op {
+                FilterOp::InsertHash(h) => {
+                    let ok = self.insert_hash(h).is_ok();
+                    report.insert_failures += !ok as u64;
+                    ok
+                },
+                FilterOp::RemoveHash(h) => {
+                    let ok = self.delete_hash(h);
+                    report.missing_removes += !ok as u64;
+                    ok
+                },
+                FilterOp::Clear => {
+                    self.clear();
+                    true
+                },
+                FilterOp::InsertHashTagged(h, generation) => {
+                    let ok = self.insert_slot(h, generation).is_ok();
+                    report.insert_failures += !ok as u64;
+                    ok
+                },
+                FilterOp::Sweep(generation) => {
+                    self.sweep(generation);
+                    true
+                },
+                FilterOp::InsertFingerprint(bucket, finger) => {
+                    let ok = self.insert_fingerprint(bucket as u64, finger as u64).is_ok();
+                    report.insert_failures += !ok as u64;
+                    ok
+                },
+                FilterOp::RemoveFingerprint(bucket, finger) => {
+                    let ok = self.remove_fingerprint(bucket as u64, finger as u64);
+                    report.missing_removes += !ok as u64;
+                    ok
+                },
+            };
+            report.applied += ok as u64;
+        }
+        report
+    }
+
+    /// True if both filters have the same geometry and hold the same
+    /// fingerprints, each in a bucket that is valid for it, so that every
+    /// inserted value is found by both. Slots and false positives may
+    /// differ.
+    pub fn same_membership(&self, other: &Filter) -> bool {
+        if !self.same_geometry(other) || self.used() != other.used() {
+            return false;
+        }
+        // A fingerprint found in its alternate bucket may belong to a value
+        // with any primary bucket, so it matches any placement of the same
+        // fingerprint in the other filter.
+        let mut counts: HashMap<u32, Placements> = HashMap::new();
+        self.for_each_placement(|finger, primary| counts.entry(finger).or_default().add(primary, 1));
+        other.for_each_placement(|finger, primary| counts.entry(finger).or_default().add(primary, -1));
+        counts.values().all(Placements::matches)
+    }
+
+    /// Stable hash of the fingerprints in all candidate buckets of `x`, for
+    /// spot-checking replicas key by key. Entry order within a bucket does
+    /// not matter, but replicas that placed a fingerprint in different
+    /// candidate buckets get different signatures even though both find
+    /// it, as replicas kicking with different `Config::seed`s often do;
+    /// `same_membership` is the full check.
+    pub fn slot_signature<T: ?Sized + Hash>(&self, x: &T) -> u64 {
+        let (_, candidates) = self.candidates(self.hash_key(x));
+        let buckets = self.buckets.borrow();
+        let entries = self.num_entries as usize;
+        let mut h = fnv1a(&[]);
+        for &bucket in candidates.iter() {
+            let start = bucket as usize * entries;
+            let mut fingers: Vec<u32> = (start .. start + entries).filter_map(|i| buckets.entry(i)).collect();
+            fingers.sort_unstable();
+            h = fnv1a_update(h, &bucket.to_le_bytes());
+            h = fnv1a_update(h, &(fingers.len() as u32).to_le_bytes());
+            for finger in fingers {
+                h = fnv1a_update(h, &finger.to_le_bytes());
+            }
+        }
+        h
+    }
+
+    /// Calls `f(fingerprint, Some(bucket))` for every stored fingerprint,
+    /// or `f(fingerprint, None)` if it is in one of its alternate buckets.
+    pub(crate) fn for_each_placement(&self, mut f: impl FnMut(u32, Option<u32>)) {
+        let buckets = self.buckets.borrow();
+        for i in 0 .. buckets.len() {
+            if let Some(finger) = buckets.entry(i) {
+                let (bucket, _) = self.bucket_entry(i);
+                let primary = if self.alternates(finger).contains(&bucket) { None } else { Some(bucket) };
+                f(finger, primary);
+            }
+        }
+    }
+}
+
+/// Placement counts of one fingerprint value: positive for `self`,
+/// negative for `other`.
+#[derive(Default)]
+struct Placements {
+    alternate: (i64, i64),
+    primary: HashMap<u32, i64>,
+}
+
+impl Placements {
+    fn add(&mut self, primary: Option<u32>, n: i64) {
+        match primary {
+            Some(bucket) => *self.primary.entry(bucket).or_insert(0) += n,
+            None if n > 0 => self.alternate.0 += n,
+            None => self.alternate.1 -= n,
+        }
+    }
+
+    /// Whether the surplus primary placements on each side can be matched
+    /// by the other side's alternate placements, with the rest pairing up.
+    fn matches(&self) -> bool {
+        let surplus_self: i64 = self.primary.values().filter(|&&n| n > 0).sum();
+        let surplus_other: i64 = -self.primary.values().filter(|&&n| n < 0).sum::<i64>();
+        let (alt_self, alt_other) = self.alternate;
+        surplus_self <= alt_other
+            && surplus_other <= alt_self
+            && alt_other - surplus_self == alt_self - surplus_other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::tests::config;
+    use crate::Config;
+
+    #[test]
+    fn replica_replaying_100k_ops_has_same_membership() {
+        let mut primary = Filter::new(&config(1 << 14)).unwrap();
+        primary.record_ops(200_000);
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut live = Vec::new();
+        for op in 0 .. 100_000u64 {
+            if op % 5000 == 4999 && live.len() > 40_000 {
+                // Occasionally thin out by clearing everything
+                primary.clear();
+                live.clear();
+            } else if live.is_empty() || rng.gen_range(0, 3) > 0 {
+                primary.insert(&op).unwrap();
+                live.push(op);
+            } else {
+                let x = live.swap_remove(rng.gen_range(0, live.len()));
+                assert!(primary.delete(&x));
+            }
+        }
+        let ops = decode_ops(&encode_ops(&primary.take_ops())).unwrap();
+        assert_eq!(ops.len(), 100_000);
+        assert_eq!(primary.dropped_ops(), 0);
+
+        let replica = Filter::new(&Config { seed: Some(99), ..config(1 << 14) }).unwrap();
+        let report = replica.apply_ops(ops);
+        assert_eq!(report, ApplyReport { applied: 100_000, insert_failures: 0, missing_removes: 0 });
+        assert!(replica.same_membership(&primary));
+        assert!(live.iter().all(|x| replica.contains(x)));
+    }
+
+    #[test]
+    fn ring_keeps_most_recent_ops() {
+        let mut filter = Filter::new(&config(64)).unwrap();
+        filter.record_ops(2);
+        for i in 0 .. 3 {
+            filter.insert(&i).unwrap();
+        }
+        assert!(filter.delete(&0));
+        let ops = filter.take_ops();
+        assert_eq!(ops, vec![FilterOp::InsertHash(filter.hash_key(&2)), FilterOp::RemoveHash(filter.hash_key(&0))]);
+        assert_eq!(filter.dropped_ops(), 2);
+        assert!(decode_ops(&[7]).is_err());
+    }
+
+    #[test]
+    fn slot_signature_tracks_neighborhood() {
+        let (a, b) = (Filter::new(&config(1024)).unwrap(), Filter::new(&config(1024)).unwrap());
+        for i in 0 .. 2000u64 {
+            a.insert(&i).unwrap();
+            b.insert(&i).unwrap();
+        }
+        assert!((0 .. 3000u64).all(|i| a.slot_signature(&i) == b.slot_signature(&i)));
+        let before = b.slot_signature(&5000u64);
+        b.insert(&5000u64).unwrap();
+        assert_ne!(b.slot_signature(&5000u64), before);
+        assert_ne!(b.slot_signature(&5000u64), a.slot_signature(&5000u64));
+        assert!(b.delete(&5000u64));
+        assert_eq!(b.slot_signature(&5000u64), a.slot_signature(&5000u64));
+    }
+}
@@ -0,0 +1,151 @@
+//! A filter backed by a second one that takes the inserts it rejects.
+//!
+//! A failed insert into the primary filter is rolled back, so it drops
+//! nothing, and the value goes to the secondary instead. The secondary is
+//! only built on the first overflow, with the primary's configuration but
+//! a single entry per bucket, and doubles its entries whenever it fills
+//! (see `Filter::insert_grow`), so it can grow to 65535 times the primary's
+//! bucket count in values. Values found in the primary are looked up in
+//! one filter; misses also check the secondary once it exists, and false
+//! positives from both add up.
+//!
+//! The hashes of overflowed values are also kept exactly, so that `delete`
+//! removes a value from the filter it went to: deleting an overflowed value
+//! from the primary instead would remove another value's colliding
+//! fingerprint there, leaving that value missing.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::{Config, Filter, InsertError};
+
+/// A `Filter` whose inserts do not fail when it is full.
+#[derive(Debug)]
+pub struct OverflowFilter {
+    primary: Filter,
+    secondary: RefCell<Option<Filter>>,
+    /// Copies of each hash inserted into the secondary
+    overflowed: RefCell<HashMap<u64, u32>>,
+}
+
+impl OverflowFilter {
+    pub fn new(config: &Config) -> Result<OverflowFilter, ()> {
+        Ok(OverflowFilter {
+            primary: Filter::new(config)?,
+            secondary: RefCell::new(None),
+            overflowed: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// Inserts `x` into the primary filter, or into the secondary if the
+    /// primary is full, returning the swaps made by the insert that
+    /// succeeded. Fails only if the secondary cannot grow any further (past
+    /// 65535 entries per bucket).
+    pub fn insert<T: ?Sized + Hash>(&self, x: &T) -> Result<u8, InsertError> {
+        let h = self.primary.hash_key(x);
+        match self.primary.or_roll_back(|| self.primary.insert_slot(h, 0)) {
+            Ok((swaps, _)) => return Ok(swaps),
+            Err(InsertError::Full) | Err(InsertError::Cycle) | Err(InsertError::Degenerate) => {},
+            Err(e) => return Err(e),
+        }
+        let mut secondary = self.secondary.borrow_mut();
+        if secondary.is_none() {
+            let c = self.primary.config();
+            let config = Config { num_entries: 1, sparse_until: None, ..c };
+            *secondary = Some(Filter::new(&config).map_err(|_| InsertError::CapacityExceeded)?);
+        }
+        let swaps = secondary.as_mut().unwrap().insert_grow(x, u64::MAX)?;
+        *self.overflowed.borrow_mut().entry(h).or_insert(0) += 1;
+        Ok(swaps)
+    }
+
+    /// True if `x` may have been inserted, looking in the secondary filter
+    /// only if the primary does not have it.
+    pub fn contains<T: ?Sized + Hash>(&self, x: &T) -> bool {
+        self.primary.contains(x) || self.secondary.borrow().as_ref().is_some_and(|f| f.contains(x))
+    }
+
+    /// Deletes one copy of `x` from the secondary filter if a copy
+    /// overflowed there, or else from the primary, returning whether one
+    /// was found. As with `Filter::delete`, `x` must have been inserted.
+    pub fn delete<T: ?Sized + Hash>(&self, x: &T) -> bool {
+        let h = self.primary.hash_key(x);
+        let mut overflowed = self.overflowed.borrow_mut();
+        match overflowed.get_mut(&h) {
+            Some(copies) => {
+                *copies -= 1;
+                if *copies == 0 {
+                    overflowed.remove(&h);
+                }
+                self.secondary.borrow().as_ref().is_some_and(|f| f.delete_hash(h))
+            },
+            None => self.primary.delete_hash(h),
+        }
+    }
+
+    pub fn primary(&self) -> &Filter {
+        &self.primary
+    }
+
+    /// Values held by the secondary filter, 0 until the first overflow.
+    pub fn overflowed(&self) -> u64 {
+        self.secondary.borrow().as_ref().map_or(0, Filter::used)
+    }
+
+    /// Entries used in both filters.
+    pub fn used(&self) -> u64 {
+        self.primary.used() + self.overflowed()
+    }
+
+    /// Sum of both filters' `Filter::estimated_fpp`, which bounds the
+    /// chance that `contains` reports a value never inserted.
+    pub fn estimated_fpp(&self) -> f64 {
+        let secondary = self.secondary.borrow().as_ref().map_or(0.0, Filter::estimated_fpp);
+        self.primary.estimated_fpp() + secondary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::config;
+    use crate::Config;
+
+    use super::OverflowFilter;
+
+    #[test]
+    fn overflowed_keys_are_found() {
+        let filter = OverflowFilter::new(&Config { num_entries: 2, ..config(64) }).unwrap();
+        assert_eq!(filter.overflowed(), 0);
+        for i in 0 .. 1000u64 {
+            filter.insert(&i).unwrap();
+        }
+        // The primary holds 128 entries at most
+        assert!(filter.overflowed() >= 1000 - 128);
+        assert_eq!(filter.used(), 1000);
+        assert!(filter.primary().used() <= 128);
+        assert!((0 .. 1000u64).all(|i| filter.contains(&i)));
+        assert!(filter.estimated_fpp() > filter.primary().estimated_fpp());
+        for i in 0 .. 1000u64 {
+            assert!(filter.delete(&i));
+        }
+        assert_eq!(filter.used(), 0);
+    }
+
+    #[test]
+    fn deletes_leave_colliding_keys() {
+        let config = Config { finger_bits: 8, num_entries: 2, ..config(64) };
+        let filter = OverflowFilter::new(&config).unwrap();
+        let n = 1000u64;
+        for i in 0 .. n {
+            filter.insert(&i).unwrap();
+        }
+        // Overflowed keys collide with keys in the primary, and deleting
+        // one there would make them false negatives
+        for i in (0 .. n).rev() {
+            assert!(filter.delete(&i));
+            assert!((0 .. i).all(|j| filter.contains(&j)), "lost a key deleting {}", i);
+        }
+        assert_eq!(filter.used(), 0);
+    }
+}
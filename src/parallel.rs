@@ -0,0 +1,94 @@
+//! Lookups spread over the rayon thread pool, behind the `rayon` feature.
+//!
+//! `Filter` is not `Sync`, as inserts and deletes write through a
+//! `RefCell`, so a parallel batch borrows the buckets once and every
+//! thread reads through that borrow. Writes from the calling thread are
+//! impossible until the batch returns, which keeps it read-only throughout.
+
+use std::hash::Hash;
+
+use rayon::prelude::*;
+
+use crate::word::FingerprintWord;
+use crate::{domain_hash, BucketType, Buckets, Candidates, Filter, Scheme};
+
+impl Filter {
+    /// `contains_all` with the lookups run on the rayon thread pool. The
+    /// lookups count towards `query_stats` as with `contains`.
+    pub fn par_contains_all<T: Hash + Sync>(&self, items: &[T]) -> Vec<bool> {
+        let buckets = self.buckets.borrow();
+        let lookup = Lookup {
+            buckets: &buckets,
+            scheme: self.scheme(),
+            alt_table: self.alt_table.as_deref(),
+            mask: self.key_mask(),
+            domain: self.domain,
+        };
+        let found: Vec<bool> = match self.bucket_type {
+            BucketType::U8 => items.par_iter().map(|x| lookup.find::<u8, _>(x)).collect(),
+            BucketType::U16 => items.par_iter().map(|x| lookup.find::<u16, _>(x)).collect(),
+            BucketType::U32 => items.par_iter().map(|x| lookup.find::<u32, _>(x)).collect(),
+        };
+        drop(buckets);
+        found.iter().for_each(|&found| self.count_query(found));
+        found
+    }
+}
+
+/// What a lookup needs of a `Filter`, which unlike the filter can be
+/// shared between threads.
+struct Lookup<'a> {
+    buckets: &'a Buckets,
+    scheme: Scheme,
+    alt_table: Option<&'a [Candidates]>,
+    mask: u32,
+    domain: Option<u64>,
+}
+
+impl Lookup<'_> {
+    /// `Filter::find_word` for `x`, without the slot.
+    fn find<W: FingerprintWord, T: ?Sized + Hash>(&self, x: &T) -> bool {
+        let (finger, idx_1) = self.scheme.finger_index::<W>(domain_hash(self.domain, x));
+        self.buckets.find_in(idx_1, finger, self.mask).is_some()
+            || self.alternates(finger).iter().any(|&b| self.buckets.find_in(b, finger, self.mask).is_some())
+    }
+
+    /// `Filter::alternates_word`.
+    fn alternates<W: FingerprintWord>(&self, stored: W) -> Candidates {
+        match self.alt_table {
+            Some(table) => table[stored.widen() as usize],
+            None => self.scheme.alternates(stored),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::config;
+    use crate::{Config, Filter};
+
+    #[test]
+    fn parallel_lookups_match_sequential() {
+        let configs = [
+            Config { finger_bits: 8, ..config(1024) },
+            Config { finger_bits: 12, occupancy_bitmap: true, ..config(1024) },
+            Config { num_hashes: 3, domain: Some(3), ..config(1024) },
+            Config { finger_bits: 32, generation_bits: 2, ..config(1024) },
+        ];
+        // One in ten present, with some false positives at 8 bits
+        let items: Vec<u64> = (0 .. 20_000).collect();
+        for c in &configs {
+            let filter = Filter::new(&Config { track_queries: true, ..c.clone() }).unwrap();
+            for i in (0 .. 20_000u64).step_by(10) {
+                filter.insert(&i).unwrap();
+            }
+            let sequential = filter.contains_all(&items);
+            let stats = filter.query_stats();
+            assert_eq!(filter.par_contains_all(&items), sequential, "{:?}", c);
+            let hits = sequential.iter().filter(|&&found| found).count() as u64;
+            assert_eq!(filter.query_stats().hits, 2 * stats.hits);
+            assert_eq!(filter.query_stats().misses, 2 * stats.misses);
+            assert_eq!(stats.hits, hits);
+        }
+    }
+}
@@ -0,0 +1,400 @@
+//! Experiment plans: one or more experiment runs described in a file, for
+//! sweeps too long to spell out as flags (see `cuckoo-filter run`).
+//!
+//! A plan is written in a small subset of TOML: one `[[run]]` table per
+//! run, holding `key = value` lines whose values are strings, integers,
+//! floats or booleans, and `#` comments. Every key is optional:
+//!
+//! ```toml
+//! [[run]]
+//! name = "two hashes"     # shown while the plan runs
+//! workload = "insert"     # insert, baseline, sweep-fp, steady or delete-audit
+//! keys = 100000           # words inserted, or probed by sweep-fp
+//! key_source = "words"    # or adversarial, with the insert workload only
+//! keyed = false           # mix a secret random domain into the hashes
+//! seed = 1
+//! finger_bits = 16
+//! num_buckets = 1000      # delete-audit sizes the filter for keys otherwise
+//! num_entries = 100
+//! max_swaps = 99
+//! num_hashes = 2
+//! warm_load = 0.9         # steady only, and required there
+//! ops = 100000            # steady and delete-audit
+//! mix = "mixed"           # steady only: inserts, lookups or mixed
+//! json = "out/two.json"   # files to write, relative to the plan's
+//! text = "out/two.txt"    # directory; without either, text goes to stdout
+//! ```
+//!
+//! Unknown keys, tables and value types are rejected with the line they
+//! are on, as are keys that do not apply to the run's workload.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::experiment::{ExperimentConfig, SteadyOps};
+
+/// What a run does, with the settings that only apply to it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Workload {
+    /// Insert `keys` keys and summarize how the inserts fared
+    Insert,
+    /// Compare with hash sets (see `experiment::compare_hashset`)
+    Baseline,
+    /// False positive rate by load (see `experiment::sweep_fp`)
+    SweepFp,
+    /// Throughput at a fixed load (see `experiment::measure_steady_state`)
+    Steady { warm_load: f64, ops: usize, mix: SteadyOps },
+    /// Random inserts and deletes checked for false negatives (see
+    /// `testkit::audit_deletes`, which needs the `testkit` feature)
+    DeleteAudit { ops: usize },
+}
+
+/// Keys inserted by the `Insert` workload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeySource {
+    /// Random words (see `experiment::random_words`)
+    Words,
+    /// Keys crowding into few buckets (see `experiment::Adversarial`)
+    Adversarial,
+}
+
+/// One run of a plan.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlannedRun {
+    pub name: Option<String>,
+    pub config: ExperimentConfig,
+    pub workload: Workload,
+    pub key_source: KeySource,
+    pub keyed: bool,
+    /// Where to write the report as JSON
+    pub json: Option<PathBuf>,
+    /// Where to write the report as text
+    pub text: Option<PathBuf>,
+}
+
+/// Why `parse` rejected a plan.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlanError {
+    /// Line the problem is on, from 1
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+impl Value {
+    fn kind(&self) -> &'static str {
+        match *self {
+            Value::Str(_) => "a string",
+            Value::Int(_) => "an integer",
+            Value::Float(_) => "a float",
+            Value::Bool(_) => "a boolean",
+        }
+    }
+}
+
+/// Keys of a `[[run]]` table, with the line each is on.
+type Table = HashMap<String, (usize, Value)>;
+
+/// Parses the plan in `text`. Relative output paths are kept as written.
+pub fn parse(text: &str) -> Result<Vec<PlannedRun>, PlanError> {
+    let mut tables: Vec<(usize, Table)> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let n = i + 1;
+        let error = |message: String| PlanError { line: n, message };
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            if line != "[[run]]" {
+                return Err(error(format!("unknown table {}, expected [[run]]", line)));
+            }
+            tables.push((n, Table::new()));
+            continue;
+        }
+        let eq = line.find('=').ok_or_else(|| error(format!("expected key = value, got {:?}", line)))?;
+        let key = line[.. eq].trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(error(format!("invalid key {:?}", key)));
+        }
+        let value = parse_value(line[eq + 1 ..].trim()).map_err(error)?;
+        let table = match tables.last_mut() {
+            Some(&mut (_, ref mut table)) => table,
+            None => return Err(error(format!("key {} is outside a [[run]] table", key))),
+        };
+        if table.insert(key.to_string(), (n, value)).is_some() {
+            return Err(error(format!("duplicate key {}", key)));
+        }
+    }
+    tables.into_iter().map(|(line, table)| planned_run(line, table)).collect()
+}
+
+/// `line` without any comment, which starts at a `#` outside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[.. i],
+            _ => {},
+        }
+    }
+    line
+}
+
+fn parse_value(s: &str) -> Result<Value, String> {
+    if s.starts_with('"') {
+        return parse_string(s).map(Value::Str);
+    }
+    match s {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => {},
+    }
+    let digits = s.replace('_', "");
+    if let Ok(x) = digits.parse() {
+        return Ok(Value::Int(x));
+    }
+    match digits.parse() {
+        Ok(x) if s.bytes().any(|b| b.is_ascii_digit()) => Ok(Value::Float(x)),
+        _ => Err(format!("expected a string, number or boolean, got {:?}", s)),
+    }
+}
+
+/// A basic TOML string, with the escapes `\"`, `\\`, `\n` and `\t`.
+fn parse_string(s: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = s[1 ..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if chars.as_str().is_empty() => return Ok(out),
+            '"' => return Err(format!("unexpected {:?} after string", chars.as_str())),
+            '\\' => out.push(match chars.next() {
+                Some('"') => '"',
+                Some('\\') => '\\',
+                Some('n') => '\n',
+                Some('t') => '\t',
+                other => return Err(format!("unsupported escape \\{}", other.map_or(String::new(), String::from))),
+            }),
+            c => out.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+/// Keys a `[[run]]` table may hold.
+const KEYS: [&str; 16] = [
+    "name", "workload", "keys", "key_source", "keyed", "seed", "finger_bits", "num_buckets", "num_entries",
+    "max_swaps", "num_hashes", "warm_load", "ops", "mix", "json", "text",
+];
+
+/// Keys that only apply to some workloads, with the workloads they apply
+/// to.
+const WORKLOAD_KEYS: [(&str, &[&str]); 3] = [
+    ("warm_load", &["steady"]),
+    ("ops", &["steady", "delete-audit"]),
+    ("mix", &["steady"]),
+];
+
+fn planned_run(line: usize, mut table: Table) -> Result<PlannedRun, PlanError> {
+    let mut keys: Vec<_> = table.iter().map(|(key, &(n, _))| (n, key.as_str())).collect();
+    keys.sort();
+    if let Some(&(n, key)) = keys.iter().find(|(_, key)| !KEYS.contains(key)) {
+        let message = format!("unknown key {}, expected one of {}", key, KEYS.join(", "));
+        return Err(PlanError { line: n, message });
+    }
+    let workload = match table.remove("workload") {
+        Some(v) => (v.0, string(v, "workload")?),
+        None => (line, "insert".to_string()),
+    };
+    for &(key, workloads) in WORKLOAD_KEYS.iter() {
+        match table.get(key) {
+            Some(&(n, _)) if !workloads.contains(&workload.1.as_str()) => {
+                let message = format!("{} only applies to the {} workload", key, workloads.join(" and "));
+                return Err(PlanError { line: n, message });
+            },
+            _ => {},
+        }
+    }
+
+    let mut take = |key: &str| table.remove(key);
+    let name = take("name").map(|v| string(v, "name")).transpose()?;
+    let mut config = ExperimentConfig::default();
+    if let Some(v) = take("keys") {
+        config.keys = integer(v, "keys")?;
+    }
+    if let Some(v) = take("seed") {
+        config.filter.seed = Some(integer(v, "seed")?);
+    }
+    if let Some(v) = take("finger_bits") {
+        config.filter.finger_bits = integer(v, "finger_bits")?;
+    }
+    let num_buckets = take("num_buckets").map(|v| integer(v, "num_buckets")).transpose()?;
+    if let Some(v) = take("num_entries") {
+        config.filter.num_entries = integer(v, "num_entries")?;
+    }
+    if let Some(v) = take("max_swaps") {
+        config.filter.max_swaps = integer(v, "max_swaps")?;
+    }
+    if let Some(v) = take("num_hashes") {
+        config.filter.num_hashes = integer(v, "num_hashes")?;
+    }
+    let key_source = match take("key_source") {
+        None => KeySource::Words,
+        Some(v) => match (v.0, string(v, "key_source")?) {
+            (_, ref s) if s == "words" => KeySource::Words,
+            (_, ref s) if s == "adversarial" => KeySource::Adversarial,
+            (n, s) => return Err(unexpected(n, "key_source", &s, "words or adversarial")),
+        },
+    };
+    let keyed = take("keyed").map(|v| boolean(v, "keyed")).transpose()?.unwrap_or(false);
+    let ops = take("ops").map(|v| integer(v, "ops")).transpose()?.unwrap_or(100000);
+    let json = take("json").map(|v| string(v, "json").map(PathBuf::from)).transpose()?;
+    let text = take("text").map(|v| string(v, "text").map(PathBuf::from)).transpose()?;
+
+    let workload = match workload.1.as_str() {
+        "insert" => Workload::Insert,
+        "baseline" => Workload::Baseline,
+        "sweep-fp" => Workload::SweepFp,
+        "steady" => {
+            let warm_load = match take("warm_load") {
+                Some(v) => float(v, "warm_load")?,
+                None => return Err(PlanError { line, message: "the steady workload needs warm_load".to_string() }),
+            };
+            let mix = match take("mix") {
+                None => SteadyOps::Mixed,
+                Some(v) => match (v.0, string(v, "mix")?) {
+                    (_, ref s) if s == "inserts" => SteadyOps::Inserts,
+                    (_, ref s) if s == "lookups" => SteadyOps::Lookups,
+                    (_, ref s) if s == "mixed" => SteadyOps::Mixed,
+                    (n, s) => return Err(unexpected(n, "mix", &s, "inserts, lookups or mixed")),
+                },
+            };
+            Workload::Steady { warm_load, ops, mix }
+        },
+        "delete-audit" => Workload::DeleteAudit { ops },
+        other => {
+            let expected = "insert, baseline, sweep-fp, steady or delete-audit";
+            return Err(unexpected(workload.0, "workload", other, expected));
+        },
+    };
+    if key_source == KeySource::Adversarial && workload != Workload::Insert {
+        let message = "key_source adversarial only applies to the insert workload".to_string();
+        return Err(PlanError { line, message });
+    }
+    config.filter.num_buckets = match (num_buckets, &workload) {
+        (Some(n), _) => n,
+        (None, Workload::DeleteAudit { .. }) => audit_buckets(&config),
+        (None, _) => config.filter.num_buckets,
+    };
+    Ok(PlannedRun { name, config, workload, key_source, keyed, json, text })
+}
+
+/// Buckets for a delete audit of `config.keys` keys, when not given.
+pub fn audit_buckets(config: &ExperimentConfig) -> u32 {
+    (config.keys / config.filter.num_entries.max(1) as usize).max(1) as u32
+}
+
+fn unexpected(line: usize, key: &str, found: &str, expected: &str) -> PlanError {
+    PlanError { line, message: format!("expected {} for {}, got {:?}", expected, key, found) }
+}
+
+fn string((line, value): (usize, Value), key: &str) -> Result<String, PlanError> {
+    match value {
+        Value::Str(s) => Ok(s),
+        other => Err(PlanError { line, message: format!("expected a string for {}, got {}", key, other.kind()) }),
+    }
+}
+
+fn boolean((line, value): (usize, Value), key: &str) -> Result<bool, PlanError> {
+    match value {
+        Value::Bool(b) => Ok(b),
+        other => Err(PlanError { line, message: format!("expected true or false for {}, got {}", key, other.kind()) }),
+    }
+}
+
+/// A non-negative integer that fits `T`.
+fn integer<T: std::convert::TryFrom<i64>>((line, value): (usize, Value), key: &str) -> Result<T, PlanError> {
+    match value {
+        Value::Int(x) => T::try_from(x).map_err(|_| PlanError { line, message: format!("{} is out of range for {}", x, key) }),
+        other => Err(PlanError { line, message: format!("expected an integer for {}, got {}", key, other.kind()) }),
+    }
+}
+
+fn float((line, value): (usize, Value), key: &str) -> Result<f64, PlanError> {
+    match value {
+        Value::Float(x) => Ok(x),
+        Value::Int(x) => Ok(x as f64),
+        other => Err(PlanError { line, message: format!("expected a number for {}, got {}", key, other.kind()) }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, PlanError, KEYS};
+
+    fn error(text: &str) -> PlanError {
+        parse(text).unwrap_err()
+    }
+
+    fn at(line: usize, message: &str) -> PlanError {
+        PlanError { line, message: message.to_string() }
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let message = format!("unknown key colour, expected one of {}", KEYS.join(", "));
+        assert_eq!(error("[[run]]\nseed = 1\ncolour = \"red\"\n"), at(3, &message));
+    }
+
+    #[test]
+    fn rejects_wrong_value_types() {
+        assert_eq!(error("[[run]]\nkeys = \"many\"\n"), at(2, "expected an integer for keys, got a string"));
+        assert_eq!(error("[[run]]\nkeyed = 1\n"), at(2, "expected true or false for keyed, got an integer"));
+    }
+
+    #[test]
+    fn rejects_duplicate_keys() {
+        assert_eq!(error("[[run]]\nseed = 1\nkeys = 10\nseed = 2\n"), at(4, "duplicate key seed"));
+    }
+
+    #[test]
+    fn rejects_keys_outside_a_run() {
+        assert_eq!(error("# plan\nseed = 1\n[[run]]\n"), at(2, "key seed is outside a [[run]] table"));
+    }
+
+    #[test]
+    fn rejects_keys_of_other_workloads() {
+        let text = "[[run]]\nworkload = \"insert\"\nmix = \"lookups\"\n";
+        assert_eq!(error(text), at(3, "mix only applies to the steady workload"));
+    }
+
+    #[test]
+    fn rejects_adversarial_keys_outside_insert() {
+        let text = "[[run]]\n\n[[run]]\nworkload = \"baseline\"\nkey_source = \"adversarial\"\n";
+        assert_eq!(error(text), at(3, "key_source adversarial only applies to the insert workload"));
+    }
+
+    #[test]
+    fn hash_inside_a_string_is_not_a_comment() {
+        let runs = parse("[[run]]\nname = \"run # 1\"  # first\n").unwrap();
+        assert_eq!(runs[0].name.as_deref(), Some("run # 1"));
+        assert_eq!(error("[[run]]\nname = \"run # 1\n"), at(2, "unterminated string"));
+    }
+
+    #[test]
+    fn rejects_unterminated_strings() {
+        assert_eq!(error("[[run]]\nseed = 1\njson = \"out.json\n"), at(3, "unterminated string"));
+        assert_eq!(error("[[run]]\ntext = \"a\\\"\n"), at(2, "unterminated string"));
+    }
+}
@@ -0,0 +1,113 @@
+//! Relieving crowded buckets without the original keys.
+//!
+//! Rehashing proper would recompute every fingerprint and bucket, which
+//! needs the keys. What can be done without them is to move fingerprints
+//! between the candidate buckets of their value. A fingerprint in its
+//! primary bucket may move to an alternate bucket, which depends only on
+//! the fingerprint; lookups check them all, so every inserted value is
+//! still found. The move is one way: a fingerprint in an alternate bucket
+//! no longer reveals its primary bucket, so it can only move between its
+//! alternates, of which there is one unless `Config::num_hashes` is above
+//! 2. Crowding caused by many values sharing a primary bucket can be
+//! spread out this way; with two hashes, crowding in alternate buckets
+//! cannot.
+//!
+//! Moving costs false positives. A fingerprint in its alternate bucket
+//! matches every value with that fingerprint, since they all share the
+//! bucket, while in its primary bucket it only matches values that also
+//! share the primary bucket.
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::Filter;
+
+impl Filter {
+    /// Moves fingerprints from crowded buckets to emptier alternate
+    /// buckets, raising `fill_entropy`. Every inserted value is still
+    /// found, but false positives rise with each fingerprint moved (see the
+    /// module docs). A fingerprint moves only if its emptiest alternate
+    /// bucket would still hold fewer entries than its current one, and
+    /// passes repeat until none can. `new_seed` picks the order of moves;
+    /// fingerprints and buckets are not recomputed, as that needs the keys.
+    ///
+    /// Moved fingerprints can no longer be attributed to their primary
+    /// bucket, which widens the bounds of `estimate_distinct`. Returns
+    /// `Err` if nothing could move. Not recorded in the op log, as no value
+    /// is added or removed.
+    pub fn rehash_in_place(&self, new_seed: u64) -> Result<(), ()> {
+        let mut rng = StdRng::seed_from_u64(new_seed);
+        let mut loads = self.bucket_loads();
+        let mut buckets = self.buckets.borrow_mut();
+        let entries = self.num_entries as usize;
+        let mut moved = 0u64;
+        loop {
+            let mut movable: Vec<usize> = (0 .. buckets.len())
+                .filter(|&i| buckets.entry(i).is_some_and(|finger| {
+                    let bucket = self.bucket_entry(i).0;
+                    self.alternates(finger).iter().any(|&b| b != bucket)
+                }))
+                .collect();
+            movable.shuffle(&mut rng);
+            let mut moves = 0;
+            for i in movable {
+                let finger = buckets.get(i);
+                let from = self.bucket_entry(i).0 as usize;
+                let to = match self.alternates(finger).iter().map(|&b| b as usize).filter(|&b| b != from)
+                    .min_by_key(|&b| loads[b]) {
+                    Some(to) => to,
+                    None => continue,
+                };
+                if loads[to] + 1 >= loads[from] {
+                    continue;
+                }
+                let free = buckets.first_free(to * entries .. (to + 1) * entries)
+                    .expect("a bucket below the fullest has a free entry");
+                buckets.remove(i);
+                buckets.put(free, finger);
+                loads[from] -= 1;
+                loads[to] += 1;
+                moves += 1;
+            }
+            // Every move lowers the sum of squared loads, so this ends
+            if moves == 0 {
+                break;
+            }
+            moved += moves;
+        }
+        if moved == 0 { Err(()) } else { Ok(()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::config;
+    use crate::{Config, Filter};
+
+    #[test]
+    fn spreads_crowded_primaries_keeping_members() {
+        let filter = Filter::new(&Config { num_entries: 16, ..config(256) }).unwrap();
+        for i in 0 .. 500u64 {
+            filter.insert(&i).unwrap();
+        }
+        // Fill four primary buckets
+        let pairs: Vec<(u64, u64)> = (0 .. 64).map(|i| (i % 4, 1 + i * 1009)).collect();
+        for &(index, finger) in &pairs {
+            filter.insert_fingerprint(index, finger).unwrap();
+        }
+        let (before, used) = (filter.fill_entropy(), filter.used());
+        filter.rehash_in_place(7).unwrap();
+        assert!(filter.fill_entropy() > before, "{} -> {}", before, filter.fill_entropy());
+        assert_eq!(filter.used(), used);
+        assert!((0 .. 500u64).all(|i| filter.contains(&i)));
+        assert!(pairs.iter().all(|&(index, finger)| filter.contains_fingerprint(index, finger)));
+        assert_eq!(filter.checksum(), filter.recompute_checksum());
+    }
+
+    #[test]
+    fn nothing_to_move() {
+        let filter = Filter::new(&config(1024)).unwrap();
+        assert!(filter.rehash_in_place(7).is_err());
+    }
+}
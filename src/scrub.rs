@@ -0,0 +1,99 @@
+//! Memory scrubbing for filters built over sensitive data, behind the
+//! `zeroize` feature.
+//!
+//! Fingerprints are short, but together with the recorded op log (which
+//! holds full 64-bit key hashes) they can still leak membership of
+//! low-entropy keys, so both are overwritten on drop, as is the journal of
+//! any open checkpoint.
+
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use crate::oplog::{FilterOp, OpLog};
+use crate::{Filter, Slots};
+
+impl Filter {
+    /// Overwrites every slot and any recorded ops with zeros and resets the
+    /// filter to empty. Geometry and configuration are kept.
+    pub fn zeroize(&mut self) {
+        let buckets = self.buckets.get_mut();
+        match buckets.slots {
+            Slots::Dense(ref mut store) => store.zeroize(),
+            Slots::Sparse(ref mut sparse) => {
+                for entries in sparse.buckets.values_mut() {
+                    entries.iter_mut().for_each(Zeroize::zeroize);
+                }
+                sparse.clear();
+            }
+        }
+        if let Some(ref mut bits) = buckets.occupied {
+            bits[..].zeroize();
+        }
+        buckets.checksum = 0;
+        // Closes any checkpoint, whose journal holds old fingerprints
+        if let Some(mut journal) = buckets.journal.take() {
+            journal.iter_mut().for_each(|write| *write = (0, None));
+        }
+        if let Some(ref mut log) = self.op_log {
+            if let OpLog::Ring { ref mut ops, .. } = *log.get_mut() {
+                for op in ops.iter_mut() {
+                    *op = FilterOp::InsertHash(0);
+                }
+                ops.clear();
+            }
+        }
+        self.used.get_mut().zeroize();
+    }
+
+    /// `to_bytes` into a buffer that is zeroed when dropped. Prefer this
+    /// for filters built over sensitive data; copies made from the buffer
+    /// are the caller's responsibility.
+    pub fn to_bytes_zeroizing(&self) -> Zeroizing<Vec<u8>> {
+        Zeroizing::new(self.to_bytes())
+    }
+}
+
+impl Zeroize for Filter {
+    fn zeroize(&mut self) {
+        Filter::zeroize(self)
+    }
+}
+
+impl Drop for Filter {
+    fn drop(&mut self) {
+        Filter::zeroize(self)
+    }
+}
+
+impl ZeroizeOnDrop for Filter {}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::config;
+    use crate::{Config, Filter};
+
+    #[test]
+    fn zeroize_clears_every_slot() {
+        let configs = [
+            config(256),
+            Config { occupancy_bitmap: true, ..config(256) },
+            Config { finger_bits: 12, lazy_pages: true, ..config(256) },
+            Config { sparse_until: Some(0.5), ..config(256) },
+        ];
+        for c in configs.iter() {
+            let mut filter = Filter::new(c).unwrap();
+            filter.record_ops(16);
+            for i in 0 .. 300 {
+                filter.insert(&i).unwrap();
+            }
+            filter.zeroize();
+            let buckets = filter.buckets.borrow();
+            assert!((0 .. buckets.len()).all(|i| buckets.get(i) == 0 && !buckets.is_occupied(i)), "{:?}", c);
+            assert!(buckets.occupied.as_ref().is_none_or(|bits| bits.iter().all(|&x| x == 0)));
+            drop(buckets);
+            assert_eq!(filter.used(), 0);
+            assert!(filter.take_ops().is_empty());
+            assert!((0 .. 300).all(|i| !filter.contains(&i)));
+            assert_eq!(filter.config(), Filter::new(c).unwrap().config());
+        }
+    }
+}
@@ -0,0 +1,604 @@
+//! Versioned byte format for `Filter`.
+//!
+//! All multi-byte integers are fixed-width and little-endian:
+//!
+//! ```text
+//! magic        4 bytes   b"CKOO"
+//! version      u16
+//! finger_bits  u8
+//! num_entries  u8        low byte
+//! max_swaps    u8
+//! num_buckets  u32
+//! hash_count   u8        (since version 3; 1 before)
+//! flags        u8        bit 0: occupancy bitmap (since version 4; 0 before)
+//!                        bits 1-4: generation_bits
+//!                        bit 5: domain is set (since version 5)
+//! domain       u64       (since version 5; 0 before, and 0 if unset)
+//! num_hashes   u8        (since version 6; 2 before)
+//! num_entries  u8        high byte (since version 7; 0 before)
+//! used         u64
+//! buckets      num_buckets * num_entries fingerprints, ceil(finger_bits / 8) bytes each
+//! occupied     ceil(slots / 64) u64 words, only with the occupancy bitmap flag
+//! checksum     u64       FNV-1a of everything above (since version 2)
+//! ```
+//!
+//! Slot `i` is entry `i % num_entries` of bucket `i / num_entries`, and is
+//! occupied if bit `i % 64` of bitmap word `i / 64` is set. Fingerprints and
+//! bitmap words are encoded one at a time whatever the in-memory `Layout`,
+//! so the bytes do not depend on the host's endianness or pointer width
+//! and load anywhere. Lookups only agree across hosts if keys hash the same
+//! on both, which the standard library's hasher does not promise.
+//!
+//! Older versions are upgraded by `migrate` before decoding, so `from_bytes`
+//! only ever parses the current layout.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::word::FingerprintWord;
+use crate::{Buckets, Config, Filter, Layout};
+
+/// Current version of the byte format written by `to_bytes`.
+pub const FORMAT_VERSION: u16 = 7;
+
+/// Length of the config header returned by `Filter::header_bytes`.
+pub const CONFIG_HEADER_LEN: usize = 25;
+
+const MAGIC: [u8; 4] = *b"CKOO";
+pub(crate) const HEADER_LEN: usize = CONFIG_HEADER_LEN + 8;
+pub(crate) const CHECKSUM_LEN: usize = 8;
+
+const FLAG_OCCUPANCY_BITMAP: u8 = 1;
+const FLAG_GENERATION_SHIFT: u8 = 1;
+const FLAG_GENERATION_BITS: u8 = 0b1_1110;
+const FLAG_DOMAIN: u8 = 0b10_0000;
+
+/// Largest `generation_bits` the flags byte holds.
+pub(crate) const MAX_GENERATION_BITS: u8 = FLAG_GENERATION_BITS >> FLAG_GENERATION_SHIFT;
+
+impl Filter {
+    /// Writes `to_bytes` to `path` atomically: the bytes go to a temporary
+    /// file beside it, which is synced and then renamed over `path`, so
+    /// readers see either the old file or the complete new one.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&self.to_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Serializes the filter using the current `FORMAT_VERSION`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.payload_len() + CHECKSUM_LEN);
+        out.extend_from_slice(&self.header_bytes());
+        out.extend_from_slice(&self.used().to_le_bytes());
+        self.write_payload(&self.buckets.borrow(), &mut out);
+        let checksum = fnv1a(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+        out
+    }
+
+    /// Appends the serialized buckets, including the occupancy bitmap if
+    /// there is one, to `out`.
+    pub(crate) fn write_payload(&self, buckets: &Buckets, out: &mut Vec<u8>) {
+        self.write_fingers(buckets, 0 .. buckets.len(), out);
+        self.write_bitmap(buckets, out);
+    }
+
+    /// Appends the serialized fingerprints of the slots in `range` to
+    /// `out`.
+    pub(crate) fn write_fingers(&self, buckets: &Buckets, range: Range<usize>, out: &mut Vec<u8>) {
+        // The format is always row-major
+        let row_major = buckets.layout == Layout::RowMajor;
+        match (u8::slice(&buckets.slots), u16::slice(&buckets.slots)) {
+            (Some(vec), _) if row_major => out.extend_from_slice(&vec[range]),
+            (_, Some(vec)) if row_major => {
+                for x in vec[range].iter() {
+                    out.extend_from_slice(&x.to_le_bytes());
+                }
+            }
+            _ => {
+                let width = self.finger_bits.div_ceil(8) as usize;
+                for i in range {
+                    out.extend_from_slice(&buckets.get(i).to_le_bytes()[.. width]);
+                }
+            }
+        }
+    }
+
+    /// Appends the serialized occupancy bitmap, if there is one, to `out`.
+    pub(crate) fn write_bitmap(&self, buckets: &Buckets, out: &mut Vec<u8>) {
+        match buckets.occupied {
+            Some(ref bits) if buckets.layout == Layout::RowMajor => {
+                for x in bits.iter() {
+                    out.extend_from_slice(&x.to_le_bytes());
+                }
+            },
+            _ if self.occupancy_bitmap => {
+                // Sparse or column-major: rebuild the row-major words
+                let n = buckets.len();
+                for start in (0 .. n).step_by(64) {
+                    let word = (start .. n.min(start + 64))
+                        .filter(|&i| buckets.is_occupied(i))
+                        .fold(0u64, |w, i| w | 1 << (i - start));
+                    out.extend_from_slice(&word.to_le_bytes());
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Deserializes a filter written by `to_bytes` of this or any older
+    /// format version.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Filter, DecodeError> {
+        if !bytes.starts_with(&MAGIC) {
+            return invalid(DecodeError::BadMagic);
+        }
+        // Older headers are shorter, so only the version is needed before
+        // migrating
+        if bytes.len() < 6 {
+            return invalid(DecodeError::Truncated);
+        }
+        let version = read_u16(bytes, 4);
+        let migrated;
+        let bytes = if version == FORMAT_VERSION {
+            bytes
+        } else {
+            migrated = migrate(version, bytes)?;
+            &migrated[..]
+        };
+
+        if bytes.len() < HEADER_LEN + CHECKSUM_LEN {
+            return invalid(DecodeError::Truncated);
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+        if fnv1a(body) != read_u64(checksum, 0) {
+            return invalid(DecodeError::ChecksumMismatch);
+        }
+        let config = Config::from_header(body)?;
+        let used = read_u64(body, CONFIG_HEADER_LEN);
+        let payload = &body[HEADER_LEN ..];
+        // Checked before building the filter, so that a forged header
+        // cannot make it allocate more than the buffer holds
+        match config.bucket_bytes() {
+            Some(len) if len == payload.len() => {},
+            Some(_) => return invalid(DecodeError::InconsistentPayload),
+            None => return invalid(DecodeError::UnsupportedConfig),
+        }
+        let filter = match Filter::new(&config) {
+            Ok(filter) => filter,
+            Err(_) => return invalid(DecodeError::UnsupportedConfig),
+        };
+        if used > filter.capacity() {
+            return invalid(DecodeError::InconsistentPayload);
+        }
+        let mut buckets = filter.buckets.borrow_mut();
+        let (payload, bitmap) = payload.split_at(filter.fingers_len());
+        if let Some(vec) = u8::slice_mut(&mut buckets.slots) {
+            vec.copy_from_slice(payload);
+        } else if let Some(vec) = u16::slice_mut(&mut buckets.slots) {
+            for (x, chunk) in vec.iter_mut().zip(payload.chunks_exact(2)) {
+                *x = u16::from_le_bytes([chunk[0], chunk[1]]);
+            }
+        } else {
+            // 32-bit or packed fingerprints, read one at a time
+            let width = filter.finger_bits.div_ceil(8) as usize;
+            let max = u32::MAX >> (32 - filter.finger_bits);
+            for (i, chunk) in payload.chunks_exact(width).enumerate() {
+                let mut b = [0u8; 4];
+                b[.. width].copy_from_slice(chunk);
+                let finger = u32::from_le_bytes(b);
+                if finger > max {
+                    return invalid(DecodeError::InconsistentPayload);
+                }
+                buckets.set(i, finger);
+            }
+        }
+        if let Some(ref mut bits) = buckets.occupied {
+            for (i, x) in bits.iter_mut().enumerate() {
+                *x = read_u64(bitmap, i * 8);
+            }
+        }
+        buckets.checksum = buckets.full_checksum();
+        drop(buckets);
+        filter.used.replace(used);
+        Ok(filter)
+    }
+
+    /// Encodes the config fields (magic, version and geometry) without the
+    /// buckets, so a receiver can check compatibility before downloading a
+    /// whole filter. This is also the prefix of `to_bytes`.
+    pub fn header_bytes(&self) -> [u8; CONFIG_HEADER_LEN] {
+        let mut out = [0u8; CONFIG_HEADER_LEN];
+        out[0 .. 4].copy_from_slice(&MAGIC);
+        out[4 .. 6].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+        out[6] = self.finger_bits;
+        out[7] = self.num_entries as u8;
+        out[8] = self.max_swaps;
+        out[9 .. 13].copy_from_slice(&self.num_buckets.to_le_bytes());
+        out[13] = self.hash_count;
+        out[14] = if self.occupancy_bitmap { FLAG_OCCUPANCY_BITMAP } else { 0 }
+            | self.generation_bits << FLAG_GENERATION_SHIFT
+            | if self.domain.is_some() { FLAG_DOMAIN } else { 0 };
+        out[15 .. 23].copy_from_slice(&self.domain.unwrap_or(0).to_le_bytes());
+        out[23] = self.num_hashes;
+        out[24] = (self.num_entries >> 8) as u8;
+        out
+    }
+
+    /// Length in bytes of the serialized buckets, including the occupancy
+    /// bitmap if there is one.
+    fn payload_len(&self) -> usize {
+        let bitmap_len = if self.occupancy_bitmap {
+            (self.capacity() as usize).div_ceil(64) * 8
+        } else {
+            0
+        };
+        self.fingers_len() + bitmap_len
+    }
+
+    /// Length in bytes of the serialized fingerprints.
+    fn fingers_len(&self) -> usize {
+        self.capacity() as usize * self.finger_bits.div_ceil(8) as usize
+    }
+}
+
+/// Reasons `Filter::from_bytes` or `Config::from_header` rejects a buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The buffer does not start with the format's magic bytes.
+    BadMagic,
+    /// The version is 0 or newer than this build reads. Every version from
+    /// 1 to `supported` is read.
+    UnsupportedVersion { found: u16, supported: u16 },
+    /// The buffer ends inside its header or checksum.
+    Truncated,
+    /// The trailing checksum does not match the contents.
+    ChecksumMismatch,
+    /// The header sets flags its version does not define.
+    UnknownFlags,
+    /// The header describes a filter this build cannot construct.
+    UnsupportedConfig,
+    /// The buckets' length or the used count disagrees with the header.
+    InconsistentPayload,
+}
+
+/// What `inspect_serialized` learns from a serialized filter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SerializedInfo {
+    /// Configuration from the header
+    pub config: Config,
+
+    /// Entries used (occupied)
+    pub used: u64,
+
+    /// Whether the trailing checksum matches the contents
+    pub checksum_ok: bool,
+}
+
+/// Reads `Filter::to_bytes` output of the current `FORMAT_VERSION` from
+/// `reader` in constant memory, checking its length against the header
+/// and its checksum. Fails with `InvalidData` on a bad header, a length
+/// that does not match it (such as a truncated file) or an older version.
+pub fn inspect_serialized<R: Read>(mut reader: R) -> io::Result<SerializedInfo> {
+    let mut header = [0u8; HEADER_LEN];
+    read_all(&mut reader, &mut header)?;
+    let config = Config::from_header(&header).map_err(|_| invalid_data("bad header"))?;
+    if read_u16(&header, 4) != FORMAT_VERSION {
+        return Err(invalid_data("old format version; convert with Filter::from_bytes"));
+    }
+    let len = config.serialized_len().ok_or_else(|| invalid_data("unsupported config"))?;
+    let used = read_u64(&header, CONFIG_HEADER_LEN);
+    if used > config.num_buckets as u64 * config.num_entries as u64 {
+        return Err(invalid_data("inconsistent payload"));
+    }
+
+    let mut h = fnv1a(&header);
+    let mut remaining = len - HEADER_LEN - CHECKSUM_LEN;
+    let mut buf = vec![0u8; remaining.min(1 << 16)];
+    while remaining > 0 {
+        let chunk = &mut buf[.. remaining.min(1 << 16)];
+        read_all(&mut reader, chunk)?;
+        h = fnv1a_update(h, chunk);
+        remaining -= chunk.len();
+    }
+    let mut checksum = [0u8; CHECKSUM_LEN];
+    read_all(&mut reader, &mut checksum)?;
+    if reader.read(&mut [0u8])? != 0 {
+        return Err(invalid_data("trailing bytes"));
+    }
+    Ok(SerializedInfo { config, used, checksum_ok: h == read_u64(&checksum, 0) })
+}
+
+/// `read_exact` reporting a short read as `InvalidData`.
+fn read_all<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<()> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => invalid_data("truncated"),
+        _ => e,
+    })
+}
+
+pub(crate) fn invalid_data(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+impl Config {
+    /// Bytes of the bucket array (fingerprints and any occupancy bitmap) of
+    /// a dense filter with this configuration as serialized, or None if it
+    /// cannot be built. Fingerprint widths other than 8, 16 and 32 bits are
+    /// packed in memory, taking less.
+    pub fn bucket_bytes(&self) -> Option<usize> {
+        if !(1 ..= 32).contains(&self.finger_bits) {
+            return None;
+        }
+        let slots = self.slot_count()?;
+        let bitmap_len = if self.occupancy_bitmap { slots.div_ceil(64) * 8 } else { 0 };
+        Some(slots * self.finger_bits.div_ceil(8) as usize + bitmap_len)
+    }
+
+    /// Length of `Filter::to_bytes` output for this configuration, or None
+    /// if it cannot be built.
+    pub fn serialized_len(&self) -> Option<usize> {
+        Some(HEADER_LEN + self.bucket_bytes()? + CHECKSUM_LEN)
+    }
+
+    /// Decodes a header written by `Filter::header_bytes` (or the start of
+    /// a `to_bytes` buffer of any supported version).
+    pub fn from_header(bytes: &[u8]) -> Result<Config, DecodeError> {
+        if !bytes.starts_with(&MAGIC) {
+            return invalid(DecodeError::BadMagic);
+        }
+        if bytes.len() < 13 {
+            return invalid(DecodeError::Truncated);
+        }
+        let version = read_u16(bytes, 4);
+        if version == 0 || version > FORMAT_VERSION {
+            return invalid(unsupported(version));
+        }
+        if (version >= 3 && bytes.len() < 14) || (version >= 4 && bytes.len() < 15)
+            || (version >= 5 && bytes.len() < 23) || (version >= 6 && bytes.len() < 24)
+            || (version >= 7 && bytes.len() < 25) {
+            return invalid(DecodeError::Truncated);
+        }
+        let flags = if version >= 4 { bytes[14] } else { 0 };
+        if flags & !(FLAG_OCCUPANCY_BITMAP | FLAG_GENERATION_BITS | FLAG_DOMAIN) != 0 {
+            return invalid(DecodeError::UnknownFlags);
+        }
+        let domain = if flags & FLAG_DOMAIN != 0 { Some(read_u64(bytes, 15)) } else { None };
+        Ok(Config {
+            finger_bits: bytes[6],
+            num_entries: u16::from_le_bytes([bytes[7], if version >= 7 { bytes[24] } else { 0 }]),
+            max_swaps: bytes[8],
+            num_buckets: read_u32(bytes, 9),
+            hash_count: if version >= 3 { bytes[13] } else { 1 },
+            occupancy_bitmap: flags & FLAG_OCCUPANCY_BITMAP != 0,
+            generation_bits: (flags & FLAG_GENERATION_BITS) >> FLAG_GENERATION_SHIFT,
+            domain,
+            num_hashes: if version >= 6 { bytes[23] } else { 2 },
+            ..Config::default()
+        })
+    }
+}
+
+/// Header fields added by each version, as `(from_version, offset,
+/// bytes)`: upgrading a buffer from `from_version` inserts `bytes`, the
+/// field's value in older filters, at `offset`.
+const MIGRATIONS: [(u16, usize, &[u8]); 6] = [
+    // Version 1 had no trailing checksum.
+    (1, 0, &[]),
+    // Version 2 had no hash_count; it always used a single hash.
+    (2, 13, &[1]),
+    // Version 3 had no flags; fingerprint 0 always marked empty.
+    (3, 14, &[0]),
+    // Version 4 had no domain; keys were hashed without one.
+    (4, 15, &[0; 8]),
+    // Version 5 had no num_hashes; values had two candidate buckets.
+    (5, 23, &[2]),
+    // Version 6 had no high byte of num_entries; it was at most 255.
+    (6, 24, &[0]),
+];
+
+/// Upgrades a buffer written with an older format version to
+/// `FORMAT_VERSION`, one version at a time.
+fn migrate(version: u16, bytes: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let mut out = bytes.to_vec();
+    let mut version = version;
+    while version < FORMAT_VERSION {
+        match MIGRATIONS.iter().find(|step| step.0 == version) {
+            Some(&(_, offset, inserted)) => migrate_step(&mut out, version, offset, inserted)?,
+            None => return invalid(unsupported(version)),
+        }
+        #[cfg(feature = "tracing")]
+        tracing::info!(from = version, to = version + 1, "migrated serialized filter");
+        version += 1;
+    }
+    if version == FORMAT_VERSION {
+        Ok(out)
+    } else {
+        invalid(unsupported(version))
+    }
+}
+
+/// Upgrades `out` from `version` to the next version: checks and strips
+/// its checksum, if it has one, inserts `inserted` at `offset`, and
+/// checksums the result.
+fn migrate_step(out: &mut Vec<u8>, version: u16, offset: usize, inserted: &[u8]) -> Result<(), DecodeError> {
+    // Version 1 has no checksum to check
+    if version >= 2 {
+        let split = match out.len().checked_sub(CHECKSUM_LEN) {
+            Some(split) if split >= offset => split,
+            _ => return invalid(DecodeError::Truncated),
+        };
+        if fnv1a(&out[.. split]) != read_u64(out, split) {
+            return invalid(DecodeError::ChecksumMismatch);
+        }
+        out.truncate(split);
+    }
+    out.splice(offset .. offset, inserted.iter().copied());
+    out[4 .. 6].copy_from_slice(&(version + 1).to_le_bytes());
+    let checksum = fnv1a(out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    Ok(())
+}
+
+/// Rejects a buffer, reporting why when `tracing` is enabled.
+#[inline(always)]
+fn invalid<T>(e: DecodeError) -> Result<T, DecodeError> {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(reason = ?e, "rejected serialized filter");
+    Err(e)
+}
+
+fn unsupported(version: u16) -> DecodeError {
+    DecodeError::UnsupportedVersion { found: version, supported: FORMAT_VERSION }
+}
+
+/// 64-bit FNV-1a hash.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    fnv1a_update(0xcbf2_9ce4_8422_2325, bytes)
+}
+
+/// Continues an FNV-1a hash `h` over `bytes`.
+pub(crate) fn fnv1a_update(mut h: u64, bytes: &[u8]) -> u64 {
+    for &b in bytes {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    h
+}
+
+pub(crate) fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+pub(crate) fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    let mut b = [0u8; 4];
+    b.copy_from_slice(&bytes[at .. at + 4]);
+    u32::from_le_bytes(b)
+}
+
+pub(crate) fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(&bytes[at .. at + 8]);
+    u64::from_le_bytes(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A version 1 filter of 2 buckets of 2 8-bit entries, written by hand:
+    /// no hash_count, flags, domain, num_hashes or checksum.
+    fn v1_bytes() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"CKOO");
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&[8, 2, 100]);
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(&2u64.to_le_bytes());
+        bytes.extend_from_slice(&[0, 7, 0, 0x5a]);
+        bytes
+    }
+
+    #[test]
+    fn v1_loads_and_reserializes_as_current_version() {
+        let filter = Filter::from_bytes(&v1_bytes()).unwrap();
+        let config = filter.config();
+        assert_eq!((config.finger_bits, config.num_entries, config.max_swaps, config.num_buckets), (8, 2, 100, 2));
+        assert_eq!((config.hash_count, config.num_hashes, config.domain), (1, 2, None));
+        assert_eq!(filter.used(), 2);
+        assert!(filter.contains_fingerprint(0, 7));
+        assert!(filter.contains_fingerprint(1, 0x5a));
+
+        let bytes = filter.to_bytes();
+        assert_eq!(read_u16(&bytes, 4), FORMAT_VERSION);
+        assert_eq!(bytes.len(), HEADER_LEN + 4 + CHECKSUM_LEN);
+        assert_eq!(&bytes[HEADER_LEN .. HEADER_LEN + 4], &[0, 7, 0, 0x5a]);
+        assert_eq!(Filter::from_bytes(&bytes).unwrap().to_bytes(), bytes);
+    }
+
+    #[test]
+    fn header_round_trips_config() {
+        let config = Config {
+            finger_bits: 12,
+            num_buckets: 300,
+            num_entries: 260,
+            max_swaps: 50,
+            hash_count: 2,
+            num_hashes: 3,
+            occupancy_bitmap: true,
+            generation_bits: 4,
+            domain: Some(0xdead_beef),
+            ..Config::default()
+        };
+        let filter = Filter::new(&config).unwrap();
+        let header = filter.header_bytes();
+        assert_eq!(Config::from_header(&header).unwrap(), config);
+        assert_eq!(&filter.to_bytes()[.. CONFIG_HEADER_LEN], &header[..]);
+        assert_eq!(Config::from_header(&header[.. CONFIG_HEADER_LEN - 1]), Err(DecodeError::Truncated));
+    }
+
+    #[test]
+    fn generation_bits_fit_the_flags() {
+        let config = Config { finger_bits: 32, generation_bits: 15, ..crate::tests::config(64) };
+        let filter = Filter::new(&config).unwrap();
+        filter.insert_with_ttl(&1u64, 0, 100).unwrap();
+        let loaded = Filter::from_bytes(&filter.to_bytes()).unwrap();
+        assert_eq!(loaded.config().generation_bits, 15);
+        assert_eq!(loaded.config().domain, None);
+        assert!(loaded.contains(&1u64));
+
+        // 16 would spill into the domain flag
+        assert!(Filter::new(&Config { generation_bits: 16, ..config }).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let mut bytes = v1_bytes();
+        bytes[4 .. 6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        let e = Filter::from_bytes(&bytes).unwrap_err();
+        assert_eq!(e, DecodeError::UnsupportedVersion { found: FORMAT_VERSION + 1, supported: FORMAT_VERSION });
+    }
+    #[test]
+    fn golden_bytes() {
+        let filter = Filter::new(&Config { num_entries: 2, ..crate::tests::config(2) }).unwrap();
+        filter.insert_fingerprint(0, 0x1234).unwrap();
+        filter.insert_fingerprint(1, 0xabcd).unwrap();
+        let mut expected = vec![b'C', b'K', b'O', b'O', 7, 0, 16, 2, 100, 2, 0, 0, 0, 1, 0];
+        expected.extend_from_slice(&[0; 8]);
+        expected.extend_from_slice(&[2, 0]);
+        expected.extend_from_slice(&[2, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&[0x34, 0x12, 0, 0, 0xcd, 0xab, 0, 0]);
+        expected.extend_from_slice(&0x0e77_a1b7_0522_7d7du64.to_le_bytes());
+        assert_eq!(filter.to_bytes(), expected);
+    }
+
+    #[test]
+    fn foreign_endian_payload_is_normalized() {
+        let filter = Filter::new(&crate::tests::config(64)).unwrap();
+        for i in 0 .. 200u64 {
+            filter.insert(&i).unwrap();
+        }
+        // The bucket array as a big-endian host holds it in memory
+        let native: Vec<u8> = (0 .. 256).flat_map(|i| filter.buckets.borrow().get(i).to_be_bytes()[2 ..].to_vec()).collect();
+        let bytes = filter.to_bytes();
+        let payload = &bytes[HEADER_LEN .. HEADER_LEN + 512];
+        assert_ne!(payload, &native[..]);
+        let swapped: Vec<u8> = native.chunks(2).flat_map(|pair| vec![pair[1], pair[0]]).collect();
+        assert_eq!(payload, &swapped[..]);
+
+        // A payload left in big-endian order loads as different fingerprints
+        let mut foreign = bytes.clone();
+        foreign[HEADER_LEN .. HEADER_LEN + 512].copy_from_slice(&native);
+        let split = foreign.len() - CHECKSUM_LEN;
+        let checksum = fnv1a(&foreign[.. split]);
+        foreign[split ..].copy_from_slice(&checksum.to_le_bytes());
+        let misread = Filter::from_bytes(&foreign).unwrap();
+        assert!((0 .. 200u64).filter(|i| misread.contains(i)).count() < 10);
+        assert!((0 .. 200u64).all(|i| Filter::from_bytes(&bytes).unwrap().contains(&i)));
+    }
+}
@@ -0,0 +1,91 @@
+//! Fill simulations, for finding the load a configuration reaches in
+//! practice before deploying it.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{Config, Filter};
+
+/// Load factors at which the first insert failed, over the trials of
+/// `Filter::simulate_fill`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FillSimulation {
+    pub trials: usize,
+    pub mean_load: f64,
+    pub median_load: f64,
+    pub min_load: f64,
+    pub max_load: f64,
+}
+
+impl Filter {
+    /// Inserts random values into `trials` copies of the filter, each
+    /// until an insert fails, and reports the load factors reached. The
+    /// filter itself is not modified, but the values and the copies' kicks
+    /// are drawn from its random number generator, so a seeded filter
+    /// gives the same results each time. All zeros if `trials` is 0.
+    pub fn simulate_fill(&self, trials: usize) -> FillSimulation {
+        let mut rng = StdRng::seed_from_u64(self.rng.borrow_mut().gen());
+        let config = Config { sparse_until: None, ..self.config() };
+        let mut loads: Vec<f64> = (0 .. trials)
+            .map(|_| {
+                let copy = Filter::new(&Config { seed: Some(rng.gen()), ..config.clone() })
+                    .expect("config of an existing filter");
+                let mut buckets = self.buckets.borrow().clone();
+                buckets.journal = None;
+                copy.buckets.replace(buckets);
+                copy.used.replace(self.used());
+                while copy.insert_hash(rng.gen()).is_ok() {}
+                copy.load_factor()
+            })
+            .collect();
+        if loads.is_empty() {
+            return FillSimulation::default();
+        }
+        loads.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let n = loads.len();
+        FillSimulation {
+            trials,
+            mean_load: loads.iter().sum::<f64>() / n as f64,
+            median_load: (loads[(n - 1) / 2] + loads[n / 2]) / 2.0,
+            min_load: loads[0],
+            max_load: loads[n - 1],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::config;
+    use crate::{expected_max_load, Config, Filter, FillSimulation};
+
+    #[test]
+    fn failures_start_near_expected_load() {
+        for &num_entries in &[2, 4, 8, 16] {
+            let filter = Filter::new(&Config { num_entries, ..config(1024) }).unwrap();
+            let sim = filter.simulate_fill(5);
+            let expected = expected_max_load(num_entries);
+            assert_eq!(sim.trials, 5);
+            assert!(sim.min_load <= sim.median_load && sim.median_load <= sim.max_load);
+            assert!(sim.min_load >= expected, "{} entries: {:?}", num_entries, sim);
+            assert!(sim.max_load < 1.0);
+            assert!(sim.mean_load - expected < 0.25, "{} entries: {:?}", num_entries, sim);
+            assert_eq!(filter.used(), 0);
+        }
+    }
+
+    #[test]
+    fn starts_from_current_contents() {
+        let filter = Filter::new(&config(1024)).unwrap();
+        for i in 0 .. 2000u64 {
+            filter.insert(&i).unwrap();
+        }
+        let bytes = filter.to_bytes();
+        let sim = filter.simulate_fill(3);
+        assert!(sim.min_load > 2000.0 / 4096.0);
+        assert_eq!(filter.to_bytes(), bytes);
+        assert_eq!(filter.simulate_fill(0), FillSimulation::default());
+        let twin = Filter::new(&config(1024)).unwrap();
+        let other = Filter::new(&config(1024)).unwrap();
+        assert_eq!(twin.simulate_fill(3), other.simulate_fill(3));
+    }
+}
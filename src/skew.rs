@@ -0,0 +1,125 @@
+//! Whether stored fingerprints are spread evenly over their values, as a
+//! good hash makes them. A poor `Hash` implementation that gives many
+//! values the same or similar hashes concentrates fingerprints on a few
+//! values, and lookups of other values sharing them become false
+//! positives far more often than `Filter::estimated_fpp` says.
+//!
+//! Fingerprint values are grouped into at most 65536 bins of (nearly)
+//! equal width, fewer when there are too few fingerprints to expect 5 in
+//! each, and the counts are compared with a uniform spread by Pearson's
+//! chi-squared test. Large filters are sampled at an even stride.
+
+use crate::Filter;
+
+/// Most slots read by `Filter::fingerprint_skew`.
+const SAMPLE_SLOTS: usize = 1 << 20;
+
+/// Standard deviations above its expectation at which the chi-squared
+/// statistic makes `SkewReport::is_suspicious` hold.
+const SUSPICIOUS_Z_SCORE: f64 = 4.0;
+
+/// Result of `Filter::fingerprint_skew`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SkewReport {
+    /// Fingerprints counted
+    pub samples: u64,
+
+    /// Bins the fingerprint values were grouped into
+    pub bins: usize,
+
+    /// Pearson's chi-squared statistic of the bin counts against a
+    /// uniform spread, with `bins - 1` degrees of freedom
+    pub chi_squared: f64,
+
+    /// Standard deviations by which `chi_squared` exceeds its expectation
+    /// for uniform fingerprints; 0 with fewer than 2 bins
+    pub z_score: f64,
+}
+
+impl SkewReport {
+    /// True if the fingerprints are spread too unevenly for a good hash,
+    /// pointing at a `Hash` implementation that clusters values.
+    pub fn is_suspicious(&self) -> bool {
+        self.z_score > SUSPICIOUS_Z_SCORE
+    }
+}
+
+impl Filter {
+    /// Tests the stored fingerprints, ignoring generation tags, for an
+    /// even spread over their values (see the module docs).
+    pub fn fingerprint_skew(&self) -> SkewReport {
+        let buckets = self.buckets.borrow();
+        let step = buckets.len().div_ceil(SAMPLE_SLOTS).max(1);
+        let (lowest, mask) = (if self.occupancy_bitmap { 0 } else { 1 }, self.key_mask());
+        let fingers: Vec<u64> = (0 .. buckets.len()).step_by(step)
+            .filter_map(|i| buckets.entry(i))
+            .filter_map(|x| ((x & mask) as u64).checked_sub(lowest))
+            .collect();
+        drop(buckets);
+
+        let values = self.finger_values();
+        let samples = fingers.len() as u64;
+        let bins = (samples / 5).min(values).min(1 << 16) as usize;
+        if bins < 2 {
+            return SkewReport { samples, bins, ..SkewReport::default() };
+        }
+        // Bin `b` holds values from `b * values / bins` up to the next
+        // bin's start
+        let mut counts = vec![0u64; bins];
+        for x in fingers {
+            counts[(x as u128 * bins as u128 / values as u128) as usize] += 1;
+        }
+        let start = |b: usize| (b as u128 * values as u128).div_ceil(bins as u128) as f64;
+        let chi_squared: f64 = counts.iter().enumerate()
+            .map(|(b, &count)| {
+                let expected = samples as f64 * (start(b + 1) - start(b)) / values as f64;
+                (count as f64 - expected).powi(2) / expected
+            })
+            .sum();
+        let df = (bins - 1) as f64;
+        SkewReport { samples, bins, chi_squared, z_score: (chi_squared - df) / (2.0 * df).sqrt() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{Hash, Hasher};
+
+    use crate::tests::config;
+    use crate::{Config, Filter};
+
+    /// Session id whose hash covers a constant prefix and only the low
+    /// byte of the id.
+    struct Session(u64);
+
+    impl Hash for Session {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            state.write(b"session:");
+            state.write_u8(self.0 as u8);
+        }
+    }
+
+    #[test]
+    fn weak_hash_is_suspicious_and_uniform_is_not() {
+        for &(finger_bits, occupancy_bitmap) in &[(8, false), (16, true), (32, false)] {
+            let c = Config { finger_bits, occupancy_bitmap, ..config(1024) };
+            let uniform = Filter::new(&c).unwrap();
+            for i in 0 .. 2000u64 {
+                uniform.insert(&i).unwrap();
+            }
+            let report = uniform.fingerprint_skew();
+            assert_eq!(report.samples, 2000);
+            assert!(!report.is_suspicious(), "{} bits: {:?}", finger_bits, report);
+
+            // 256 distinct hashes, 2 or 3 copies of each
+            let weak = Filter::new(&c).unwrap();
+            for i in 0 .. 600u64 {
+                weak.insert(&Session(i)).unwrap();
+            }
+            let report = weak.fingerprint_skew();
+            assert!(report.is_suspicious(), "{} bits: {:?}", finger_bits, report);
+        }
+        let empty = Filter::new(&config(1024)).unwrap().fingerprint_skew();
+        assert_eq!((empty.samples, empty.bins, empty.z_score), (0, 0, 0.0));
+    }
+}
@@ -0,0 +1,167 @@
+//! Sparse bucket storage for filters provisioned far beyond their current
+//! contents (see `Config::sparse_until`).
+//!
+//! Only buckets holding at least one fingerprint are allocated. Once the
+//! number of occupied slots passes the configured limit, `Buckets` converts
+//! itself to the dense representation for good.
+
+use std::collections::HashMap;
+use std::mem;
+
+use crate::store::U8Store;
+use crate::{Buckets, Config, Slots};
+
+#[derive(Clone, Debug)]
+pub(crate) struct SparseSlots {
+    /// Configuration of the dense representation
+    pub(crate) config: Config,
+
+    /// Total number of slots, allocated or not
+    pub(crate) capacity: usize,
+
+    /// Occupied slot count above which to convert to dense
+    pub(crate) limit: u64,
+
+    /// Occupied slots
+    pub(crate) occupied: u64,
+
+    /// Entries of the occupied buckets, keyed by bucket index
+    pub(crate) buckets: HashMap<u32, Box<[Option<u32>]>>,
+}
+
+impl SparseSlots {
+    pub(crate) fn new(c: &Config, capacity: usize, limit: u64) -> SparseSlots {
+        SparseSlots {
+            config: c.clone(),
+            capacity,
+            limit,
+            occupied: 0,
+            buckets: HashMap::new(),
+        }
+    }
+
+    fn locate(&self, i: usize) -> (u32, usize) {
+        let entries = self.config.num_entries as usize;
+        ((i / entries) as u32, i % entries)
+    }
+
+    pub(crate) fn entry(&self, i: usize) -> Option<u32> {
+        let (bucket, entry) = self.locate(i);
+        self.buckets.get(&bucket).and_then(|b| b[entry])
+    }
+
+    pub(crate) fn put(&mut self, i: usize, finger: u32) {
+        let (bucket, entry) = self.locate(i);
+        let entries = self.config.num_entries as usize;
+        let b = self.buckets.entry(bucket).or_insert_with(|| vec![None; entries].into_boxed_slice());
+        if b[entry].replace(finger).is_none() {
+            self.occupied += 1;
+        }
+    }
+
+    pub(crate) fn remove(&mut self, i: usize) {
+        let (bucket, entry) = self.locate(i);
+        if let Some(b) = self.buckets.get_mut(&bucket) {
+            if b[entry].take().is_some() {
+                self.occupied -= 1;
+            }
+            if b.iter().all(Option::is_none) {
+                self.buckets.remove(&bucket);
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.buckets.clear();
+        self.occupied = 0;
+    }
+
+    /// Heap bytes held by the map, estimated from its capacity.
+    pub(crate) fn heap_bytes(&self) -> usize {
+        let entry = mem::size_of::<(u32, Box<[Option<u32>]>)>() + 1;
+        let bucket = self.config.num_entries as usize * mem::size_of::<Option<u32>>();
+        self.buckets.capacity() * entry + self.buckets.len() * bucket
+    }
+}
+
+impl Buckets {
+    /// Replaces sparse storage with the equivalent dense storage.
+    pub(crate) fn densify(&mut self) {
+        let sparse = match mem::replace(&mut self.slots, Slots::Dense(Box::new(U8Store::default()))) {
+            Slots::Sparse(sparse) => sparse,
+            dense => {
+                self.slots = dense;
+                return;
+            },
+        };
+        let SparseSlots { config, capacity, buckets, .. } = *sparse;
+        // Moving the entries over is not a write to roll back
+        let journal = self.journal.take();
+        *self = Buckets::dense(&config, capacity);
+        for (bucket, entries) in buckets {
+            let start = bucket as usize * config.num_entries as usize;
+            for (e, finger) in entries.iter().enumerate() {
+                if let Some(finger) = *finger {
+                    self.put(start + e, finger);
+                }
+            }
+        }
+        self.journal = journal;
+        #[cfg(feature = "tracing")]
+        tracing::info!(capacity = self.len(), "converted sparse buckets to dense");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::config;
+    use crate::{Config, Filter, Slots};
+
+    fn is_sparse(filter: &Filter) -> bool {
+        matches!(filter.buckets.borrow().slots, Slots::Sparse(_))
+    }
+
+    #[test]
+    fn membership_and_stats_hold_across_conversion() {
+        for &occupancy_bitmap in &[false, true] {
+            let c = Config { sparse_until: Some(0.25), occupancy_bitmap, ..config(1024) };
+            let filter = Filter::new(&c).unwrap();
+            let dense = Filter::new(&Config { sparse_until: None, ..c.clone() }).unwrap();
+            assert!(is_sparse(&filter));
+            for i in 0 .. 2000u64 {
+                filter.insert(&i).unwrap();
+                dense.insert(&i).unwrap();
+                assert_eq!(is_sparse(&filter), i < 1024);
+                if i % 97 == 0 {
+                    assert!((0 ..= i).all(|x| filter.contains(&x)));
+                    assert_eq!(filter.used(), i + 1);
+                    assert_eq!(filter.load_factor(), dense.load_factor());
+                    assert_eq!(filter.hottest_buckets(8), dense.hottest_buckets(8));
+                    assert_eq!(filter.to_bytes(), dense.to_bytes());
+                }
+            }
+            assert_eq!(filter.checksum(), dense.checksum());
+            assert_eq!(filter.checksum(), filter.recompute_checksum());
+        }
+    }
+
+    #[test]
+    fn deletes_free_sparse_buckets() {
+        let filter = Filter::new(&Config { sparse_until: Some(0.5), ..config(1024) }).unwrap();
+        let empty = filter.memory_usage();
+        for i in 0 .. 100 {
+            filter.insert(&i).unwrap();
+        }
+        assert!(filter.memory_usage() > empty);
+        for i in 0 .. 100 {
+            assert!(filter.delete(&i));
+        }
+        assert!(is_sparse(&filter));
+        assert_eq!(filter.used(), 0);
+        let buckets = filter.buckets.borrow();
+        match buckets.slots {
+            Slots::Sparse(ref sparse) => assert!(sparse.buckets.is_empty()),
+            Slots::Dense(_) => unreachable!(),
+        }
+    }
+}
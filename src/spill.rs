@@ -0,0 +1,112 @@
+//! A filter that degrades instead of rejecting inserts once it is full.
+//!
+//! A value whose insert fails is rolled back out of the table, so nothing
+//! is dropped, and its hash goes to a spill list that `contains` and
+//! `delete` scan after the table. The list is unbounded: lookups of values
+//! missing from the table slow down as it grows, but no inserted value is
+//! ever lost. Spilled hashes are matched exactly, so they add no false
+//! positives. `drain_spill` moves them back into the table once deletes
+//! have made room.
+
+use std::cell::RefCell;
+use std::hash::Hash;
+
+use crate::{Config, Filter};
+
+/// A `Filter` with an unbounded spill list behind it.
+#[derive(Debug)]
+pub struct SpillFilter {
+    filter: Filter,
+    spill: RefCell<Vec<u64>>,
+}
+
+impl SpillFilter {
+    pub fn new(config: &Config) -> Result<SpillFilter, ()> {
+        Ok(SpillFilter { filter: Filter::new(config)?, spill: RefCell::new(Vec::new()) })
+    }
+
+    /// Inserts `x` into the table, or appends it to the spill list if the
+    /// insert fails. Returns whether it went into the table.
+    pub fn insert<T: ?Sized + Hash>(&self, x: &T) -> bool {
+        let h = self.filter.hash_key(x);
+        let stored = self.filter.or_roll_back(|| self.filter.insert_slot(h, 0)).is_ok();
+        if !stored {
+            self.spill.borrow_mut().push(h);
+        }
+        stored
+    }
+
+    /// True if `x` may have been inserted, scanning the spill list if the
+    /// table does not have it.
+    pub fn contains<T: ?Sized + Hash>(&self, x: &T) -> bool {
+        let h = self.filter.hash_key(x);
+        self.filter.contains_hash(h) || self.spill.borrow().contains(&h)
+    }
+
+    /// Deletes one copy of `x`, from the spill list if it is there (an
+    /// exact match), or else from the table, returning whether one was
+    /// found. As with `Filter::delete`, `x` must have been inserted.
+    pub fn delete<T: ?Sized + Hash>(&self, x: &T) -> bool {
+        let h = self.filter.hash_key(x);
+        let mut spill = self.spill.borrow_mut();
+        match spill.iter().position(|&s| s == h) {
+            Some(i) => {
+                spill.swap_remove(i);
+                true
+            },
+            None => self.filter.delete_hash(h),
+        }
+    }
+
+    /// Retries inserting each spilled value into the table, keeping those
+    /// that still do not fit, and returns how many moved.
+    pub fn drain_spill(&self) -> usize {
+        let mut spill = self.spill.borrow_mut();
+        let before = spill.len();
+        spill.retain(|&h| self.filter.or_roll_back(|| self.filter.insert_slot(h, 0)).is_err());
+        before - spill.len()
+    }
+
+    /// Number of values in the spill list.
+    pub fn overflow_len(&self) -> usize {
+        self.spill.borrow().len()
+    }
+
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    /// Values held in the table and the spill list.
+    pub fn used(&self) -> u64 {
+        self.filter.used() + self.overflow_len() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpillFilter;
+    use crate::tests::config;
+
+    #[test]
+    fn twice_capacity_loses_nothing() {
+        let filter = SpillFilter::new(&config(1024)).unwrap();
+        let n = 2 * filter.filter().capacity();
+        let spilled = (0 .. n).filter(|i| !filter.insert(i)).count();
+        assert_eq!(filter.overflow_len(), spilled);
+        assert!(spilled as u64 >= n - filter.filter().capacity());
+        assert_eq!(filter.used(), n);
+        assert!((0 .. n).all(|i| filter.contains(&i)));
+
+        // Deleting half makes room for spilled values to move back
+        for i in 0 .. n / 2 {
+            assert!(filter.delete(&i));
+        }
+        assert!(filter.drain_spill() > 0);
+        assert_eq!(filter.used(), n / 2);
+        assert!((n / 2 .. n).all(|i| filter.contains(&i)));
+        for i in n / 2 .. n {
+            assert!(filter.delete(&i));
+        }
+        assert_eq!((filter.used(), filter.overflow_len()), (0, 0));
+    }
+}
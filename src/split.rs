@@ -0,0 +1,82 @@
+//! Splitting a filter into shards by bucket range, e.g. to spread it
+//! across machines.
+//!
+//! Each shard keeps the original geometry, so keys hash to the same
+//! fingerprints and buckets in every shard, and holds the fingerprints
+//! stored in its range of buckets, each in the same slot as before. That
+//! is the primary bucket of most of them; fingerprints kicked to an
+//! alternate bucket go to the shard owning that bucket, which may not own
+//! the value's primary bucket. So the shard owning a key's primary bucket
+//! answers for it exactly as the original filter only if all of the key's
+//! candidate buckets fall in that shard; in general a key must be looked
+//! up in the shards owning each of its candidate buckets, or in all of
+//! them. Taken together, the shards find exactly the keys the original
+//! finds, and `Filter::merge_many` puts them back together.
+//!
+//! Shards start out with sparse storage (see `Config::sparse_until`),
+//! allocating only their occupied buckets rather than the whole array
+//! each, though at several times the bytes per slot of dense storage. A
+//! shard converts to dense once it holds more fingerprints than its range
+//! has slots.
+
+use crate::{Config, Filter};
+
+impl Filter {
+    /// Splits the filter into `n` shards, shard `s` holding the
+    /// fingerprints in buckets `b` with `b * n / num_buckets == s`, a
+    /// contiguous range (see the module docs). Shards have no observer or
+    /// op log. Returns no shards if `n` is 0.
+    pub fn split(&self, n: u32) -> Vec<Filter> {
+        if n == 0 {
+            return Vec::new();
+        }
+        let share = 1.0 / n as f64;
+        let config = Config { sparse_until: Some(share), ..self.config() };
+        let shards: Vec<Filter> = (0 .. n)
+            .map(|_| Filter::new(&config).expect("the configuration of an existing filter"))
+            .collect();
+        let buckets = self.buckets.borrow();
+        for i in 0 .. buckets.len() {
+            if let Some(finger) = buckets.entry(i) {
+                let (bucket, _) = self.bucket_entry(i);
+                let shard = &shards[(bucket as u64 * n as u64 / self.num_buckets as u64) as usize];
+                shard.buckets.borrow_mut().put(i, finger);
+                shard.used.replace_with(|&mut x| x + 1);
+            }
+        }
+        shards
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::config;
+    use crate::Filter;
+
+    #[test]
+    fn shards_answer_like_the_original() {
+        let filter = Filter::new(&config(1024)).unwrap();
+        for i in 0 .. 2000u64 {
+            filter.insert(&i).unwrap();
+        }
+        let shards = filter.split(4);
+        assert_eq!(shards.len(), 4);
+        assert_eq!(shards.iter().map(Filter::used).sum::<u64>(), 2000);
+        let shard_of = |b: u32| (b as usize * 4) / 1024;
+        let mut local = 0;
+        for i in 0 .. 4000u64 {
+            let (_, candidates) = filter.candidates(filter.hash_key(&i));
+            let owner = shard_of(candidates[0]);
+            if candidates.iter().all(|&b| shard_of(b) == owner) {
+                assert_eq!(shards[owner].contains(&i), filter.contains(&i), "key {}", i);
+                local += 1;
+            }
+            let union = shards.iter().any(|shard| shard.contains(&i));
+            assert_eq!(union, filter.contains(&i), "key {}", i);
+        }
+        assert!(local > 500);
+        let merged = Filter::merge_many(&shards).unwrap();
+        assert!((0 .. 2000u64).all(|i| merged.contains(&i)));
+        assert!(filter.split(0).is_empty());
+    }
+}
@@ -0,0 +1,388 @@
+//! Dense slot storage, one `BucketStore` per fingerprint width.
+//!
+//! `Buckets` holds its dense slots as a `Box<dyn BucketStore>` picked by
+//! `store_for` from `Config::finger_bits`, and reads and writes single
+//! slots through the trait. Widths with a machine word of their own (8, 16
+//! and 32 bits) get a plain slice, which the insert and lookup hot paths
+//! reach directly through `FingerprintWord::slice`; any other width is bit
+//! packed. Supporting another layout takes one implementation and one
+//! entry in `store_for`.
+//!
+//! `PagedStore` wraps any of these in pages allocated on first write (see
+//! `Config::lazy_pages`).
+
+use std::any::Any;
+use std::fmt::Debug;
+use std::mem;
+
+use crate::hugepage;
+
+pub(crate) trait BucketStore: Any + Debug + Send + Sync {
+    /// Fingerprint at position `i`, widened.
+    fn get(&self, i: usize) -> u32;
+
+    /// Stores `finger`, which must fit in `entry_bits`, at position `i`.
+    fn set(&mut self, i: usize, finger: u32);
+
+    /// Number of positions.
+    fn len(&self) -> usize;
+
+    /// Bits stored per position.
+    fn entry_bits(&self) -> u8;
+
+    /// Sets every position to 0.
+    fn clear(&mut self);
+
+    /// Heap bytes held.
+    fn heap_bytes(&self) -> usize;
+
+    /// Asks the OS to back the storage with huge pages (see
+    /// `Config::huge_pages`).
+    fn advise_huge_pages(&mut self);
+
+    /// Overwrites the storage with zeros, in a way the compiler will not
+    /// elide.
+    #[cfg(feature = "zeroize")]
+    fn zeroize(&mut self);
+
+    fn clone_box(&self) -> Box<dyn BucketStore>;
+
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl Clone for Box<dyn BucketStore> {
+    fn clone(&self) -> Box<dyn BucketStore> {
+        self.clone_box()
+    }
+}
+
+/// Storage for `n` fingerprints of `bits` bits each, or None if no store
+/// holds that width.
+pub(crate) fn store_for(bits: u8, n: usize) -> Option<Box<dyn BucketStore>> {
+    match bits {
+        8 => Some(Box::new(U8Store(vec![0; n].into_boxed_slice()))),
+        16 => Some(Box::new(U16Store(vec![0; n].into_boxed_slice()))),
+        32 => Some(Box::new(U32Store(vec![0; n].into_boxed_slice()))),
+        1 ..= 31 => Some(Box::new(PackedStore::new(bits, n))),
+        _ => None,
+    }
+}
+
+/// Heap bytes of `store_for(bits, n)`, computed without allocating, or 0
+/// if no store holds that width.
+pub(crate) fn store_bytes(bits: u8, n: u64) -> u64 {
+    match bits {
+        8 | 16 | 32 => n * (bits / 8) as u64,
+        1 ..= 31 => packed_words(bits, n) * 8,
+        _ => 0,
+    }
+}
+
+macro_rules! word_store {
+    ($name:ident, $word:ty, $bits:expr) => {
+        #[derive(Clone, Debug, Default)]
+        pub(crate) struct $name(pub(crate) Box<[$word]>);
+
+        impl BucketStore for $name {
+            fn get(&self, i: usize) -> u32 {
+                self.0[i] as u32
+            }
+
+            fn set(&mut self, i: usize, finger: u32) {
+                self.0[i] = finger as $word;
+            }
+
+            fn len(&self) -> usize {
+                self.0.len()
+            }
+
+            fn entry_bits(&self) -> u8 {
+                $bits
+            }
+
+            fn clear(&mut self) {
+                self.0.iter_mut().for_each(|x| *x = 0);
+            }
+
+            fn heap_bytes(&self) -> usize {
+                mem::size_of_val(&self.0[..])
+            }
+
+            fn advise_huge_pages(&mut self) {
+                hugepage::advise(&mut self.0);
+            }
+
+            #[cfg(feature = "zeroize")]
+            fn zeroize(&mut self) {
+                zeroize::Zeroize::zeroize(&mut self.0[..]);
+            }
+
+            fn clone_box(&self) -> Box<dyn BucketStore> {
+                Box::new(self.clone())
+            }
+
+            fn as_any(&self) -> &dyn Any {
+                self
+            }
+
+            fn as_any_mut(&mut self) -> &mut dyn Any {
+                self
+            }
+        }
+    };
+}
+
+word_store!(U8Store, u8, 8);
+word_store!(U16Store, u16, 16);
+word_store!(U32Store, u32, 32);
+
+/// Fingerprints of any width below 32 bits, packed back to back into
+/// 64-bit words, so that e.g. 12-bit fingerprints take 12 bits each
+/// rather than 16.
+#[derive(Clone, Debug)]
+pub(crate) struct PackedStore {
+    bits: u8,
+    len: usize,
+    /// One spare word at the end, so that every entry's bits lie within
+    /// two consecutive words
+    words: Box<[u64]>,
+}
+
+/// Words of a `PackedStore` of `n` fingerprints of `bits` bits each.
+fn packed_words(bits: u8, n: u64) -> u64 {
+    (n * bits as u64).div_ceil(64) + 1
+}
+
+impl PackedStore {
+    fn new(bits: u8, len: usize) -> PackedStore {
+        let words = packed_words(bits, len as u64) as usize;
+        PackedStore { bits, len, words: vec![0; words].into_boxed_slice() }
+    }
+
+    fn mask(&self) -> u64 {
+        (1 << self.bits) - 1
+    }
+}
+
+impl BucketStore for PackedStore {
+    fn get(&self, i: usize) -> u32 {
+        let at = i * self.bits as usize;
+        let (word, shift) = (at / 64, at % 64);
+        let mut x = self.words[word] >> shift;
+        if shift + self.bits as usize > 64 {
+            x |= self.words[word + 1] << (64 - shift);
+        }
+        (x & self.mask()) as u32
+    }
+
+    fn set(&mut self, i: usize, finger: u32) {
+        let at = i * self.bits as usize;
+        let (word, shift) = (at / 64, at % 64);
+        let (mask, finger) = (self.mask(), finger as u64 & self.mask());
+        self.words[word] = self.words[word] & !(mask << shift) | finger << shift;
+        if shift + self.bits as usize > 64 {
+            let rest = 64 - shift;
+            self.words[word + 1] = self.words[word + 1] & !(mask >> rest) | finger >> rest;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn entry_bits(&self) -> u8 {
+        self.bits
+    }
+
+    fn clear(&mut self) {
+        self.words.iter_mut().for_each(|x| *x = 0);
+    }
+
+    fn heap_bytes(&self) -> usize {
+        mem::size_of_val(&self.words[..])
+    }
+
+    fn advise_huge_pages(&mut self) {
+        hugepage::advise(&mut self.words);
+    }
+
+    #[cfg(feature = "zeroize")]
+    fn zeroize(&mut self) {
+        zeroize::Zeroize::zeroize(&mut self.words[..]);
+    }
+
+    fn clone_box(&self) -> Box<dyn BucketStore> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Slots per page of a `PagedStore`: 4 KiB of 8-bit fingerprints.
+const PAGE_SLOTS: usize = 4096;
+
+/// Fingerprints in pages of `PAGE_SLOTS` slots, each allocated the first
+/// time a nonzero fingerprint is written to it. Pages never written read
+/// as 0, so a huge filter holding a handful of fingerprints only holds a
+/// handful of pages, at the cost of a page lookup on every access.
+#[derive(Clone, Debug)]
+pub(crate) struct PagedStore {
+    bits: u8,
+    len: usize,
+    pages: Box<[Option<Box<dyn BucketStore>>]>,
+}
+
+impl PagedStore {
+    /// Paged storage for `n` fingerprints of `bits` bits each, or None if
+    /// no store holds that width.
+    pub(crate) fn new(bits: u8, n: usize) -> Option<PagedStore> {
+        store_for(bits, 0)?;
+        let pages = (0 .. n.div_ceil(PAGE_SLOTS)).map(|_| None).collect();
+        Some(PagedStore { bits, len: n, pages })
+    }
+
+    /// Number of pages written to and not freed since.
+    pub(crate) fn allocated_pages(&self) -> usize {
+        self.pages.iter().flatten().count()
+    }
+}
+
+impl BucketStore for PagedStore {
+    fn get(&self, i: usize) -> u32 {
+        match self.pages[i / PAGE_SLOTS] {
+            Some(ref page) => page.get(i % PAGE_SLOTS),
+            None => 0,
+        }
+    }
+
+    fn set(&mut self, i: usize, finger: u32) {
+        let (bits, len) = (self.bits, self.len);
+        let page = &mut self.pages[i / PAGE_SLOTS];
+        if page.is_none() && finger == 0 {
+            return;
+        }
+        // The last page only covers the slots left
+        let page = page.get_or_insert_with(|| {
+            let start = i / PAGE_SLOTS * PAGE_SLOTS;
+            store_for(bits, PAGE_SLOTS.min(len - start)).unwrap()
+        });
+        page.set(i % PAGE_SLOTS, finger);
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn entry_bits(&self) -> u8 {
+        self.bits
+    }
+
+    /// Frees every page.
+    fn clear(&mut self) {
+        self.pages.iter_mut().for_each(|page| *page = None);
+    }
+
+    fn heap_bytes(&self) -> usize {
+        let pages = self.pages.iter().flatten().map(|page| mem::size_of_val(page) + page.heap_bytes());
+        mem::size_of_val(&self.pages[..]) + pages.sum::<usize>()
+    }
+
+    /// Pages are too small to benefit.
+    fn advise_huge_pages(&mut self) {}
+
+    #[cfg(feature = "zeroize")]
+    fn zeroize(&mut self) {
+        for page in self.pages.iter_mut().flatten() {
+            page.zeroize();
+        }
+        self.clear();
+    }
+
+    fn clone_box(&self) -> Box<dyn BucketStore> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::tests::{check_membership, config};
+    use crate::{Config, Filter};
+
+    /// Writes random fingerprints to `store` and checks every position
+    /// reads back what was written last, neighbors included.
+    fn check_store(mut store: Box<dyn BucketStore>, bits: u8) {
+        let n = store.len();
+        assert_eq!(store.entry_bits(), bits);
+        let mask = (u64::MAX >> (64 - bits)) as u32;
+        let mut model = vec![0u32; n];
+        let mut rng = StdRng::seed_from_u64(bits as u64);
+        for _ in 0 .. 4 * n {
+            let (i, finger) = (rng.gen_range(0, n), rng.gen::<u32>() & mask);
+            store.set(i, finger);
+            model[i] = finger;
+        }
+        store.set(n - 1, mask);
+        model[n - 1] = mask;
+        assert!((0 .. n).all(|i| store.get(i) == model[i]), "{} bits", bits);
+        let copy = store.clone();
+        store.clear();
+        assert!((0 .. n).all(|i| store.get(i) == 0));
+        assert!((0 .. n).all(|i| copy.get(i) == model[i]));
+    }
+
+    #[test]
+    fn every_width_stores_what_is_set() {
+        assert!(store_for(0, 8).is_none() && store_for(33, 8).is_none());
+        for bits in 1 ..= 32 {
+            let store = store_for(bits, 1000).unwrap();
+            assert_eq!(store.len(), 1000);
+            assert_eq!(store.heap_bytes() as u64, store_bytes(bits, 1000));
+            check_store(store, bits);
+            check_store(Box::new(PagedStore::new(bits, 3 * PAGE_SLOTS + 5).unwrap()), bits);
+        }
+    }
+
+    #[test]
+    fn huge_lazy_filter_allocates_few_pages() {
+        let filter = Filter::new(&Config { lazy_pages: true, ..config(1 << 24) }).unwrap();
+        assert_eq!(filter.allocated_pages(), Some(0));
+        for i in 0 .. 10u64 {
+            filter.insert(&i).unwrap();
+        }
+        // One page per key at most, out of 16384
+        let pages = filter.allocated_pages().unwrap();
+        assert!(pages > 0 && pages <= 10, "{}", pages);
+        assert!(filter.memory_usage() < 1 << 20);
+        assert!((0 .. 10u64).all(|i| filter.contains(&i)));
+        filter.clear();
+        assert_eq!(filter.allocated_pages(), Some(0));
+        assert_eq!(Filter::new(&config(64)).unwrap().allocated_pages(), None);
+    }
+
+    #[test]
+    fn hot_paths_per_width() {
+        for &finger_bits in &[8, 12, 16, 20, 32] {
+            check_membership(&Filter::new(&Config { finger_bits, ..config(1024) }).unwrap(), 1500);
+        }
+    }
+}
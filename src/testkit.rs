@@ -0,0 +1,444 @@
+//! Helpers for property-testing code that embeds a filter, behind the
+//! `testkit` feature.
+//!
+//! `random_ops` and `DeterministicFilter` are both driven by a seed, so a
+//! failure reported by `check_consistency` replays exactly from the seed
+//! and config alone. `audit_deletes` hunts for deletes that make other keys
+//! false negatives, and reports the ops that replay any it finds.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{expected_max_load, Candidates, Config, Filter};
+
+/// A filter whose kicks make the same random choices on every run.
+#[derive(Debug)]
+pub struct DeterministicFilter {
+    filter: Filter,
+}
+
+impl DeterministicFilter {
+    /// Builds a filter from `config` with its seed replaced by `seed`.
+    pub fn new(seed: u64, config: &Config) -> Result<DeterministicFilter, ()> {
+        let config = Config { seed: Some(seed), ..config.clone() };
+        Ok(DeterministicFilter { filter: Filter::new(&config)? })
+    }
+
+    pub fn into_inner(self) -> Filter {
+        self.filter
+    }
+}
+
+impl Deref for DeterministicFilter {
+    type Target = Filter;
+
+    fn deref(&self) -> &Filter {
+        &self.filter
+    }
+}
+
+/// An operation on a set of `u64` keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Insert(u64),
+    Delete(u64),
+    Contains(u64),
+}
+
+/// Exact multiset of keys, the reference a filter is checked against.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModelSet {
+    counts: HashMap<u64, u32>,
+}
+
+impl ModelSet {
+    pub fn new() -> ModelSet {
+        ModelSet::default()
+    }
+
+    pub fn insert(&mut self, key: u64) {
+        *self.counts.entry(key).or_insert(0) += 1;
+    }
+
+    /// Removes one copy of `key`, returning whether there was one.
+    pub fn delete(&mut self, key: u64) -> bool {
+        match self.counts.get_mut(&key) {
+            Some(n) if *n > 1 => *n -= 1,
+            Some(_) => {
+                self.counts.remove(&key);
+            },
+            None => return false,
+        }
+        true
+    }
+
+    pub fn contains(&self, key: u64) -> bool {
+        self.counts.contains_key(&key)
+    }
+}
+
+/// `n` random ops over keys below `key_space`: roughly half lookups, a
+/// third inserts and the rest deletes.
+pub fn random_ops(seed: u64, n: usize, key_space: u64) -> Vec<Op> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0 .. n)
+        .map(|_| {
+            let key = rng.gen_range(0, key_space);
+            match rng.gen_range(0, 6) {
+                0 ..= 2 => Op::Contains(key),
+                3 | 4 => Op::Insert(key),
+                _ => Op::Delete(key),
+            }
+        })
+        .collect()
+}
+
+/// Outcome of a consistent `check_consistency` run.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConsistencyReport {
+    /// Lookups of keys not in the model
+    pub negative_lookups: u64,
+
+    /// Those the filter reported as present
+    pub false_positives: u64,
+
+    /// Expected false positives, summed over the lookups (see
+    /// `check_consistency`)
+    pub expected_false_positives: f64,
+
+    /// Deletes skipped because the model did not hold the key (deleting
+    /// absent values is not allowed)
+    pub skipped_deletes: u64,
+
+    /// Position of the first insert that failed, after which the ops were
+    /// not applied (a failed insert drops a fingerprint)
+    pub full_at: Option<usize>,
+}
+
+/// Ways a filter can disagree with the model.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Violation {
+    /// The key looked up by the op at this position was in the model but
+    /// not found
+    FalseNegative { op: usize, key: u64 },
+    /// More false positives than `expected` plus six standard deviations
+    TooManyFalsePositives { observed: u64, expected: f64 },
+}
+
+/// Applies `ops` to a `DeterministicFilter` built from `seed` and `config`
+/// and to a `ModelSet`, checking that the filter never misses a key of the
+/// model and that its false positives stay within a statistical bound.
+///
+/// The expected false positive rate of a lookup is `estimated_fpp` plus
+/// the share of fingerprint values stored in their alternate bucket:
+/// alternate buckets depend only on the fingerprint, so such a fingerprint
+/// matches every key that shares it, whatever its primary bucket.
+pub fn check_consistency(seed: u64, config: &Config, ops: &[Op]) -> Result<ConsistencyReport, Violation> {
+    let filter = DeterministicFilter::new(seed, config).expect("invalid config");
+    let mut model = ModelSet::new();
+    let mut report = ConsistencyReport::default();
+    let mut fpp = None;
+    for (i, op) in ops.iter().enumerate() {
+        match *op {
+            Op::Insert(key) => {
+                if filter.insert(&key).is_err() {
+                    report.full_at = Some(i);
+                    break;
+                }
+                model.insert(key);
+                fpp = None;
+            },
+            Op::Delete(key) => {
+                if model.delete(key) {
+                    filter.delete(&key);
+                    fpp = None;
+                } else {
+                    report.skipped_deletes += 1;
+                }
+            },
+            Op::Contains(key) => {
+                let found = filter.contains(&key);
+                if model.contains(key) {
+                    if !found {
+                        return Err(Violation::FalseNegative { op: i, key });
+                    }
+                } else {
+                    report.negative_lookups += 1;
+                    report.false_positives += found as u64;
+                    report.expected_false_positives += *fpp.get_or_insert_with(|| expected_fpp(&filter));
+                }
+            },
+        }
+    }
+    // Each lookup is a Bernoulli trial, so the count's variance is below
+    // its mean; 1 absorbs tiny expectations
+    let expected = report.expected_false_positives;
+    if report.false_positives as f64 > expected + 6.0 * expected.sqrt() + 1.0 {
+        return Err(Violation::TooManyFalsePositives { observed: report.false_positives, expected });
+    }
+    Ok(report)
+}
+
+fn expected_fpp(filter: &Filter) -> f64 {
+    let mut alternate = HashSet::new();
+    let mask = filter.key_mask();
+    filter.for_each_placement(|finger, primary| {
+        if primary.is_none() {
+            alternate.insert(finger & mask);
+        }
+    });
+    filter.estimated_fpp() + alternate.len() as f64 / filter.finger_values() as f64
+}
+
+/// Outcome of a clean `audit_deletes` or `audit_ops` run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeleteAuditReport {
+    pub inserts: u64,
+
+    /// Deletes of keys the model held, each followed by lookups of every
+    /// other held key sharing the bucket it emptied
+    pub deletes: u64,
+
+    /// Lookups made after deletes
+    pub checked: u64,
+
+    /// Deletes not applied because the model did not hold the key
+    /// (deleting absent values is not allowed)
+    pub absent_deletes: u64,
+
+    /// Those the filter reported as present, which would have removed
+    /// another key's fingerprint had they been applied
+    pub unsafe_absent_deletes: u64,
+
+    /// Inserts that failed, rolled back (see `Filter::checkpoint`) so
+    /// that they drop no fingerprint
+    pub failed_inserts: u64,
+}
+
+/// A key of the model that a delete made the filter miss.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeleteViolation {
+    /// Position of the delete
+    pub op: usize,
+    pub deleted: u64,
+    pub missing: u64,
+    /// The ops up to and including the delete, which `audit_ops` replays
+    /// with the same seed and config
+    pub ops: Vec<Op>,
+}
+
+/// Applies `n` random inserts and deletes to a `DeterministicFilter` built
+/// from `seed` and `config` and to a `ModelSet`, checking after every
+/// delete that the filter still finds each key of the model that could
+/// have lost its fingerprint.
+///
+/// Keys are drawn below twice the filter's capacity, so deletes hit both
+/// held and absent keys and some keys are held more than once. Inserts
+/// give way to deletes of held keys once the load nears the point where
+/// inserts start failing.
+pub fn audit_deletes(seed: u64, config: &Config, n: usize) -> Result<DeleteAuditReport, DeleteViolation> {
+    let mut audit = DeleteAudit::new(seed, config);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let key_space = 2 * audit.filter.capacity();
+    let target_load = 0.9 * expected_max_load(audit.filter.num_entries);
+    for i in 0 .. n {
+        let op = match rng.gen_range(0, 7) {
+            0 ..= 3 if audit.filter.load_factor() < target_load => Op::Insert(rng.gen_range(0, key_space)),
+            6 => Op::Delete(rng.gen_range(0, key_space)),
+            _ if !audit.held.is_empty() => Op::Delete(audit.held[rng.gen_range(0, audit.held.len())]),
+            _ => Op::Insert(rng.gen_range(0, key_space)),
+        };
+        audit.ops.push(op);
+        audit.apply(i, op)?;
+    }
+    Ok(audit.report)
+}
+
+/// `audit_deletes` over the given ops, such as those of a
+/// `DeleteViolation`. Lookups are not checked.
+pub fn audit_ops(seed: u64, config: &Config, ops: &[Op]) -> Result<DeleteAuditReport, DeleteViolation> {
+    let mut audit = DeleteAudit::new(seed, config);
+    for (i, &op) in ops.iter().enumerate() {
+        audit.ops.push(op);
+        audit.apply(i, op)?;
+    }
+    Ok(audit.report)
+}
+
+struct DeleteAudit {
+    filter: DeterministicFilter,
+    model: ModelSet,
+    /// Keys of the model, once per copy, to draw deletes from
+    held: Vec<u64>,
+    /// Positions of each key in `held`
+    positions: HashMap<u64, Vec<usize>>,
+    /// Keys of the model by candidate bucket
+    by_bucket: HashMap<u32, HashSet<u64>>,
+    ops: Vec<Op>,
+    report: DeleteAuditReport,
+}
+
+impl DeleteAudit {
+    fn new(seed: u64, config: &Config) -> DeleteAudit {
+        DeleteAudit {
+            filter: DeterministicFilter::new(seed, config).expect("invalid config"),
+            model: ModelSet::new(),
+            held: Vec::new(),
+            positions: HashMap::new(),
+            by_bucket: HashMap::new(),
+            ops: Vec::new(),
+            report: DeleteAuditReport::default(),
+        }
+    }
+
+    /// Applies the op at position `i`.
+    fn apply(&mut self, i: usize, op: Op) -> Result<(), DeleteViolation> {
+        match op {
+            Op::Insert(key) => {
+                let checkpoint = self.filter.checkpoint().expect("checkpoint open");
+                if self.filter.insert(&key).is_err() {
+                    self.filter.restore(checkpoint).expect("checkpoint closed");
+                    self.report.failed_inserts += 1;
+                    return Ok(());
+                }
+                self.filter.discard(checkpoint).expect("checkpoint closed");
+                self.report.inserts += 1;
+                if !self.model.contains(key) {
+                    for &bucket in self.candidates(key).iter() {
+                        self.by_bucket.entry(bucket).or_default().insert(key);
+                    }
+                }
+                self.model.insert(key);
+                self.positions.entry(key).or_default().push(self.held.len());
+                self.held.push(key);
+            },
+            Op::Delete(key) => {
+                if !self.model.delete(key) {
+                    self.report.absent_deletes += 1;
+                    self.report.unsafe_absent_deletes += self.filter.contains(&key) as u64;
+                    return Ok(());
+                }
+                self.report.deletes += 1;
+                self.release(key);
+                if !self.model.contains(key) {
+                    for bucket in self.candidates(key).iter() {
+                        self.by_bucket.get_mut(bucket).map(|keys| keys.remove(&key));
+                    }
+                }
+                // Only keys with the emptied bucket among their candidates
+                // can have lost their fingerprint
+                let (bucket, _) = self.filter.locate(&key).expect("held key not found");
+                self.filter.delete(&key);
+                for &other in self.by_bucket.get(&bucket).into_iter().flatten() {
+                    self.report.checked += 1;
+                    if !self.filter.contains(&other) {
+                        return Err(DeleteViolation { op: i, deleted: key, missing: other, ops: self.ops.clone() });
+                    }
+                }
+            },
+            Op::Contains(_) => {},
+        }
+        Ok(())
+    }
+
+    /// Removes one copy of `key` from `held`.
+    fn release(&mut self, key: u64) {
+        let at = self.positions.get_mut(&key).and_then(Vec::pop).expect("held key");
+        let last = self.held.len() - 1;
+        self.held.swap_remove(at);
+        if at != last {
+            let moved = self.positions.get_mut(&self.held[at]).expect("held key");
+            let i = moved.iter().position(|&p| p == last).expect("held key");
+            moved[i] = at;
+        }
+        if self.positions[&key].is_empty() {
+            self.positions.remove(&key);
+        }
+    }
+
+    fn candidates(&self, key: u64) -> Candidates {
+        self.filter.candidates(self.filter.hash_key(&key)).1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn apply(filter: &Filter, ops: &[Op]) {
+        for op in ops {
+            match *op {
+                Op::Insert(key) => {
+                    let _ = filter.insert(&key);
+                },
+                Op::Delete(key) => {
+                    filter.delete(&key);
+                },
+                Op::Contains(_) => {},
+            }
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn filter_agrees_with_model(seed: u64, finger_bits in prop::sample::select(vec![8u8, 12, 16, 32])) {
+            let config = Config { finger_bits, num_buckets: 256, ..Config::default() };
+            let ops = random_ops(seed, 2000, 1000);
+            let report = check_consistency(seed, &config, &ops);
+            prop_assert!(report.is_ok(), "seed {}: {:?}", seed, report);
+        }
+
+        #[test]
+        fn seed_replays_byte_for_byte(seed: u64) {
+            let config = Config { num_buckets: 64, ..Config::default() };
+            let ops = random_ops(seed, 1000, 400);
+            let a = DeterministicFilter::new(seed, &config).unwrap();
+            let b = DeterministicFilter::new(seed, &config).unwrap();
+            apply(&a, &ops);
+            apply(&b, &ops);
+            prop_assert_eq!(a.to_bytes(), b.to_bytes());
+        }
+    }
+
+    #[test]
+    fn model_counts_copies() {
+        let mut model = ModelSet::new();
+        model.insert(1);
+        model.insert(1);
+        assert!(model.delete(1));
+        assert!(model.contains(1));
+        assert!(model.delete(1));
+        assert!(!model.contains(1));
+        assert!(!model.delete(1));
+    }
+
+    #[test]
+    fn delete_audit_runs_clean() {
+        for &(finger_bits, num_entries, n) in &[(16, 4, 1_000_000), (8, 4, 200_000), (12, 2, 200_000)] {
+            let config = Config { finger_bits, num_buckets: 64, num_entries, ..Config::default() };
+            let report = audit_deletes(1, &config, n)
+                .unwrap_or_else(|v| panic!("{} bits: key {} missing after deleting {} at op {}", finger_bits, v.missing, v.deleted, v.op));
+            assert_eq!(report.inserts + report.failed_inserts + report.deletes + report.absent_deletes, n as u64);
+            assert!(report.deletes > n as u64 / 10 && report.checked > report.deletes, "{:?}", report);
+            assert!(report.absent_deletes > 0 && report.unsafe_absent_deletes <= report.absent_deletes);
+        }
+    }
+
+    #[test]
+    fn delete_audit_replays_ops() {
+        let config = Config { num_buckets: 16, ..Config::default() };
+        let ops: Vec<Op> = (0 .. 40).map(Op::Insert).chain((0 .. 40).step_by(2).map(Op::Delete)).collect();
+        let report = audit_ops(3, &config, &ops).unwrap();
+        assert_eq!((report.inserts, report.deletes, report.absent_deletes), (40, 20, 0));
+        assert_eq!(audit_ops(3, &config, &ops), Ok(report));
+        let absent = audit_ops(3, &config, &[Op::Insert(1), Op::Delete(2), Op::Delete(1)]).unwrap();
+        assert_eq!((absent.deletes, absent.absent_deletes), (1, 1));
+    }
+}
@@ -0,0 +1,134 @@
+//! Vectored views of `Filter::to_bytes`, for sending large filters to
+//! replicas without first copying them into one buffer.
+//!
+//! The byte format is row-major and little-endian, so a dense row-major
+//! filter on a little-endian host can lend its bucket array as is. Other
+//! filters fall back to a copy of the payload.
+
+use std::cell::Ref;
+use std::io::IoSlice;
+use std::{mem, slice};
+
+use crate::serialize::{fnv1a, fnv1a_update, CHECKSUM_LEN, HEADER_LEN};
+use crate::word::FingerprintWord;
+use crate::{Buckets, Filter, Layout};
+
+/// `to_bytes` output as borrowed slices, returned by
+/// `Filter::as_transmit_frame`. Holds a borrow of the buckets, so the
+/// filter cannot be modified while the frame is alive.
+#[derive(Debug)]
+pub struct TransmitFrame<'a> {
+    header: [u8; HEADER_LEN],
+    buckets: Ref<'a, Buckets>,
+    /// Payload converted to the byte format, if the buckets cannot be lent
+    copied: Option<Vec<u8>>,
+    checksum: [u8; CHECKSUM_LEN],
+}
+
+impl Filter {
+    /// Returns the serialized filter as a frame whose `io_slices`, written
+    /// in order (e.g. with `Write::write_vectored`), equal `to_bytes`.
+    pub fn as_transmit_frame(&self) -> TransmitFrame<'_> {
+        let mut header = [0u8; HEADER_LEN];
+        header[.. HEADER_LEN - 8].copy_from_slice(&self.header_bytes());
+        header[HEADER_LEN - 8 ..].copy_from_slice(&self.used().to_le_bytes());
+        let buckets = self.buckets.borrow();
+        let copied = if lend(&buckets).is_some() {
+            None
+        } else {
+            let mut payload = Vec::new();
+            self.write_payload(&buckets, &mut payload);
+            Some(payload)
+        };
+        let mut frame = TransmitFrame { header, buckets, copied, checksum: [0; CHECKSUM_LEN] };
+        let checksum = frame.payload().iter().fold(fnv1a(&frame.header), |h, part| fnv1a_update(h, part));
+        frame.checksum = checksum.to_le_bytes();
+        frame
+    }
+}
+
+impl TransmitFrame<'_> {
+    /// The header, bucket array, occupancy bitmap (if any) and checksum.
+    pub fn io_slices(&self) -> Vec<IoSlice<'_>> {
+        let mut slices = vec![IoSlice::new(&self.header)];
+        slices.extend(self.payload().iter().filter(|part| !part.is_empty()).map(|part| IoSlice::new(part)));
+        slices.push(IoSlice::new(&self.checksum));
+        slices
+    }
+
+    /// Total length of the slices, the same as `to_bytes().len()`.
+    pub fn len(&self) -> usize {
+        HEADER_LEN + self.payload().iter().map(|part| part.len()).sum::<usize>() + CHECKSUM_LEN
+    }
+
+    /// Always false: a frame has at least a header.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    fn payload(&self) -> [&[u8]; 2] {
+        match self.copied {
+            Some(ref payload) => [payload, &[]],
+            None => lend(&self.buckets).unwrap(),
+        }
+    }
+}
+
+/// The fingerprints and occupancy bitmap in the byte format, if the
+/// buckets already store them that way.
+fn lend(buckets: &Buckets) -> Option<[&[u8]; 2]> {
+    if buckets.layout != Layout::RowMajor || cfg!(target_endian = "big") {
+        return None;
+    }
+    let fingers = if let Some(vec) = u8::slice(&buckets.slots) {
+        vec
+    } else if let Some(vec) = u16::slice(&buckets.slots) {
+        u16_bytes(vec)
+    } else {
+        // Sparse, 32-bit or packed, which the format stores otherwise
+        return None;
+    };
+    let bitmap = buckets.occupied.as_ref().map_or(&[][..], |bits| u64_bytes(bits));
+    Some([fingers, bitmap])
+}
+
+fn u16_bytes(words: &[u16]) -> &[u8] {
+    // SAFETY: u16 has no padding or invalid byte patterns, and u8 has the
+    // weakest alignment
+    unsafe { slice::from_raw_parts(words.as_ptr() as *const u8, mem::size_of_val(words)) }
+}
+
+fn u64_bytes(words: &[u64]) -> &[u8] {
+    // SAFETY: as for `u16_bytes`
+    unsafe { slice::from_raw_parts(words.as_ptr() as *const u8, mem::size_of_val(words)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::config;
+    use crate::{Config, Filter, Layout};
+
+    #[test]
+    fn frame_concatenates_to_bytes() {
+        let configs = [
+            (config(256), true),
+            (Config { finger_bits: 8, occupancy_bitmap: true, ..config(256) }, true),
+            (Config { finger_bits: 12, ..config(256) }, false),
+            (Config { finger_bits: 32, ..config(256) }, false),
+            (Config { layout: Layout::ColumnMajor, ..config(256) }, false),
+            (Config { sparse_until: Some(0.9), ..config(256) }, false),
+        ];
+        for (c, lent) in &configs {
+            let filter = Filter::new(c).unwrap();
+            for i in 0 .. 500u64 {
+                filter.insert(&i).unwrap();
+            }
+            let bytes = filter.to_bytes();
+            let frame = filter.as_transmit_frame();
+            let joined: Vec<u8> = frame.io_slices().iter().flat_map(|slice| slice.iter().cloned()).collect();
+            assert_eq!(joined, bytes, "{:?}", c);
+            assert_eq!(frame.len(), bytes.len());
+            assert_eq!(frame.copied.is_none(), *lent && cfg!(target_endian = "little"));
+        }
+    }
+}
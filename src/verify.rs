@@ -0,0 +1,225 @@
+//! Ground-truth comparison of a filter against known members and
+//! non-members.
+
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use crate::Filter;
+
+/// Result of `Filter::verify_against`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerifyReport {
+    /// Number of member values checked
+    pub members: u64,
+
+    /// Positions (in iteration order) of members the filter does not
+    /// contain; must be empty for a correct filter
+    pub false_negatives: Vec<usize>,
+
+    /// Number of non-member values probed
+    pub non_members: u64,
+
+    /// Number of non-members the filter reports as present
+    pub false_positives: u64,
+
+    /// `false_positives / non_members`, or 0 without probes
+    pub fp_rate: f64,
+}
+
+impl VerifyReport {
+    /// True if no member was missing.
+    pub fn is_sound(&self) -> bool {
+        self.false_negatives.is_empty()
+    }
+}
+
+/// Standard deviations above `FppReport::expected` at which
+/// `FppReport::is_inflated` holds.
+const INFLATED_Z_SCORE: f64 = 3.0;
+
+/// Result of `Filter::fpp_report`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FppReport {
+    /// Number of absent values probed
+    pub probes: u64,
+
+    /// Number of probes the filter reports as present
+    pub false_positives: u64,
+
+    /// `false_positives / probes`, or 0 without probes
+    pub observed: f64,
+
+    /// `Filter::estimated_fpp` when the report was made
+    pub estimated: f64,
+
+    /// Rate uniformly hashed probes would see given the fingerprints
+    /// stored. Alternate buckets depend only on the fingerprint, so a
+    /// probe always finds a copy of its fingerprint sitting in one of
+    /// those; this rate counts them, and is usually well above
+    /// `estimated`, which treats every candidate entry as independent.
+    pub expected: f64,
+
+    /// Standard deviations by which `false_positives` exceeds the count
+    /// `expected` predicts for this many probes; infinite if it predicts
+    /// none and there were some
+    pub z_score: f64,
+}
+
+impl FppReport {
+    /// True if the observed rate exceeds `expected` by more than chance
+    /// explains, which points at a weak hash.
+    pub fn is_inflated(&self) -> bool {
+        self.z_score > INFLATED_Z_SCORE
+    }
+}
+
+impl Filter {
+    /// Checks the filter against an exact set: every value of `truth` must
+    /// be found, and `non_members` (values known to be absent) measure the
+    /// empirical false positive rate. Does not count towards `query_stats`.
+    pub fn verify_against<'a, T: ?Sized + Hash + 'a>(
+        &self,
+        truth: impl IntoIterator<Item = &'a T>,
+        non_members: impl IntoIterator<Item = &'a T>,
+    ) -> VerifyReport {
+        let mut report = VerifyReport::default();
+        for (i, x) in truth.into_iter().enumerate() {
+            report.members += 1;
+            if self.find(self.hash_key(x)).is_none() {
+                report.false_negatives.push(i);
+            }
+        }
+        for x in non_members {
+            report.non_members += 1;
+            if self.find(self.hash_key(x)).is_some() {
+                report.false_positives += 1;
+            }
+        }
+        if report.non_members > 0 {
+            report.fp_rate = report.false_positives as f64 / report.non_members as f64;
+        }
+        report
+    }
+
+    /// Measures the false positive rate over `absent_probes`, values known
+    /// not to have been inserted, and compares it with `estimated_fpp` and
+    /// with the rate the stored fingerprints lead to expect. Does not count
+    /// towards `query_stats`.
+    pub fn fpp_report<T: Hash>(&self, absent_probes: &[T]) -> FppReport {
+        let probes = absent_probes.len() as u64;
+        let false_positives = absent_probes.iter()
+            .filter(|x| self.find(self.hash_key(*x)).is_some())
+            .count() as u64;
+        let expected = self.expected_fpp();
+        let mean = probes as f64 * expected;
+        let excess = false_positives as f64 - mean;
+        let z_score = match (mean * (1.0 - expected)).sqrt() {
+            sd if sd > 0.0 => excess / sd,
+            _ if excess > 0.0 => f64::INFINITY,
+            _ => 0.0,
+        };
+        FppReport {
+            probes,
+            false_positives,
+            observed: if probes > 0 { false_positives as f64 / probes as f64 } else { 0.0 },
+            estimated: self.estimated_fpp(),
+            expected,
+            z_score,
+        }
+    }
+
+    /// `FppReport::expected`: a probe's fingerprint is uniform over the
+    /// fingerprint values and its primary bucket uniform over the buckets,
+    /// so it matches there with the chance that bucket holds its
+    /// fingerprint, and in its alternates if its fingerprint is stored in
+    /// any of them.
+    fn expected_fpp(&self) -> f64 {
+        let buckets = self.buckets.borrow();
+        let entries = self.num_entries as usize;
+        let mask = self.key_mask();
+        // Distinct fingerprints per bucket, summed
+        let mut distinct = 0;
+        let mut in_alternate = HashSet::new();
+        let mut seen = Vec::with_capacity(entries);
+        for bucket in 0 .. self.num_buckets {
+            seen.clear();
+            let start = bucket as usize * entries;
+            for finger in (start .. start + entries).filter_map(|i| buckets.entry(i)) {
+                let finger = finger & mask;
+                if !seen.contains(&finger) {
+                    seen.push(finger);
+                    if self.alternates(finger).contains(&bucket) {
+                        in_alternate.insert(finger);
+                    }
+                }
+            }
+            distinct += seen.len() as u64;
+        }
+        let values = self.finger_values() as f64;
+        let primary = distinct as f64 / (self.num_buckets as f64 * values);
+        (primary + in_alternate.len() as f64 / values).min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{Hash, Hasher};
+
+    use crate::tests::config;
+    use crate::Filter;
+
+    #[test]
+    fn verify_against_catches_injected_false_negative() {
+        let filter = Filter::new(&config(512)).unwrap();
+        let members: Vec<u64> = (0 .. 1000).collect();
+        let non_members: Vec<u64> = (1000 .. 11_000).collect();
+        for x in &members {
+            filter.insert(x).unwrap();
+        }
+        let report = filter.verify_against(&members, &non_members);
+        assert!(report.is_sound());
+        assert_eq!((report.members, report.non_members), (1000, 10_000));
+        assert_eq!(report.fp_rate, report.false_positives as f64 / 10_000.0);
+        assert!(report.fp_rate < 0.01);
+
+        // Clear the slot of a member behind the filter's back, as a bug
+        // losing a fingerprint would
+        let (bucket, entry) = filter.locate(&500u64).unwrap();
+        filter.clear_slot(filter.slot(bucket, entry));
+        let report = filter.verify_against(&members, &non_members);
+        assert!(!report.is_sound());
+        assert_eq!(report.false_negatives, vec![500]);
+    }
+
+    /// Key whose hash ignores its low 8 bits.
+    struct Coarse(u64);
+
+    impl Hash for Coarse {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            (self.0 >> 8).hash(state);
+        }
+    }
+
+    #[test]
+    fn fpp_report_flags_weak_hash() {
+        let filter = Filter::new(&config(1024)).unwrap();
+        for i in 0 .. 1500u64 {
+            filter.insert(&(i << 8)).unwrap();
+        }
+        let uniform = filter.fpp_report(&(1 << 20 .. (1 << 20) + 20_000u64).collect::<Vec<_>>());
+        assert_eq!(uniform.probes, 20_000);
+        assert!(!uniform.is_inflated(), "{:?}", uniform);
+        assert!(uniform.observed < 2.0 * uniform.expected && uniform.expected > uniform.estimated, "{:?}", uniform);
+
+        let weak = Filter::new(&config(1024)).unwrap();
+        for i in 0 .. 1500u64 {
+            weak.insert(&Coarse(i << 8)).unwrap();
+        }
+        // Absent, but sharing a hash with a member when i < 1500
+        let probes: Vec<Coarse> = (0 .. 20_000u64).map(|i| Coarse(i << 8 | 1)).collect();
+        let report = weak.fpp_report(&probes);
+        assert!(report.is_inflated(), "{:?}", report);
+        assert!(report.false_positives >= 1500 && report.observed > 10.0 * report.expected, "{:?}", report);
+        assert_eq!(Filter::new(&config(1024)).unwrap().fpp_report::<u64>(&[]).z_score, 0.0);
+    }
+}
@@ -0,0 +1,166 @@
+//! Fingerprint widths. Insert, lookup and kick logic is written once over
+//! `FingerprintWord`; `Filter` picks the word once per public call, the
+//! narrowest holding `finger_bits`.
+
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use crate::store::{U16Store, U32Store, U8Store};
+use crate::Slots;
+
+/// Unsigned integer a fingerprint is stored in. Alternate buckets hash the
+/// fingerprint as this type, so the width is part of the hashing scheme.
+pub(crate) trait FingerprintWord: Copy + Eq + Hash + Debug {
+    /// Truncates `x` to this width.
+    fn from_u64(x: u64) -> Self;
+
+    fn widen(self) -> u32;
+
+    /// Dense slots of this width, if that is what `slots` holds.
+    fn slice(slots: &Slots) -> Option<&[Self]>;
+
+    fn slice_mut(slots: &mut Slots) -> Option<&mut [Self]>;
+
+    /// Fingerprint taken from hash bits `x`, at most `max` (which is one
+    /// less than a power of two), leaving the bits above clear for a
+    /// generation tag. Unless `zero_allowed` (with an occupancy bitmap), 0
+    /// is skipped because it marks empty slots.
+    fn from_hash(x: u64, zero_allowed: bool, max: u64) -> Self {
+        if zero_allowed {
+            Self::from_u64(x & max)
+        } else {
+            Self::from_u64(x % max + 1)
+        }
+    }
+}
+
+impl FingerprintWord for u8 {
+    fn from_u64(x: u64) -> u8 {
+        x as u8
+    }
+
+    fn widen(self) -> u32 {
+        self as u32
+    }
+
+    fn slice(slots: &Slots) -> Option<&[u8]> {
+        match *slots {
+            Slots::Dense(ref store) => store.as_any().downcast_ref::<U8Store>().map(|s| &s.0[..]),
+            _ => None,
+        }
+    }
+
+    fn slice_mut(slots: &mut Slots) -> Option<&mut [u8]> {
+        match *slots {
+            Slots::Dense(ref mut store) => store.as_any_mut().downcast_mut::<U8Store>().map(|s| &mut s.0[..]),
+            _ => None,
+        }
+    }
+}
+
+impl FingerprintWord for u16 {
+    fn from_u64(x: u64) -> u16 {
+        x as u16
+    }
+
+    fn widen(self) -> u32 {
+        self as u32
+    }
+
+    fn slice(slots: &Slots) -> Option<&[u16]> {
+        match *slots {
+            Slots::Dense(ref store) => store.as_any().downcast_ref::<U16Store>().map(|s| &s.0[..]),
+            _ => None,
+        }
+    }
+
+    fn slice_mut(slots: &mut Slots) -> Option<&mut [u16]> {
+        match *slots {
+            Slots::Dense(ref mut store) => store.as_any_mut().downcast_mut::<U16Store>().map(|s| &mut s.0[..]),
+            _ => None,
+        }
+    }
+}
+
+impl FingerprintWord for u32 {
+    fn from_u64(x: u64) -> u32 {
+        x as u32
+    }
+
+    fn widen(self) -> u32 {
+        self
+    }
+
+    fn slice(slots: &Slots) -> Option<&[u32]> {
+        match *slots {
+            Slots::Dense(ref store) => store.as_any().downcast_ref::<U32Store>().map(|s| &s.0[..]),
+            _ => None,
+        }
+    }
+
+    fn slice_mut(slots: &mut Slots) -> Option<&mut [u32]> {
+        match *slots {
+            Slots::Dense(ref mut store) => store.as_any_mut().downcast_mut::<U32Store>().map(|s| &mut s.0[..]),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    use crate::tests::config;
+    use crate::{Config, Filter};
+
+    /// Hash of every slot's contents.
+    fn digest(filter: &Filter) -> u64 {
+        let mut h = DefaultHasher::new();
+        let buckets = filter.buckets.borrow();
+        for i in 0 .. buckets.len() {
+            buckets.entry(i).hash(&mut h);
+        }
+        h.finish()
+    }
+
+    #[test]
+    fn placement_matches_per_width_code() {
+        // Digests taken before insert and lookup were written generically,
+        // inserting until the first kick, which was not seeded then
+        for &(finger_bits, n, expected) in &[(8, 1158u64, 0xf595_e110_cdac_988e), (16, 1391, 0xe845_4691_6293_835d)] {
+            let filter = Filter::new(&Config { finger_bits, ..Config::default() }).unwrap();
+            for i in 0 .. n {
+                assert_eq!(filter.insert(&i), Ok(0));
+            }
+            assert_ne!(filter.insert(&n), Ok(0));
+            let filter = Filter::new(&Config { finger_bits, ..Config::default() }).unwrap();
+            for i in 0 .. n {
+                filter.insert(&i).unwrap();
+            }
+            assert_eq!(digest(&filter), expected, "{} bits", finger_bits);
+        }
+    }
+
+    #[test]
+    fn seeded_kicks_are_reproducible() {
+        // Swap and failure counts and contents after filling to 88% with a
+        // seeded kick RNG
+        for &(finger_bits, swaps, failures, expected) in &[
+            (8, 417, 359, 0x7f8a_78c6_07cf_6501),
+            (12, 966, 23, 0x0f56_e9c5_b8b0_bced),
+            (16, 833, 6, 0x834c_4a7e_5136_af77),
+            (32, 922, 19, 0x5379_39f6_4d86_a1c4),
+        ] {
+            let filter = Filter::new(&Config { finger_bits, ..config(1024) }).unwrap();
+            let (mut s, mut f) = (0u64, 0u64);
+            for i in 0 .. 3600u64 {
+                match filter.insert(&i) {
+                    Ok(n) => s += u64::from(n),
+                    Err(_) => f += 1,
+                }
+            }
+            assert_eq!((s, f, digest(&filter)), (swaps, failures, expected), "{} bits", finger_bits);
+        }
+    }
+}
@@ -0,0 +1,320 @@
+//! Runs the `cuckoo-filter` binary's subcommands end to end.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use cuckoo_filter::plan;
+use cuckoo_filter::Filter;
+
+const KEYS: &str = "tests/fixtures/keys.txt";
+
+/// Path of a scratch file unique to this process and `name`.
+fn scratch(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("cuckoo-cli-{}-{}", std::process::id(), name))
+}
+
+/// Runs the binary with `args`, feeding it `stdin`.
+fn run(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cuckoo-filter"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+/// Builds a filter of the fixture keys at `out`.
+fn build(out: &Path) -> Filter {
+    let output = run(&["build", "--capacity", "1000", "--out", out.to_str().unwrap(), KEYS], b"");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    Filter::from_bytes(&fs::read(out).unwrap()).unwrap()
+}
+
+fn fixture_keys() -> Vec<String> {
+    fs::read_to_string(KEYS).unwrap().lines().map(String::from).collect()
+}
+
+#[test]
+fn build_from_stdin_saves_filter() {
+    let out = scratch("stdin.ckf");
+    let keys = fs::read(KEYS).unwrap();
+    let output = run(&["build", "--capacity", "1000", "--fp", "0.001", "--out", out.to_str().unwrap()], &keys);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let summary = stdout(&output);
+    assert!(summary.contains("items       : 1000\n"), "{}", summary);
+    assert!(summary.contains("failures    : 0\n"), "{}", summary);
+
+    let bytes = fs::read(&out).unwrap();
+    fs::remove_file(&out).unwrap();
+    assert!(summary.contains(&format!("bytes       : {}\n", bytes.len())), "{}", summary);
+    let filter = Filter::from_bytes(&bytes).unwrap();
+    assert_eq!(filter.used(), 1000);
+    assert!(fixture_keys().iter().all(|key| filter.contains(key.as_bytes())));
+    assert!(filter.estimated_fpp() <= 0.001);
+}
+
+#[test]
+fn build_binary_keys() {
+    let (input, out) = (scratch("keys.bin"), scratch("binary.ckf"));
+    let keys: Vec<Vec<u8>> = (0 .. 500u32).map(|i| i.to_le_bytes().iter().chain(b"\n\0").cloned().collect()).collect();
+    let mut bytes = Vec::new();
+    for key in &keys {
+        bytes.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(key);
+    }
+    fs::write(&input, &bytes).unwrap();
+    let output = run(&["build", "--capacity", "500", "--binary", "--out", out.to_str().unwrap(), input.to_str().unwrap()], b"");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let filter = Filter::from_bytes(&fs::read(&out).unwrap()).unwrap();
+    fs::remove_file(&input).unwrap();
+    fs::remove_file(&out).unwrap();
+    assert_eq!(filter.used(), 500);
+    assert!(keys.iter().all(|key| filter.contains(&key[..])));
+}
+
+#[test]
+fn build_requires_capacity_and_out() {
+    let output = run(&["build", KEYS], b"");
+    assert_eq!(output.status.code(), Some(2));
+    let output = run(&["build", "--capacity", "10", KEYS], b"");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn query_prints_membership_and_exit_code() {
+    let path = scratch("query.ckf");
+    let filter = build(&path);
+    let file = path.to_str().unwrap();
+    let keys = fixture_keys();
+    let absent: Vec<String> = (0 ..).map(|i| format!("absent-{}", i)).filter(|k| !filter.contains(k.as_bytes())).take(2).collect();
+
+    let output = run(&["query", file, &keys[0], &keys[1]], b"");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), format!("{}\tpresent\n{}\tpresent\n", keys[0], keys[1]));
+
+    let output = run(&["query", file, &keys[2], &absent[0]], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), format!("{}\tpresent\n{}\tabsent\n", keys[2], absent[0]));
+
+    // Keys from stdin
+    let output = run(&["query", file], fs::read(KEYS).unwrap().as_slice());
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output).lines().filter(|line| line.ends_with("\tpresent")).count(), 1000);
+
+    let stdin = format!("{}\n{}\n{}\n", keys[3], absent[0], absent[1]);
+    let output = run(&["query", file, "--count-only"], stdin.as_bytes());
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "1\n");
+    fs::remove_file(&path).unwrap();
+
+    let output = run(&["query", file, &keys[0]], b"");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stdout(&output).is_empty());
+}
+
+#[test]
+fn stats_of_valid_file() {
+    let path = scratch("stats.ckf");
+    let filter = build(&path);
+    let output = run(&["stats", path.to_str().unwrap()], b"");
+    let file_bytes = fs::metadata(&path).unwrap().len();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let out = stdout(&output);
+    let c = filter.config();
+    for line in &[
+        format!("finger_bits      : {}", c.finger_bits),
+        format!("num_buckets      : {}", c.num_buckets),
+        format!("used             : 1000 / {}", filter.capacity()),
+        format!("load_factor      : {}", filter.load_factor()),
+        format!("file_bytes       : {}", file_bytes),
+        "checksum         : ok".to_string(),
+    ] {
+        assert!(out.lines().any(|l| l == line), "{:?} missing from\n{}", line, out);
+    }
+    assert!(!out.contains("occupancy        : buckets"));
+}
+
+#[test]
+fn stats_histogram() {
+    let path = scratch("histogram.ckf");
+    let filter = build(&path);
+    let output = run(&["stats", path.to_str().unwrap(), "--histogram"], b"");
+    fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let out = stdout(&output);
+    let rows: Vec<(u64, u64)> = out
+        .lines()
+        .skip_while(|line| *line != "occupancy        : buckets")
+        .skip(1)
+        .take_while(|line| !line.starts_with("fingerprint_skew"))
+        .map(|line| {
+            let (used, count) = line.split_at(line.find(':').unwrap());
+            (used.trim().parse().unwrap(), count[1 ..].trim().parse().unwrap())
+        })
+        .collect();
+    let entries = filter.config().num_entries as u64;
+    assert_eq!(rows.len() as u64, entries + 1);
+    assert_eq!(rows.iter().map(|&(_, count)| count).sum::<u64>(), filter.config().num_buckets as u64);
+    assert_eq!(rows.iter().map(|&(used, count)| used * count).sum::<u64>(), 1000);
+    assert!(out.contains("fingerprint_skew : chi2"));
+}
+
+#[test]
+fn stats_reports_damaged_files() {
+    let path = scratch("damaged.ckf");
+    build(&path);
+    let mut bytes = fs::read(&path).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    fs::write(&path, &bytes).unwrap();
+    let output = run(&["stats", path.to_str().unwrap(), "--histogram"], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("checksum         : MISMATCH"));
+    assert!(!stdout(&output).contains("occupancy        : buckets"));
+
+    fs::write(&path, &bytes[.. bytes.len() / 2]).unwrap();
+    let output = run(&["stats", path.to_str().unwrap()], b"");
+    fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stdout(&output).is_empty());
+    assert!(!output.stderr.is_empty());
+}
+
+#[test]
+fn stats_of_old_version() {
+    let output = run(&["stats", "tests/fixtures/v1.ckf"], b"");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let text = stdout(&output);
+    assert!(text.lines().any(|l| l.starts_with("format_version   : 1 (current")), "{}", text);
+    assert!(text.lines().any(|l| l == "checksum         : none"), "{}", text);
+    assert!(text.lines().any(|l| l == "num_buckets      : 64"), "{}", text);
+}
+
+#[test]
+fn upgrade_rewrites_old_versions() {
+    let out = scratch("upgraded.ckf");
+    let output = run(&["upgrade", "tests/fixtures/v1.ckf", out.to_str().unwrap()], b"");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let upgraded = fs::read(&out).unwrap();
+    fs::remove_file(&out).unwrap();
+    let v1 = Filter::from_bytes(&fs::read("tests/fixtures/v1.ckf").unwrap()).unwrap();
+    assert_eq!(upgraded, v1.to_bytes());
+
+    let output = run(&["upgrade", "tests/fixtures/missing.ckf", out.to_str().unwrap()], b"");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn steady_plan_reports_phases_separately() {
+    let dir = scratch("steady");
+    fs::create_dir_all(&dir).unwrap();
+    let plan = dir.join("plan.toml");
+    fs::write(&plan, "[[run]]\nworkload = \"steady\"\nnum_buckets = 256\nnum_entries = 4\n\
+        warm_load = 0.5\nops = 1000\nmix = \"mixed\"\nseed = 1\njson = \"steady.json\"\ntext = \"steady.txt\"\n").unwrap();
+    let output = run(&["run", plan.to_str().unwrap()], b"");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(dir.join("steady.json")).unwrap()).unwrap();
+    let text = fs::read_to_string(dir.join("steady.txt")).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    // Warmup fills half of the 1024 entries; the steady phase holds there
+    assert_eq!(json["warmup"]["inserts"], 512);
+    assert_eq!(json["warmup"]["load_factor"], 0.5);
+    let steady = &json["steady"];
+    assert_eq!((steady["inserts"].as_u64(), steady["lookups"].as_u64()), (Some(500), Some(500)));
+    assert_eq!(steady["mix"], "mixed");
+    assert!(steady["ops_per_sec"].as_f64().unwrap() > 0.0);
+    assert!(steady["latency_ns"]["p50"].as_u64() <= steady["latency_ns"]["max"].as_u64());
+    assert!(text.contains("warmup        : 512 inserts (untimed)\n"), "{}", text);
+    assert!(text.contains("steady        : Mixed, 500 inserts, 500 lookups\n"), "{}", text);
+}
+
+#[test]
+fn baseline_plan_compares_with_hash_sets() {
+    let dir = scratch("baseline");
+    fs::create_dir_all(&dir).unwrap();
+    let plan = dir.join("plan.toml");
+    fs::write(&plan, "[[run]]\nworkload = \"baseline\"\nkeys = 2000\nnum_buckets = 1000\nnum_entries = 4\n\
+        seed = 1\njson = \"baseline.json\"\ntext = \"baseline.txt\"\n").unwrap();
+    let output = run(&["run", plan.to_str().unwrap()], b"");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(dir.join("baseline.json")).unwrap()).unwrap();
+    let text = fs::read_to_string(dir.join("baseline.txt")).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    let rows = json["baseline"].as_array().unwrap();
+    let names: Vec<&str> = rows.iter().map(|row| row["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["cuckoo filter", "HashSet<String>", "HashSet<u64>"]);
+    for (row, name) in rows.iter().zip(&names) {
+        assert!(row["lookups_per_sec"].as_f64().unwrap() > 0.0, "{}", name);
+        assert!(row["build_secs"].as_f64().unwrap() >= 0.0, "{}", name);
+        assert!(text.lines().any(|line| line.starts_with(name)), "{}", text);
+    }
+    let (filter, strings) = (&rows[0], &rows[1]);
+    // 4000 two-byte entries against 2000 strings and their table
+    assert!(filter["memory_bytes"].as_u64().unwrap() < strings["memory_bytes"].as_u64().unwrap());
+    assert!(filter["memory_bytes"].as_u64().unwrap() > 8000);
+    assert!(filter["false_positive_rate"].as_f64().unwrap() < 0.05);
+    assert_eq!(strings["false_positive_rate"], 0.0);
+}
+
+/// `experiments.toml` with its sizes cut down, and without the delete
+/// audit unless built with the `testkit` feature.
+fn scaled_example_plan() -> String {
+    let text = fs::read_to_string("experiments.toml").unwrap();
+    let runs: Vec<&str> = text.split("[[run]]").collect();
+    let mut scaled = runs[0].to_string();
+    for run in &runs[1 ..] {
+        if run.contains("delete-audit") && !cfg!(feature = "testkit") {
+            continue;
+        }
+        scaled.push_str("[[run]]");
+        for line in run.lines().skip(1) {
+            let (key, value) = match line.split_once(" = ") {
+                Some((key, value)) => (key, value.parse::<u64>().ok()),
+                None => (line, None),
+            };
+            match (key, value) {
+                ("keys", Some(n)) | ("ops", Some(n)) => scaled.push_str(&format!("\n{} = {}", key, n / 100)),
+                ("num_buckets", Some(n)) => scaled.push_str(&format!("\n{} = {}", key, n / 10)),
+                _ => scaled.push_str(&format!("\n{}", line)),
+            }
+        }
+        scaled.push('\n');
+    }
+    scaled
+}
+
+#[test]
+fn example_plan_writes_declared_outputs() {
+    let dir = scratch("example");
+    fs::create_dir_all(&dir).unwrap();
+    let plan_path = dir.join("experiments.toml");
+    let text = scaled_example_plan();
+    fs::write(&plan_path, &text).unwrap();
+    let output = run(&["run", plan_path.to_str().unwrap()], b"");
+    let stdout = stdout(&output);
+
+    let runs = plan::parse(&text).unwrap();
+    assert!(runs.len() >= 4);
+    let outputs: Vec<PathBuf> = runs.iter().flat_map(|run| run.json.iter().chain(run.text.iter())).cloned().collect();
+    let missing: Vec<&PathBuf> = outputs.iter().filter(|out| !dir.join(out).is_file()).collect();
+    let sizes: Vec<u64> = outputs.iter().filter_map(|out| fs::metadata(dir.join(out)).ok()).map(|m| m.len()).collect();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success(), "{}{}", stdout, String::from_utf8_lossy(&output.stderr));
+    assert!(missing.is_empty(), "missing {:?}\n{}", missing, stdout);
+    assert!(sizes.iter().all(|&n| n > 0));
+    for run in &runs {
+        assert!(stdout.contains(run.name.as_ref().unwrap().as_str()), "{}", stdout);
+    }
+}
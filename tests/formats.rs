@@ -0,0 +1,37 @@
+//! Filters saved by every released format version stay loadable.
+//!
+//! Each `tests/fixtures/vN.ckf` holds the 16-bit filter of 64 buckets of 4
+//! entries that keys 0 to 99 fill, as written by version N. Version 1 had
+//! no checksum.
+
+use std::fs;
+
+use cuckoo_filter::{Filter, FORMAT_VERSION};
+
+fn fixture(version: u16) -> Vec<u8> {
+    fs::read(format!("tests/fixtures/v{}.ckf", version)).unwrap()
+}
+
+#[test]
+fn every_version_loads() {
+    let current = Filter::from_bytes(&fixture(FORMAT_VERSION)).unwrap().to_bytes();
+    for version in 1 ..= FORMAT_VERSION {
+        let bytes = fixture(version);
+        assert_eq!(u16::from_le_bytes([bytes[4], bytes[5]]), version);
+        let filter = Filter::from_bytes(&bytes).unwrap_or_else(|e| panic!("version {}: {:?}", version, e));
+        assert_eq!(filter.used(), 100);
+        assert_eq!(filter.capacity(), 256);
+        assert!((0 .. 100u64).all(|i| filter.contains(&i)), "version {}", version);
+        assert_eq!(filter.to_bytes(), current, "version {}", version);
+    }
+}
+
+#[test]
+fn damaged_fixtures_are_rejected() {
+    for version in 2 ..= FORMAT_VERSION {
+        let mut bytes = fixture(version);
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0x40;
+        assert!(Filter::from_bytes(&bytes).is_err(), "version {}", version);
+    }
+}
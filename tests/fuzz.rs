@@ -0,0 +1,142 @@
+//! Bounded, seeded runs of the checks of the `fuzz/` targets, so that
+//! `cargo test` exercises them without cargo-fuzz. Inputs are random
+//! bytes and random damage to valid encodings, and random operation
+//! sequences on small filters.
+
+use std::collections::HashMap;
+use std::fs;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use cuckoo_filter::{decode_ops, inspect_serialized, Config, Filter, FilterDelta};
+
+/// The `decode` target: no decoder panics, and a filter decoded from
+/// `data` is not much larger than `data`.
+fn decode(data: &[u8]) {
+    if let Ok(filter) = Filter::from_bytes(data) {
+        let bytes = filter.to_bytes();
+        assert!(bytes.len() <= data.len() + 32, "{:?}", data);
+        assert_eq!(Filter::from_bytes(&bytes).unwrap().to_bytes(), bytes);
+        for key in 0u8 .. 16 {
+            filter.contains(&key);
+            let _ = filter.insert(&key);
+        }
+    }
+    let _ = inspect_serialized(data);
+    let _ = decode_ops(data);
+    let _ = FilterDelta::from_bytes(data);
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = Filter::from_dump(text);
+    }
+}
+
+#[test]
+fn decoders_survive_random_and_damaged_input() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let filter = Filter::new(&Config { num_buckets: 16, seed: Some(1), ..Config::default() }).unwrap();
+    for i in 0 .. 40u64 {
+        filter.insert(&i).unwrap();
+    }
+    // Version 1 has no checksum, so damage to it reaches the decoder
+    let valid = [filter.to_bytes(), fs::read("tests/fixtures/v1.ckf").unwrap(), filter.to_dump().into_bytes()];
+    for _ in 0 .. 4000 {
+        let mut data = valid[rng.gen_range(0, valid.len())].clone();
+        match rng.gen_range(0, 4) {
+            0 => data = (0 .. rng.gen_range(0, 64)).map(|_| rng.gen()).collect(),
+            1 => data.truncate(rng.gen_range(0, data.len())),
+            _ => {
+                for _ in 0 .. rng.gen_range(1, 4) {
+                    let i = rng.gen_range(0, data.len());
+                    data[i] = rng.gen();
+                }
+            },
+        }
+        decode(&data);
+    }
+}
+
+#[derive(Debug)]
+enum Op {
+    Insert(u8),
+    Delete(u8),
+    Contains(u8),
+    Clear,
+    /// Merge a filter holding these keys
+    Merge(Vec<u8>),
+}
+
+fn random_op<R: Rng>(rng: &mut R) -> Op {
+    match rng.gen_range(0, 20) {
+        0 => Op::Clear,
+        1 => Op::Merge((0 .. rng.gen_range(0, 4)).map(|_| rng.gen()).collect()),
+        2 ..= 9 => Op::Insert(rng.gen()),
+        10 ..= 14 => Op::Delete(rng.gen()),
+        _ => Op::Contains(rng.gen()),
+    }
+}
+
+/// The `ops` target: `ops` applied to a filter built from `config` agree
+/// with an exact multiset until an insert fails.
+fn apply(config: &Config, ops: &[Op]) {
+    let filter = Filter::new(config).unwrap();
+    let mut model: HashMap<u8, u64> = HashMap::new();
+    // A failed insert drops a fingerprint, after which only a clear
+    // makes the model exact again
+    let mut exact = true;
+    for op in ops {
+        match *op {
+            Op::Insert(key) => match filter.insert(&key) {
+                Ok(_) => *model.entry(key).or_insert(0) += 1,
+                Err(_) => exact = false,
+            },
+            Op::Delete(key) => {
+                if let Some(n) = model.get_mut(&key).filter(|n| **n > 0) {
+                    *n -= 1;
+                    assert!(filter.delete(&key) || !exact);
+                }
+            },
+            Op::Contains(key) => {
+                let found = filter.contains(&key);
+                assert!(found || !exact || model.get(&key).is_none_or(|&n| n == 0));
+            },
+            Op::Clear => {
+                filter.clear();
+                model.clear();
+                exact = true;
+            },
+            Op::Merge(ref keys) => {
+                let other = Filter::new(config).unwrap();
+                if keys.iter().all(|key| other.insert(key).is_ok()) && filter.merge(&other).is_ok() {
+                    keys.iter().for_each(|&key| *model.entry(key).or_insert(0) += 1);
+                } else {
+                    exact = false;
+                }
+            },
+        }
+        if exact {
+            assert_eq!(filter.used(), model.values().sum::<u64>(), "{:?} {:?}", config, ops);
+            assert!(model.iter().all(|(key, &n)| n == 0 || filter.contains(key)), "{:?} {:?}", config, ops);
+        }
+        assert!(filter.load_factor() <= 1.0);
+    }
+}
+
+#[test]
+fn op_sequences_agree_with_model() {
+    let mut rng = StdRng::seed_from_u64(2);
+    for _ in 0 .. 3000 {
+        let config = Config {
+            finger_bits: if rng.gen() { 16 } else { 8 },
+            num_buckets: rng.gen_range(1, 9),
+            num_entries: rng.gen_range(1, 5),
+            hash_count: rng.gen_range(1, 3),
+            num_hashes: rng.gen_range(2, 5),
+            occupancy_bitmap: rng.gen(),
+            seed: Some(0),
+            ..Config::default()
+        };
+        let ops: Vec<Op> = (0 .. rng.gen_range(0, 64)).map(|_| random_op(&mut rng)).collect();
+        apply(&config, &ops);
+    }
+}